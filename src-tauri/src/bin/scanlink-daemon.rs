@@ -0,0 +1,107 @@
+//! Headless ScanLink daemon.
+//!
+//! Runs the same WebSocket pairing/scan server as the desktop app without
+//! creating a window or webview, for kiosks and thin clients.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+use app_lib::daemon::{self, DaemonArgs};
+use log::{LevelFilter, Log, Metadata, Record};
+
+/// Minimal logger writing to stdout and optionally to a file
+struct DaemonLogger {
+    level: LevelFilter,
+    file: Option<Mutex<File>>,
+}
+
+impl Log for DaemonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = format!(
+            "{} [{}] {}: {}",
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+
+        println!("{}", line);
+        if let Some(file) = &self.file {
+            if let Ok(mut file) = file.lock() {
+                let _ = writeln!(file, "{}", line);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stdout().flush();
+        if let Some(file) = &self.file {
+            if let Ok(mut file) = file.lock() {
+                let _ = file.flush();
+            }
+        }
+    }
+}
+
+fn init_logger(args: &DaemonArgs) -> Result<(), String> {
+    let file = match &args.log_file {
+        Some(path) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| format!("Failed to open log file {:?}: {}", path, e))?;
+            Some(Mutex::new(file))
+        }
+        None => None,
+    };
+
+    let level = if args.verbose { LevelFilter::Debug } else { LevelFilter::Info };
+    // The logger lives for the whole process
+    let logger: &'static DaemonLogger = Box::leak(Box::new(DaemonLogger { level, file }));
+    log::set_logger(logger)
+        .map_err(|e| format!("Failed to initialize logger: {}", e))?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    let args = match daemon::parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, daemon::USAGE);
+            std::process::exit(2);
+        }
+    };
+    if args.help {
+        println!("{}", daemon::USAGE);
+        return;
+    }
+
+    if let Err(e) = init_logger(&args) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+
+    let provisioning = args.is_provisioning();
+    let result = daemon::execute(args).await;
+    match &result {
+        Ok(()) if !provisioning => log::info!("ScanLink daemon stopped"),
+        Ok(()) => {}
+        Err(e) if provisioning => eprintln!("{}", e),
+        Err(e) => log::error!("{}", e),
+    }
+    log::logger().flush();
+    if result.is_err() {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,350 @@
+use arboard::{Clipboard, ImageData};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+use crate::keyboard;
+
+/// Time the target application gets to read a pasted value before the
+/// previous clipboard content is put back
+const RESTORE_DELAY: Duration = Duration::from_millis(150);
+
+/// Targets X11 owners advertise that are not actual content
+const X11_META_TARGETS: [&str; 5] = ["TARGETS", "TIMESTAMP", "MULTIPLE", "SAVE_TARGETS", "DELETE"];
+
+/// Text targets in order of preference
+const TEXT_TARGETS: [&str; 4] = ["text/plain;charset=utf-8", "UTF8_STRING", "text/plain", "STRING"];
+
+/// Clipboard content captured before pasting a scan.
+///
+/// Owners often offer several formats (a spreadsheet cell as HTML, text and an
+/// image); only the one picked by [`preferred_target`] is kept, because wl-copy
+/// and xclip can serve just one type per selection.
+#[derive(Debug, Clone, PartialEq)]
+enum ClipboardSnapshot {
+    Empty,
+    /// Raw content of one MIME target, read with wl-paste or xclip
+    Mime { mime: String, data: Vec<u8> },
+    Text(String),
+    Image { width: usize, height: usize, bytes: Vec<u8> },
+}
+
+impl ClipboardSnapshot {
+    /// Short description for logs, without the content itself
+    fn describe(&self) -> String {
+        match self {
+            ClipboardSnapshot::Empty => "empty".to_string(),
+            ClipboardSnapshot::Mime { mime, data } => format!("{}, {} bytes", mime, data.len()),
+            ClipboardSnapshot::Text(text) => format!("text, {} bytes", text.len()),
+            ClipboardSnapshot::Image { width, height, .. } => format!("image {}x{}", width, height),
+        }
+    }
+}
+
+/// Picks the target that best preserves the content: images first (they usually
+/// also offer a lossy text form), then UTF-8 text, then any other MIME type
+fn preferred_target(targets: &[String]) -> Option<&str> {
+    let content: Vec<&str> = targets
+        .iter()
+        .map(|t| t.trim())
+        .filter(|t| !t.is_empty() && !X11_META_TARGETS.contains(t))
+        .collect();
+
+    content.iter().find(|t| t.starts_with("image/"))
+        .or_else(|| TEXT_TARGETS.iter().find_map(|text| content.iter().find(|t| *t == text)))
+        .or_else(|| content.iter().find(|t| t.contains('/')))
+        .or_else(|| content.first())
+        .copied()
+}
+
+/// Runs a clipboard tool and returns its stdout, or None when it fails (e.g. empty clipboard)
+fn read_command(program: &str, args: &[&str]) -> Option<Vec<u8>> {
+    let output = Command::new(program).args(args).stderr(Stdio::null()).output().ok()?;
+    if output.status.success() {
+        Some(output.stdout)
+    } else {
+        None
+    }
+}
+
+/// Runs a clipboard tool with `data` on stdin
+fn write_command(program: &str, args: &[&str], data: &[u8]) -> Result<(), String> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("Failed to execute {}: {}", program, e))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(data).map_err(|e| format!("Failed to write to {}: {}", program, e))?;
+    }
+
+    // Both tools fork a background process that keeps serving the content
+    let status = child.wait().map_err(|e| format!("Failed to wait for {}: {}", program, e))?;
+    if !status.success() {
+        return Err(format!("{} exited with {}", program, status));
+    }
+    Ok(())
+}
+
+/// Snapshot from a tool's list of targets (one per line) and a reader for one target
+fn snapshot_targets(list: Option<Vec<u8>>, read: impl FnOnce(&str) -> Option<Vec<u8>>) -> ClipboardSnapshot {
+    let Some(list) = list else {
+        return ClipboardSnapshot::Empty;
+    };
+    let targets: Vec<String> = String::from_utf8_lossy(&list).lines().map(String::from).collect();
+
+    match preferred_target(&targets) {
+        Some(mime) => match read(mime) {
+            Some(data) => ClipboardSnapshot::Mime { mime: mime.to_string(), data },
+            None => ClipboardSnapshot::Empty,
+        },
+        None => ClipboardSnapshot::Empty,
+    }
+}
+
+fn snapshot_wl() -> ClipboardSnapshot {
+    snapshot_targets(read_command("wl-paste", &["--list-types"]), |mime| {
+        read_command("wl-paste", &["--no-newline", "--type", mime])
+    })
+}
+
+fn snapshot_xclip() -> ClipboardSnapshot {
+    snapshot_targets(read_command("xclip", &["-selection", "clipboard", "-t", "TARGETS", "-o"]), |mime| {
+        read_command("xclip", &["-selection", "clipboard", "-t", mime, "-o"])
+    })
+}
+
+/// How a snapshot is put back
+#[derive(Debug, PartialEq)]
+enum Restore<'a> {
+    /// Run a clipboard tool with `data` on stdin
+    Command { program: &'static str, args: Vec<&'a str>, data: &'a [u8] },
+    /// Write it with arboard
+    Arboard,
+}
+
+/// Raw MIME content goes back through the tool that read it: wl-copy on Wayland,
+/// xclip otherwise. Text and images come from arboard and go back through it.
+fn restore_with(snapshot: &ClipboardSnapshot, use_wl: bool) -> Restore<'_> {
+    match snapshot {
+        ClipboardSnapshot::Empty if use_wl => Restore::Command { program: "wl-copy", args: vec!["--clear"], data: &[] },
+        ClipboardSnapshot::Mime { mime, data } if use_wl => {
+            Restore::Command { program: "wl-copy", args: vec!["--type", mime], data }
+        }
+        ClipboardSnapshot::Mime { mime, data } => {
+            Restore::Command { program: "xclip", args: vec!["-selection", "clipboard", "-t", mime, "-i"], data }
+        }
+        ClipboardSnapshot::Empty | ClipboardSnapshot::Text(_) | ClipboardSnapshot::Image { .. } => Restore::Arboard,
+    }
+}
+
+/// arboard only knows text and images, used when no clipboard tool is installed
+fn snapshot_arboard(clipboard: &mut Clipboard) -> ClipboardSnapshot {
+    if let Ok(text) = clipboard.get_text() {
+        return ClipboardSnapshot::Text(text);
+    }
+    match clipboard.get_image() {
+        Ok(image) => ClipboardSnapshot::Image {
+            width: image.width,
+            height: image.height,
+            bytes: image.bytes.into_owned(),
+        },
+        Err(_) => ClipboardSnapshot::Empty,
+    }
+}
+
+/// Clipboard used by the paste-based backends. When preservation is enabled, the
+/// user's content (text, image or any other MIME type) is captured before the
+/// first paste and put back by [`PasteClipboard::restore`].
+pub struct PasteClipboard {
+    preserve: bool,
+    /// Use wl-copy/wl-paste instead of arboard (Wayland)
+    use_wl: bool,
+    /// Snapshot other MIME types with xclip (X11)
+    use_xclip: bool,
+    /// Kept alive for the whole sequence, as X11 serves content from its owner
+    clipboard: Option<Clipboard>,
+    saved: Option<ClipboardSnapshot>,
+}
+
+impl PasteClipboard {
+    pub fn new(preserve: bool) -> Self {
+        let env = keyboard::environment();
+        Self {
+            preserve,
+            use_wl: env.wayland && env.wl_clipboard,
+            use_xclip: !env.wayland && env.xclip,
+            clipboard: None,
+            saved: None,
+        }
+    }
+
+    fn arboard(&mut self) -> Result<&mut Clipboard, String> {
+        if self.clipboard.is_none() {
+            self.clipboard = Some(Clipboard::new().map_err(|e| format!("Failed to access clipboard: {}", e))?);
+        }
+        self.clipboard.as_mut().ok_or_else(|| "Clipboard not available".to_string())
+    }
+
+    fn take_snapshot(&mut self) -> ClipboardSnapshot {
+        if self.use_wl {
+            snapshot_wl()
+        } else if self.use_xclip {
+            snapshot_xclip()
+        } else {
+            match self.arboard() {
+                Ok(clipboard) => snapshot_arboard(clipboard),
+                Err(e) => {
+                    log::warn!("Cannot save clipboard: {}", e);
+                    ClipboardSnapshot::Empty
+                }
+            }
+        }
+    }
+
+    /// Puts text on the clipboard so it can be pasted with Ctrl+V
+    pub fn set_text(&mut self, text: &str) -> Result<(), String> {
+        if self.preserve && self.saved.is_none() {
+            let snapshot = self.take_snapshot();
+            log::debug!("Saved clipboard content: {}", snapshot.describe());
+            self.saved = Some(snapshot);
+        }
+
+        if self.use_wl {
+            log::debug!("Using wl-copy for clipboard");
+            let copied = Command::new("wl-copy")
+                .args(["--", text])
+                .output()
+                .map(|o| o.status.success())
+                .unwrap_or(false);
+            if !copied {
+                return Err("Failed to set clipboard content".to_string());
+            }
+            return Ok(());
+        }
+
+        self.arboard()?
+            .set_text(text)
+            .map_err(|e| format!("Failed to set clipboard: {}", e))
+    }
+
+    /// Puts back the content saved before the first paste, if any
+    pub fn restore(mut self) {
+        let Some(snapshot) = self.saved.take() else {
+            return;
+        };
+
+        // Let the target application read the pasted value first
+        thread::sleep(RESTORE_DELAY);
+
+        if let Err(e) = self.write_snapshot(&snapshot) {
+            log::warn!("Failed to restore clipboard ({}): {}", snapshot.describe(), e);
+        } else {
+            log::debug!("Restored clipboard content: {}", snapshot.describe());
+        }
+    }
+
+    fn write_snapshot(&mut self, snapshot: &ClipboardSnapshot) -> Result<(), String> {
+        if let Restore::Command { program, args, data } = restore_with(snapshot, self.use_wl) {
+            if program == "xclip" {
+                // Hand ownership to xclip, which keeps serving after we exit
+                self.clipboard = None;
+            }
+            return write_command(program, &args, data);
+        }
+
+        match snapshot {
+            ClipboardSnapshot::Text(text) => self.arboard()?.set_text(text.as_str()).map_err(|e| e.to_string()),
+            ClipboardSnapshot::Image { width, height, bytes } => self.arboard()?
+                .set_image(ImageData { width: *width, height: *height, bytes: bytes.as_slice().into() })
+                .map_err(|e| e.to_string()),
+            ClipboardSnapshot::Empty | ClipboardSnapshot::Mime { .. } => self.arboard()?.clear().map_err(|e| e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn targets(list: &[&str]) -> Vec<String> {
+        list.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn test_preferred_target() {
+        // Images win over their text representation
+        let image = targets(&["TARGETS", "text/uri-list", "UTF8_STRING", "image/png"]);
+        assert_eq!(preferred_target(&image), Some("image/png"));
+
+        let text = targets(&["TIMESTAMP", "TARGETS", "STRING", "UTF8_STRING", "text/html"]);
+        assert_eq!(preferred_target(&text), Some("UTF8_STRING"));
+
+        let binary = targets(&["SAVE_TARGETS", "application/x-office-spreadsheet"]);
+        assert_eq!(preferred_target(&binary), Some("application/x-office-spreadsheet"));
+
+        assert_eq!(preferred_target(&targets(&["TARGETS", ""])), None);
+    }
+
+    #[test]
+    fn test_snapshot_keeps_the_preferred_target() {
+        let list = Some(b"TARGETS\ntext/html\nUTF8_STRING\nimage/png\n".to_vec());
+        let mut requested = None;
+        let snapshot = snapshot_targets(list, |mime| {
+            requested = Some(mime.to_string());
+            Some(vec![0x89, b'P', b'N', b'G'])
+        });
+        assert_eq!(requested.as_deref(), Some("image/png"));
+        assert_eq!(snapshot, ClipboardSnapshot::Mime { mime: "image/png".into(), data: vec![0x89, b'P', b'N', b'G'] });
+        assert_eq!(snapshot.describe(), "image/png, 4 bytes");
+    }
+
+    #[test]
+    fn test_snapshot_of_an_empty_clipboard() {
+        // The tool fails when nothing owns the clipboard
+        assert_eq!(snapshot_targets(None, |_| panic!("nothing to read")), ClipboardSnapshot::Empty);
+        // Only meta targets
+        assert_eq!(snapshot_targets(Some(b"TARGETS\nTIMESTAMP\n".to_vec()), |_| panic!("nothing to read")), ClipboardSnapshot::Empty);
+        // The owner went away between listing and reading
+        assert_eq!(snapshot_targets(Some(b"UTF8_STRING\n".to_vec()), |_| None), ClipboardSnapshot::Empty);
+    }
+
+    #[test]
+    fn test_snapshot_keeps_binary_content_byte_for_byte() {
+        let data = vec![0, 159, 146, 150, 255, b'\n'];
+        let snapshot = snapshot_targets(Some(b"application/x-office-spreadsheet\n".to_vec()), |_| Some(data.clone()));
+        let Restore::Command { data: restored, .. } = restore_with(&snapshot, true) else {
+            panic!("MIME content is restored with a command");
+        };
+        assert_eq!(restored, data.as_slice());
+    }
+
+    #[test]
+    fn test_restore_command_selection() {
+        let mime = ClipboardSnapshot::Mime { mime: "text/html".into(), data: b"<b>x</b>".to_vec() };
+        assert_eq!(
+            restore_with(&mime, true),
+            Restore::Command { program: "wl-copy", args: vec!["--type", "text/html"], data: b"<b>x</b>" }
+        );
+        assert_eq!(
+            restore_with(&mime, false),
+            Restore::Command { program: "xclip", args: vec!["-selection", "clipboard", "-t", "text/html", "-i"], data: b"<b>x</b>" }
+        );
+
+        assert_eq!(
+            restore_with(&ClipboardSnapshot::Empty, true),
+            Restore::Command { program: "wl-copy", args: vec!["--clear"], data: &[] }
+        );
+        assert_eq!(restore_with(&ClipboardSnapshot::Empty, false), Restore::Arboard);
+
+        let text = ClipboardSnapshot::Text("previous".into());
+        let image = ClipboardSnapshot::Image { width: 1, height: 1, bytes: vec![0; 4] };
+        for use_wl in [true, false] {
+            assert_eq!(restore_with(&text, use_wl), Restore::Arboard);
+            assert_eq!(restore_with(&image, use_wl), Restore::Arboard);
+        }
+    }
+}
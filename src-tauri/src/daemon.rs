@@ -0,0 +1,393 @@
+//! Headless server behind the `scanlink-daemon` binary.
+//!
+//! This is the only part of the library the binary uses: it parses the
+//! command line and runs either the server or a provisioning task, sharing
+//! the scan pipeline with the desktop app.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use crate::models::{BarcodeMessage, ConnectionInfo, TokenValidity};
+use crate::qr_service::{advertised_ip, render_qr_terminal};
+use crate::websocket::{ServerEvent, WebSocketServer};
+use crate::provisioning::{self, Bundle};
+use crate::storage::AppConfig;
+use crate::{output, pipeline, policy, storage, vault};
+
+pub const USAGE: &str = "Usage: scanlink-daemon [--log-file <path>] [--passphrase-file <path>] [--regenerate-token] [--verbose]
+       scanlink-daemon --generate-signing-key <path>
+       scanlink-daemon --export-bundle <path> --signing-key <path> [--include-devices]
+       scanlink-daemon --import-bundle <path> [--trusted-key <public key>]
+
+Options:
+  --log-file <path>         Also append log output to the given file
+  --passphrase-file <path>  Encrypt config secrets with the passphrase in this file
+                            instead of the keyring (or set SCANLINK_CONFIG_PASSPHRASE)
+  --regenerate-token        Replace the pairing token, invalidating printed QR codes
+  -v, --verbose             Log debug messages
+  -h, --help                Show this help
+
+Provisioning (runs instead of the server):
+  --generate-signing-key <path>  Write a new bundle signing key and print its public key
+  --export-bundle <path>         Write a signed bundle of this machine's settings
+  --signing-key <path>           Key used to sign the exported bundle
+  --include-devices              Also export paired devices with their keys (keep the bundle secret)
+  --import-bundle <path>         Apply a signed bundle to this machine's config
+  --trusted-key <public key>     Accept bundles from this key in addition to the policy's
+                                 trusted_bundle_keys";
+
+/// Parsed command line
+#[derive(Debug, Default, PartialEq)]
+pub struct DaemonArgs {
+    pub log_file: Option<PathBuf>,
+    pub verbose: bool,
+    /// Print the usage and exit
+    pub help: bool,
+    passphrase_file: Option<PathBuf>,
+    regenerate_token: bool,
+    provisioning: Option<Provisioning>,
+}
+
+impl DaemonArgs {
+    /// True when a provisioning task runs instead of the server
+    pub fn is_provisioning(&self) -> bool {
+        self.provisioning.is_some()
+    }
+}
+
+/// One-shot provisioning actions
+#[derive(Debug, PartialEq)]
+enum Provisioning {
+    GenerateKey(PathBuf),
+    Export { path: PathBuf, signing_key: PathBuf, include_devices: bool },
+    Import { path: PathBuf, trusted_key: Option<String> },
+}
+
+fn value(iter: &mut impl Iterator<Item = String>, flag: &str) -> Result<String, String> {
+    iter.next().ok_or_else(|| format!("{} requires a value", flag))
+}
+
+/// Parses the arguments that follow the program name
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<DaemonArgs, String> {
+    let mut parsed = DaemonArgs::default();
+    let mut signing_key = None;
+    let mut include_devices = false;
+    let mut trusted_key = None;
+
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--log-file" => parsed.log_file = Some(PathBuf::from(value(&mut iter, &arg)?)),
+            "--passphrase-file" => parsed.passphrase_file = Some(PathBuf::from(value(&mut iter, &arg)?)),
+            "--generate-signing-key" => {
+                parsed.provisioning = Some(Provisioning::GenerateKey(PathBuf::from(value(&mut iter, &arg)?)));
+            }
+            "--export-bundle" => {
+                parsed.provisioning = Some(Provisioning::Export {
+                    path: PathBuf::from(value(&mut iter, &arg)?),
+                    signing_key: PathBuf::new(),
+                    include_devices: false,
+                });
+            }
+            "--import-bundle" => {
+                parsed.provisioning = Some(Provisioning::Import {
+                    path: PathBuf::from(value(&mut iter, &arg)?),
+                    trusted_key: None,
+                });
+            }
+            "--signing-key" => signing_key = Some(PathBuf::from(value(&mut iter, &arg)?)),
+            "--include-devices" => include_devices = true,
+            "--trusted-key" => trusted_key = Some(value(&mut iter, &arg)?),
+            "--regenerate-token" => parsed.regenerate_token = true,
+            "-v" | "--verbose" => parsed.verbose = true,
+            "-h" | "--help" => parsed.help = true,
+            other => return Err(format!("Unknown argument: {}", other)),
+        }
+    }
+
+    match parsed.provisioning.as_mut() {
+        Some(Provisioning::Export { signing_key: key, include_devices: devices, .. }) => {
+            *key = signing_key.ok_or("--export-bundle requires --signing-key")?;
+            *devices = include_devices;
+        }
+        Some(Provisioning::Import { trusted_key: key, .. }) => *key = trusted_key,
+        _ => {}
+    }
+
+    Ok(parsed)
+}
+
+/// Runs the provisioning task if one was given, otherwise the server until
+/// SIGTERM or Ctrl+C
+pub async fn execute(mut args: DaemonArgs) -> Result<(), String> {
+    if let Some(path) = &args.passphrase_file {
+        let passphrase = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read passphrase file {:?}: {}", path, e))?;
+        vault::set_passphrase(passphrase.trim_end_matches(['\r', '\n']).to_string());
+    }
+
+    match args.provisioning.take() {
+        Some(task) => provision(task),
+        None => run(args).await,
+    }
+}
+
+/// Resolves when the process receives SIGTERM or Ctrl+C
+async fn wait_for_shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = sigterm.recv() => log::info!("Received SIGTERM"),
+                    _ = tokio::signal::ctrl_c() => log::info!("Received Ctrl+C"),
+                }
+            }
+            Err(e) => {
+                log::warn!("Failed to install SIGTERM handler: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+                log::info!("Received Ctrl+C");
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        log::info!("Received Ctrl+C");
+    }
+}
+
+/// Loads the config for provisioning, refusing to work on defaults after corruption
+fn load_config_for_provisioning() -> Result<AppConfig, String> {
+    match storage::load_with_recovery() {
+        (_, Some(recovery)) => Err(format!("{}, fix or restore the config first", recovery.error)),
+        (config, None) => Ok(config),
+    }
+}
+
+fn provision(task: Provisioning) -> Result<(), String> {
+    match task {
+        Provisioning::GenerateKey(path) => {
+            let (signing_key, public_key) = provisioning::generate_signing_key()?;
+            let mut options = OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            options
+                .open(&path)
+                .and_then(|mut file| file.write_all(signing_key.as_bytes()))
+                .map_err(|e| format!("Failed to write signing key {:?}: {}", path, e))?;
+            println!("Signing key written to {:?}", path);
+            println!("Add this public key to trusted_bundle_keys in the policy of each machine:\n{}", public_key);
+        }
+        Provisioning::Export { path, signing_key, include_devices } => {
+            let config = load_config_for_provisioning()?;
+            let signing_key = std::fs::read_to_string(&signing_key)
+                .map_err(|e| format!("Failed to read signing key {:?}: {}", signing_key, e))?;
+            let bundle = provisioning::export(&config, &signing_key, include_devices)?;
+            let content = serde_json::to_string_pretty(&bundle).map_err(|e| e.to_string())?;
+            std::fs::write(&path, content).map_err(|e| format!("Failed to write bundle {:?}: {}", path, e))?;
+            println!("Bundle written to {:?}", path);
+        }
+        Provisioning::Import { path, trusted_key } => {
+            let config = load_config_for_provisioning()?;
+            let content = std::fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read bundle {:?}: {}", path, e))?;
+            let bundle: Bundle = serde_json::from_str(&content).map_err(|e| format!("Invalid bundle: {}", e))?;
+            let (config, result) = provisioning::import(&bundle, &config, policy::current(), trusted_key.as_deref())?;
+            storage::save(&config)?;
+            println!(
+                "Applied {} settings and {} devices",
+                result.applied_settings.len(), result.devices_added
+            );
+            if !result.locked_settings.is_empty() {
+                println!("Kept settings locked by policy: {}", result.locked_settings.join(", "));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Prints the pairing QR code for the phone to scan, and how long it stays valid
+fn print_pairing_qr(connection_info: &ConnectionInfo, validity: &TokenValidity) -> Result<(), String> {
+    println!("{}", render_qr_terminal(connection_info)?);
+    println!(
+        "Scan the QR code above with the ScanLink app to pair ({}:{})",
+        connection_info.ip, connection_info.port
+    );
+
+    let expires_at = validity
+        .expires_at
+        .and_then(|timestamp| chrono::DateTime::from_timestamp(timestamp, 0))
+        .map(|time| time.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string());
+    match (expires_at, validity.remaining_pairings) {
+        (Some(expires_at), _) => println!("The QR code pairs new devices until {}", expires_at),
+        (None, Some(1)) => println!("The QR code pairs one more device"),
+        (None, Some(remaining)) => println!("The QR code pairs {} more devices", remaining),
+        (None, None) => println!("The QR code stays valid until the token is regenerated"),
+    }
+    Ok(())
+}
+
+async fn run(args: DaemonArgs) -> Result<(), String> {
+    let (mut config, recovery) = storage::load_with_recovery();
+    // Nobody to ask when running headless: go back to the last good config
+    if let Some(recovery) = recovery {
+        if recovery.key_unavailable {
+            return Err(format!(
+                "{}, pass --passphrase-file, set {} or unlock the keyring",
+                recovery.error,
+                vault::PASSPHRASE_ENV
+            ));
+        }
+        if recovery.backup_available {
+            log::warn!("{}, restoring the last good backup", recovery.error);
+            config = storage::restore_backup()?;
+        }
+    }
+    output::prepare_sinks(&config.output_sinks);
+    let now = chrono::Utc::now().timestamp();
+    let mut changed = config.ensure_master_token(now);
+    if args.regenerate_token {
+        config.rotate_master_token(now);
+        changed = true;
+    }
+    changed |= config.ensure_tls_identity()?;
+    if changed {
+        storage::save(&config)?;
+    }
+    let cert_fingerprint = config.active_tls_identity().map(|identity| identity.fingerprint.clone());
+    let token = config.master_token.clone().unwrap_or_default();
+
+    let config = Arc::new(Mutex::new(config));
+    let ws_server = WebSocketServer::new(config.clone());
+
+    // Bind first so the QR code carries the port actually in use
+    let (barcode_tx, mut barcode_rx) = mpsc::unbounded_channel::<BarcodeMessage>();
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel::<ServerEvent>();
+    let bound = ws_server.bind(barcode_tx, event_tx)?;
+    let port = bound.addr.port();
+    let ip = advertised_ip(bound.addr)?;
+
+    let mut connection_info = ConnectionInfo::new(ip.clone(), port, token, cert_fingerprint);
+    let validity = config.lock().unwrap().master_token_validity();
+    print_pairing_qr(&connection_info, &validity)?;
+
+    // Reprint the QR code whenever the token rotates; nothing sends commands
+    // in headless mode, but log whatever else the server reports
+    let events_config = config.clone();
+    tokio::spawn(async move {
+        while let Some(event) = event_rx.recv().await {
+            match event {
+                ServerEvent::MasterTokenRotated => {
+                    let validity = {
+                        let config = events_config.lock().unwrap();
+                        connection_info.token = config.master_token.clone().unwrap_or_default();
+                        config.master_token_validity()
+                    };
+                    println!("The pairing token was rotated, earlier QR codes no longer pair new devices");
+                    if let Err(e) = print_pairing_qr(&connection_info, &validity) {
+                        log::error!("Failed to print the new QR code: {}", e);
+                    }
+                }
+                event => log::debug!("Server event: {:?}", event),
+            }
+        }
+    });
+
+    // Deliver scans exactly like the desktop app does
+    let barcode_task = tokio::spawn(async move {
+        while let Some(barcode_msg) = barcode_rx.recv().await {
+            let record = pipeline::process_barcode(&barcode_msg, &config).await;
+            log::debug!("Scan recorded in history as {}", record.id);
+        }
+    });
+
+    let mut server_task = tokio::spawn(bound.run());
+
+    log::info!("ScanLink daemon listening on {}:{} (log file: {:?})", ip, port, args.log_file);
+
+    let server_result = tokio::select! {
+        _ = wait_for_shutdown_signal() => {
+            log::info!("Shutting down...");
+            ws_server.shutdown();
+            match tokio::time::timeout(tokio::time::Duration::from_secs(5), &mut server_task).await {
+                Ok(result) => result,
+                Err(_) => {
+                    log::warn!("WebSocket server did not stop in time, aborting");
+                    server_task.abort();
+                    Ok(())
+                }
+            }
+        }
+        result = &mut server_task => result,
+    };
+
+    // Let scans that were already received finish typing before exiting
+    if tokio::time::timeout(tokio::time::Duration::from_secs(5), barcode_task).await.is_err() {
+        log::warn!("Timed out waiting for pending scans to be delivered");
+    }
+
+    server_result.map_err(|e| format!("WebSocket server task failed: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<DaemonArgs, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_server_args() {
+        assert_eq!(parse(&[]).unwrap(), DaemonArgs::default());
+
+        let args = parse(&["--log-file", "/var/log/scanlink.log", "-v", "--regenerate-token", "--passphrase-file", "/etc/scanlink/pass"]).unwrap();
+        assert_eq!(args.log_file, Some(PathBuf::from("/var/log/scanlink.log")));
+        assert_eq!(args.passphrase_file, Some(PathBuf::from("/etc/scanlink/pass")));
+        assert!(args.verbose && args.regenerate_token && !args.help);
+        assert!(!args.is_provisioning());
+
+        assert!(parse(&["--help"]).unwrap().help);
+        assert!(parse(&["-h"]).unwrap().help);
+    }
+
+    #[test]
+    fn test_parse_provisioning_args() {
+        assert_eq!(
+            parse(&["--generate-signing-key", "key"]).unwrap().provisioning,
+            Some(Provisioning::GenerateKey(PathBuf::from("key")))
+        );
+
+        // Options of a task may come before it
+        let export = parse(&["--include-devices", "--export-bundle", "bundle.json", "--signing-key", "key"]).unwrap();
+        assert_eq!(
+            export.provisioning,
+            Some(Provisioning::Export { path: "bundle.json".into(), signing_key: "key".into(), include_devices: true })
+        );
+        assert!(export.is_provisioning());
+
+        assert_eq!(
+            parse(&["--import-bundle", "bundle.json", "--trusted-key", "abc"]).unwrap().provisioning,
+            Some(Provisioning::Import { path: "bundle.json".into(), trusted_key: Some("abc".into()) })
+        );
+        assert_eq!(
+            parse(&["--import-bundle", "bundle.json"]).unwrap().provisioning,
+            Some(Provisioning::Import { path: "bundle.json".into(), trusted_key: None })
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(parse(&["--port", "1"]).unwrap_err(), "Unknown argument: --port");
+        assert_eq!(parse(&["--log-file"]).unwrap_err(), "--log-file requires a value");
+        assert_eq!(parse(&["--passphrase-file"]).unwrap_err(), "--passphrase-file requires a value");
+        assert_eq!(parse(&["--trusted-key"]).unwrap_err(), "--trusted-key requires a value");
+        assert_eq!(parse(&["--export-bundle", "bundle.json"]).unwrap_err(), "--export-bundle requires --signing-key");
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::{HashSet, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use crate::storage;
+
+const RECENT_SCANS_FILE: &str = "recent_scans.jsonl";
+/// How many scan ids are remembered across all devices
+const MAX_ENTRIES: usize = 10_000;
+/// How long a scan id is remembered; retries after this are treated as new scans
+const RETENTION_SECS: i64 = 24 * 60 * 60;
+
+/// One remembered id: when it was delivered, the device and its scan id
+type Entry = (i64, String, String);
+
+/// Recently delivered client scan ids, so retried uploads are never typed twice.
+///
+/// Ids are scoped per device, since each phone generates its own. The oldest
+/// entries are forgotten first once the retention time or capacity is reached.
+/// When backed by a file, every id is appended to it so a retry that arrives
+/// after a restart is still recognized.
+#[derive(Default)]
+pub struct RecentScanIds {
+    seen: HashSet<(String, String)>,
+    order: VecDeque<Entry>,
+    path: Option<PathBuf>,
+    /// Lines in the file, compacted once expired ones make up most of it
+    file_lines: usize,
+}
+
+impl RecentScanIds {
+    /// In memory only
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remembers ids in the app data directory, falling back to memory if it is unavailable
+    pub fn persistent() -> Self {
+        match storage::get_data_file_path(RECENT_SCANS_FILE) {
+            Ok(path) => Self::load(path),
+            Err(e) => {
+                log::warn!("Recent scan ids are kept in memory only: {}", e);
+                Self::new()
+            }
+        }
+    }
+
+    /// Loads the ids remembered at `path` and appends new ones to it
+    pub fn load(path: PathBuf) -> Self {
+        Self::load_at(path, chrono::Utc::now().timestamp())
+    }
+
+    fn load_at(path: PathBuf, now: i64) -> Self {
+        let mut recent = Self::new();
+        if let Ok(file) = File::open(&path) {
+            for line in BufReader::new(file).lines().map_while(Result::ok) {
+                // Lines cut short by a crash are skipped
+                if let Ok((seen_at, device, scan)) = serde_json::from_str::<Entry>(&line) {
+                    recent.remember(seen_at, device, scan);
+                }
+            }
+            recent.expire(now);
+        }
+        recent.path = Some(path);
+        recent.compact();
+        recent
+    }
+
+    /// True if the id was already delivered
+    pub fn contains(&mut self, device_id: &str, scan_id: &str) -> bool {
+        self.contains_at(device_id, scan_id, chrono::Utc::now().timestamp())
+    }
+
+    fn contains_at(&mut self, device_id: &str, scan_id: &str, now: i64) -> bool {
+        self.expire(now);
+        self.seen.contains(&(device_id.to_string(), scan_id.to_string()))
+    }
+
+    /// Records a delivered id and returns true if it was not seen before
+    pub fn insert(&mut self, device_id: &str, scan_id: &str) -> bool {
+        self.insert_at(device_id, scan_id, chrono::Utc::now().timestamp())
+    }
+
+    fn insert_at(&mut self, device_id: &str, scan_id: &str, now: i64) -> bool {
+        if self.contains_at(device_id, scan_id, now) {
+            return false;
+        }
+
+        self.remember(now, device_id.to_string(), scan_id.to_string());
+        self.append(&(now, device_id.to_string(), scan_id.to_string()));
+        true
+    }
+
+    fn remember(&mut self, seen_at: i64, device_id: String, scan_id: String) {
+        let key = (device_id, scan_id);
+        if !self.seen.insert(key.clone()) {
+            return;
+        }
+        if self.order.len() >= MAX_ENTRIES {
+            if let Some((_, device, scan)) = self.order.pop_front() {
+                self.seen.remove(&(device, scan));
+            }
+        }
+        self.order.push_back((seen_at, key.0, key.1));
+    }
+
+    fn expire(&mut self, now: i64) {
+        while let Some((seen_at, _, _)) = self.order.front() {
+            if now - seen_at < RETENTION_SECS {
+                break;
+            }
+            if let Some((_, device, scan)) = self.order.pop_front() {
+                self.seen.remove(&(device, scan));
+            }
+        }
+    }
+
+    fn append(&mut self, entry: &Entry) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        if self.file_lines >= 2 * MAX_ENTRIES {
+            self.compact();
+            return;
+        }
+
+        let result = serde_json::to_string(entry)
+            .map_err(|e| e.to_string())
+            .and_then(|line| {
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .and_then(|mut file| writeln!(file, "{}", line))
+                    .map_err(|e| e.to_string())
+            });
+        match result {
+            Ok(()) => self.file_lines += 1,
+            Err(e) => log::warn!("Failed to remember scan id: {}", e),
+        }
+    }
+
+    /// Rewrites the file with only the ids still remembered
+    fn compact(&mut self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        let mut content = String::new();
+        for entry in &self.order {
+            if let Ok(line) = serde_json::to_string(entry) {
+                content.push_str(&line);
+                content.push('\n');
+            }
+        }
+
+        let tmp_path = path.with_extension("jsonl.tmp");
+        match fs::write(&tmp_path, content).and_then(|_| fs::rename(&tmp_path, path)) {
+            Ok(()) => self.file_lines = self.order.len(),
+            Err(e) => log::warn!("Failed to compact recent scan ids: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("scanlink-dedup-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join(RECENT_SCANS_FILE)
+    }
+
+    #[test]
+    fn test_duplicates_are_rejected_per_device() {
+        let mut recent = RecentScanIds::new();
+        assert!(recent.insert_at("d1", "s1", 0));
+        assert!(!recent.insert_at("d1", "s1", 10));
+        assert!(recent.insert_at("d2", "s1", 10));
+        assert_eq!(recent.order.len(), 2);
+    }
+
+    #[test]
+    fn test_old_ids_expire() {
+        let mut recent = RecentScanIds::new();
+        assert!(recent.insert_at("d1", "s1", 0));
+        assert!(recent.insert_at("d1", "s1", RETENTION_SECS));
+        assert_eq!(recent.order.len(), 1);
+    }
+
+    #[test]
+    fn test_ids_survive_a_restart() {
+        let path = temp_path();
+        let mut recent = RecentScanIds::load_at(path.clone(), 0);
+        assert!(recent.insert_at("d1", "old", 0));
+        assert!(recent.insert_at("d1", "new", 100));
+        fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b"[200, \"d1\"").unwrap();
+
+        let mut reloaded = RecentScanIds::load_at(path.clone(), RETENTION_SECS + 50);
+        assert!(reloaded.contains_at("d1", "new", RETENTION_SECS + 50));
+        assert!(!reloaded.contains_at("d1", "old", RETENTION_SECS + 50));
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 1, "expired and torn lines are compacted away");
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
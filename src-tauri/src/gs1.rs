@@ -0,0 +1,330 @@
+use chrono::{Datelike, Local, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+/// ASCII Group Separator, how scanners transmit FNC1 between variable-length fields
+pub const GS: char = '\u{1d}';
+
+/// Symbology identifiers announcing GS1 data (GS1-128, DataMatrix, QR, DataBar, DotCode)
+const GS1_SYMBOLOGY_IDS: [&str; 5] = ["]C1", "]d2", "]Q3", "]e0", "]J1"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AiFormat {
+    /// Exact number of digits ending in a GS1 check digit
+    NumericCheck(usize),
+    /// Exact number of digits
+    Numeric(usize),
+    /// YYMMDD, day 00 meaning the end of the month
+    Date,
+    /// Up to this many digits
+    NumericVar(usize),
+    /// Up to this many characters
+    AlphanumericVar(usize),
+}
+
+impl AiFormat {
+    fn fixed_length(&self) -> Option<usize> {
+        match self {
+            AiFormat::NumericCheck(len) | AiFormat::Numeric(len) => Some(*len),
+            AiFormat::Date => Some(6),
+            AiFormat::NumericVar(_) | AiFormat::AlphanumericVar(_) => None,
+        }
+    }
+}
+
+/// Application Identifiers we understand. Unknown AIs make the whole string invalid,
+/// since their length (and so where the next field starts) is unknown.
+const AI_TABLE: [(&str, AiFormat); 22] = [
+    ("00", AiFormat::NumericCheck(18)),     // SSCC
+    ("01", AiFormat::NumericCheck(14)),     // GTIN
+    ("02", AiFormat::NumericCheck(14)),     // GTIN of contained items
+    ("10", AiFormat::AlphanumericVar(20)),  // Batch/lot
+    ("11", AiFormat::Date),                 // Production date
+    ("12", AiFormat::Date),                 // Due date
+    ("13", AiFormat::Date),                 // Packaging date
+    ("15", AiFormat::Date),                 // Best before
+    ("16", AiFormat::Date),                 // Sell by
+    ("17", AiFormat::Date),                 // Expiry
+    ("20", AiFormat::Numeric(2)),           // Variant
+    ("21", AiFormat::AlphanumericVar(20)),  // Serial number
+    ("22", AiFormat::AlphanumericVar(20)),  // Consumer product variant
+    ("30", AiFormat::NumericVar(8)),        // Variable count
+    ("37", AiFormat::NumericVar(8)),        // Count of trade items
+    ("240", AiFormat::AlphanumericVar(30)), // Additional product id
+    ("241", AiFormat::AlphanumericVar(30)), // Customer part number
+    ("250", AiFormat::AlphanumericVar(30)), // Secondary serial number
+    ("400", AiFormat::AlphanumericVar(30)), // Customer order number
+    ("410", AiFormat::NumericCheck(13)),    // Ship to GLN
+    ("414", AiFormat::NumericCheck(13)),    // Location GLN
+    ("7003", AiFormat::Numeric(10)),        // Expiry date and time
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Gs1Element {
+    pub ai: String,
+    pub value: String,
+}
+
+/// Parsed GS1 element string. The common fields are also exposed by name.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Gs1Data {
+    pub elements: Vec<Gs1Element>,
+    /// AI 01
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gtin: Option<String>,
+    /// AI 10
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lot: Option<String>,
+    /// AI 17 as an ISO date (YYYY-MM-DD)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiry: Option<String>,
+    /// AI 21
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+}
+
+impl Gs1Data {
+    /// Value of the first element with this AI
+    pub fn get(&self, ai: &str) -> Option<&str> {
+        self.elements.iter().find(|e| e.ai == ai).map(|e| e.value.as_str())
+    }
+
+    fn from_elements(elements: Vec<Gs1Element>) -> Self {
+        let mut data = Self { elements, ..Self::default() };
+        data.gtin = data.get("01").map(String::from);
+        data.lot = data.get("10").map(String::from);
+        data.serial = data.get("21").map(String::from);
+        data.expiry = data.get("17")
+            .and_then(|value| gs1_date(value, Local::now().year()))
+            .map(|date| date.format("%Y-%m-%d").to_string());
+        data
+    }
+}
+
+fn lookup_ai(data: &str) -> Option<(&'static str, AiFormat)> {
+    AI_TABLE.iter().find(|(ai, _)| data.starts_with(ai)).copied()
+}
+
+/// GS1 mod-10 check: weights 3 and 1 alternate from the digit left of the check digit
+fn has_valid_check_digit(digits: &str) -> bool {
+    let values: Vec<u32> = digits.chars().filter_map(|c| c.to_digit(10)).collect();
+    let Some((check, body)) = values.split_last() else {
+        return false;
+    };
+
+    let sum: u32 = body
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, digit)| if i % 2 == 0 { digit * 3 } else { *digit })
+        .sum();
+    (10 - sum % 10) % 10 == *check
+}
+
+/// Resolves a YYMMDD date. The century is chosen so the year falls within
+/// 49 years before and 50 years after `current_year`, as the GS1 spec requires.
+fn gs1_date(value: &str, current_year: i32) -> Option<NaiveDate> {
+    if value.len() != 6 || !value.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let yy: i32 = value[0..2].parse().ok()?;
+    let month: u32 = value[2..4].parse().ok()?;
+    let day: u32 = value[4..6].parse().ok()?;
+
+    let mut century = current_year - current_year % 100;
+    let difference = yy - current_year % 100;
+    if difference >= 51 {
+        century -= 100;
+    } else if difference <= -50 {
+        century += 100;
+    }
+    let year = century + yy;
+
+    if day == 0 {
+        // Last day of the month
+        if !(1..=12).contains(&month) {
+            return None;
+        }
+        let (next_year, next_month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+        NaiveDate::from_ymd_opt(next_year, next_month, 1)?.pred_opt()
+    } else {
+        NaiveDate::from_ymd_opt(year, month, day)
+    }
+}
+
+fn validate_element(ai: &str, format: AiFormat, value: &str) -> Result<(), String> {
+    let all_digits = value.chars().all(|c| c.is_ascii_digit());
+
+    match format {
+        AiFormat::NumericCheck(len) | AiFormat::Numeric(len) | AiFormat::NumericVar(len)
+            if !all_digits || value.is_empty() || value.len() > len =>
+        {
+            return Err(format!("AI ({}) must be numeric, up to {} digits: '{}'", ai, len, value));
+        }
+        AiFormat::AlphanumericVar(max) if value.is_empty() || value.chars().count() > max => {
+            return Err(format!("AI ({}) must have 1 to {} characters: '{}'", ai, max, value));
+        }
+        _ => {}
+    }
+
+    if let Some(len) = format.fixed_length() {
+        if value.len() != len {
+            return Err(format!("AI ({}) must have exactly {} characters: '{}'", ai, len, value));
+        }
+    }
+
+    match format {
+        AiFormat::NumericCheck(_) if !has_valid_check_digit(value) => {
+            Err(format!("AI ({}) has an invalid check digit: '{}'", ai, value))
+        }
+        AiFormat::Date if gs1_date(value, Local::now().year()).is_none() => {
+            Err(format!("AI ({}) is not a valid date: '{}'", ai, value))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Parses the human readable form, e.g. `(01)09506000134352(10)ABC`
+fn parse_bracketed(data: &str) -> Result<Vec<Gs1Element>, String> {
+    let mut elements = Vec::new();
+    let mut rest = data;
+
+    while !rest.is_empty() {
+        let inner = rest.strip_prefix('(').ok_or_else(|| format!("Expected '(' at '{}'", rest))?;
+        let (ai, after) = inner.split_once(')').ok_or("Unclosed AI bracket")?;
+        let (value, next) = match after.find('(') {
+            Some(index) => after.split_at(index),
+            None => (after, ""),
+        };
+
+        let (known, format) = lookup_ai(ai)
+            .filter(|(known, _)| *known == ai)
+            .ok_or_else(|| format!("Unknown AI ({})", ai))?;
+        validate_element(known, format, value)?;
+        elements.push(Gs1Element { ai: known.to_string(), value: value.to_string() });
+        rest = next;
+    }
+
+    Ok(elements)
+}
+
+/// Parses the transmitted form: AIs followed by their data, variable-length
+/// fields terminated by GS (FNC1) unless they are last
+fn parse_raw(data: &str) -> Result<Vec<Gs1Element>, String> {
+    let mut elements = Vec::new();
+    let mut rest = data.trim_start_matches(GS);
+
+    while !rest.is_empty() {
+        let (ai, format) = lookup_ai(rest).ok_or_else(|| format!("Unknown AI at '{}'", rest))?;
+        let after = &rest[ai.len()..];
+
+        let (value, next) = match format.fixed_length() {
+            Some(len) => {
+                let end = after.char_indices().nth(len).map(|(i, _)| i).unwrap_or(after.len());
+                after.split_at(end)
+            }
+            None => match after.find(GS) {
+                Some(index) => after.split_at(index),
+                None => (after, ""),
+            },
+        };
+
+        validate_element(ai, format, value)?;
+        elements.push(Gs1Element { ai: ai.to_string(), value: value.to_string() });
+        // A separator after a fixed-length field is redundant but allowed
+        rest = next.trim_start_matches(GS);
+    }
+
+    Ok(elements)
+}
+
+/// Parses and validates a GS1 element string, in transmitted form (with an optional
+/// symbology identifier such as `]d2`) or in human readable form with brackets
+pub fn parse(barcode: &str) -> Result<Gs1Data, String> {
+    let data = GS1_SYMBOLOGY_IDS
+        .iter()
+        .find_map(|id| barcode.strip_prefix(id))
+        .unwrap_or(barcode);
+
+    let elements = if data.starts_with('(') {
+        parse_bracketed(data)?
+    } else {
+        parse_raw(data)?
+    };
+
+    if elements.is_empty() {
+        return Err("No GS1 elements found".to_string());
+    }
+    Ok(Gs1Data::from_elements(elements))
+}
+
+fn looks_like_gs1(barcode: &str, barcode_type: Option<&str>) -> bool {
+    GS1_SYMBOLOGY_IDS.iter().any(|id| barcode.starts_with(id))
+        || barcode.contains(GS)
+        || (barcode.starts_with('(') && barcode[1..].starts_with(|c: char| c.is_ascii_digit()))
+        || barcode_type.is_some_and(|t| t.to_ascii_uppercase().contains("GS1"))
+}
+
+/// Parses scans that look like GS1 data. Plain barcodes (an EAN-13, a URL in a QR
+/// code) are left alone; GS1-looking scans that fail validation are logged.
+pub fn detect(barcode: &str, barcode_type: Option<&str>) -> Option<Gs1Data> {
+    if !looks_like_gs1(barcode, barcode_type) {
+        return None;
+    }
+
+    match parse(barcode) {
+        Ok(data) => Some(data),
+        Err(e) => {
+            log::warn!("Scan looks like GS1 data but is invalid: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_transmitted_form() {
+        let scan = format!("]d201095011015300031725123110AB-123{}21SN0042", GS);
+        let data = parse(&scan).unwrap();
+
+        assert_eq!(data.gtin.as_deref(), Some("09501101530003"));
+        assert_eq!(data.expiry.as_deref(), Some("2025-12-31"));
+        assert_eq!(data.lot.as_deref(), Some("AB-123"));
+        assert_eq!(data.serial.as_deref(), Some("SN0042"));
+        assert_eq!(data.elements.len(), 4);
+    }
+
+    #[test]
+    fn test_parse_bracketed_form() {
+        let data = parse("(01)09501101530003(17)260200(10)L1").unwrap();
+        assert_eq!(data.get("01"), Some("09501101530003"));
+        // Day 00 is the last day of the month
+        assert_eq!(data.expiry.as_deref(), Some("2026-02-28"));
+        assert_eq!(data.lot.as_deref(), Some("L1"));
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        assert!(parse("0109501101530004").unwrap_err().contains("check digit"));
+        assert!(parse("01095011015300").is_err(), "GTIN too short");
+        assert!(parse("0109501101530003171313310").is_err(), "invalid month");
+        assert!(parse("99ABC").unwrap_err().contains("Unknown AI"));
+        assert!(parse(&format!("10{}", "X".repeat(21))).is_err(), "lot too long");
+    }
+
+    #[test]
+    fn test_detect_ignores_plain_barcodes() {
+        assert_eq!(detect("7891234567895", Some("EAN_13")), None);
+        assert!(detect("0109501101530003", Some("GS1_128")).is_some());
+        assert_eq!(detect("https://example.com", Some("QR_CODE")), None);
+    }
+
+    #[test]
+    fn test_century_window() {
+        assert_eq!(gs1_date("991231", 2026), NaiveDate::from_ymd_opt(1999, 12, 31));
+        assert_eq!(gs1_date("751231", 2026), NaiveDate::from_ymd_opt(2075, 12, 31));
+        assert_eq!(gs1_date("770101", 2026), NaiveDate::from_ymd_opt(1977, 1, 1));
+    }
+}
@@ -0,0 +1,531 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Utc};
+use crate::gs1::Gs1Data;
+use crate::models::BarcodeMessage;
+use crate::output::SinkResult;
+use crate::storage;
+
+const HISTORY_FILE: &str = "scan_history.jsonl";
+const DEFAULT_PAGE_SIZE: usize = 50;
+/// Size at which the oldest records are dropped (roughly 100,000 scans)
+const MAX_HISTORY_BYTES: u64 = 32 * 1024 * 1024;
+
+fn get_history_path() -> Result<PathBuf, String> {
+    storage::get_data_file_path(HISTORY_FILE)
+}
+
+/// Exclusive access to the history file, shared by every process using it
+/// (the app and the daemon) and released on drop.
+///
+/// The lock is taken on a separate file because rewrites replace the history
+/// file itself.
+struct HistoryLock {
+    _file: File,
+}
+
+impl HistoryLock {
+    #[cfg(unix)]
+    fn acquire(history_path: &Path) -> Result<Self, String> {
+        use std::os::unix::io::AsRawFd;
+
+        let file = Self::open(history_path)?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(format!("Failed to lock history: {}", std::io::Error::last_os_error()));
+        }
+        Ok(Self { _file: file })
+    }
+
+    #[cfg(windows)]
+    fn acquire(history_path: &Path) -> Result<Self, String> {
+        const ERROR_SHARING_VIOLATION: i32 = 32;
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        loop {
+            match Self::open(history_path) {
+                Ok(file) => return Ok(Self { _file: file }),
+                Err(e) if e.raw_os_error() == Some(ERROR_SHARING_VIOLATION) && std::time::Instant::now() < deadline => {
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                }
+                Err(e) => return Err(format!("Failed to lock history: {}", e)),
+            }
+        }
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    fn acquire(history_path: &Path) -> Result<Self, String> {
+        Ok(Self { _file: Self::open(history_path)? })
+    }
+
+    #[cfg(not(windows))]
+    fn open(history_path: &Path) -> Result<File, String> {
+        OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(history_path.with_extension("jsonl.lock"))
+            .map_err(|e| format!("Failed to open history lock: {}", e))
+    }
+
+    /// Opening without sharing fails while another handle is open
+    #[cfg(windows)]
+    fn open(history_path: &Path) -> std::io::Result<File> {
+        use std::os::windows::fs::OpenOptionsExt;
+
+        OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .share_mode(0)
+            .open(history_path.with_extension("jsonl.lock"))
+    }
+}
+
+/// Result of delivering a scan to the configured outputs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputResult {
+    /// True when every sink succeeded
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Per-sink outcome (absent in records written before output sinks)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sinks: Vec<SinkResult>,
+}
+
+impl OutputResult {
+    pub fn failed(error: String) -> Self {
+        Self { success: false, error: Some(error), sinks: Vec::new() }
+    }
+
+    /// Summarizes per-sink results; the error lists every failed sink
+    pub fn from_sinks(sinks: Vec<SinkResult>) -> Self {
+        let errors: Vec<String> = sinks
+            .iter()
+            .filter(|r| !r.success)
+            .map(|r| format!("{}: {}", r.sink, r.error.as_deref().unwrap_or("failed")))
+            .collect();
+
+        Self {
+            success: errors.is_empty(),
+            error: if errors.is_empty() { None } else { Some(errors.join("; ")) },
+            sinks,
+        }
+    }
+}
+
+/// A single scan as persisted in the history file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanRecord {
+    pub id: String,
+    pub barcode: String,
+    /// Barcode as scanned, when transform rules changed it
+    #[serde(rename = "originalBarcode", default, skip_serializing_if = "Option::is_none")]
+    pub original_barcode: Option<String>,
+    #[serde(rename = "barcodeType", skip_serializing_if = "Option::is_none")]
+    pub barcode_type: Option<String>,
+    #[serde(rename = "deviceId")]
+    pub device_id: String,
+    #[serde(rename = "deviceName", skip_serializing_if = "Option::is_none")]
+    pub device_name: Option<String>,
+    /// When the device reports the scan happened (RFC 3339)
+    #[serde(rename = "scannedAt")]
+    pub scanned_at: String,
+    /// When the desktop received the scan (RFC 3339)
+    #[serde(rename = "receivedAt")]
+    pub received_at: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gs1: Option<Gs1Data>,
+    pub output: OutputResult,
+}
+
+impl ScanRecord {
+    pub fn new(barcode_msg: &BarcodeMessage, output: OutputResult) -> Self {
+        let now = Utc::now();
+        let scanned_at = DateTime::from_timestamp(barcode_msg.timestamp, 0).unwrap_or(now);
+
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            barcode: barcode_msg.barcode.clone(),
+            original_barcode: None,
+            barcode_type: barcode_msg.barcode_type.clone(),
+            device_id: barcode_msg.device_id.clone(),
+            device_name: barcode_msg.device_name.clone(),
+            scanned_at: scanned_at.to_rfc3339(),
+            received_at: now.to_rfc3339(),
+            gs1: barcode_msg.gs1.clone(),
+            output,
+        }
+    }
+}
+
+/// Filter and paging options for history queries
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HistoryQuery {
+    #[serde(rename = "deviceId", default)]
+    pub device_id: Option<String>,
+    /// Only records received at or after this instant (RFC 3339)
+    #[serde(default)]
+    pub from: Option<String>,
+    /// Only records received at or before this instant (RFC 3339)
+    #[serde(default)]
+    pub to: Option<String>,
+    /// Case-insensitive substring matched against the barcode
+    #[serde(default)]
+    pub pattern: Option<String>,
+    #[serde(default)]
+    pub offset: usize,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+/// One page of history, newest first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryPage {
+    pub records: Vec<ScanRecord>,
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+fn parse_bound(value: &Option<String>, name: &str) -> Result<Option<DateTime<Utc>>, String> {
+    match value {
+        Some(v) if !v.is_empty() => DateTime::parse_from_rfc3339(v)
+            .map(|dt| Some(dt.with_timezone(&Utc)))
+            .map_err(|e| format!("Invalid '{}' date: {}", name, e)),
+        _ => Ok(None),
+    }
+}
+
+/// Reads every non-empty line of the history file
+fn read_lines(path: &Path) -> Result<Vec<String>, String> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let file = File::open(path)
+        .map_err(|e| format!("Failed to open history: {}", e))?;
+
+    let mut lines = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(|e| format!("Failed to read history: {}", e))?;
+        if !line.trim().is_empty() {
+            lines.push(line);
+        }
+    }
+
+    Ok(lines)
+}
+
+/// Reads every record from disk, skipping lines that fail to parse
+fn read_all(path: &Path) -> Result<Vec<ScanRecord>, String> {
+    let mut records = Vec::new();
+    for (index, line) in read_lines(path)?.iter().enumerate() {
+        match serde_json::from_str::<ScanRecord>(line) {
+            Ok(record) => records.push(record),
+            Err(e) => log::warn!("Skipping malformed history line {}: {}", index + 1, e),
+        }
+    }
+
+    Ok(records)
+}
+
+/// Appends a scan record to the history file
+pub fn append(record: &ScanRecord) -> Result<(), String> {
+    append_to(&get_history_path()?, record, MAX_HISTORY_BYTES)
+}
+
+fn append_to(path: &Path, record: &ScanRecord, max_bytes: u64) -> Result<(), String> {
+    let line = serde_json::to_string(record)
+        .map_err(|e| format!("Failed to serialize scan record: {}", e))?;
+
+    let _lock = HistoryLock::acquire(path)?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("Failed to open history: {}", e))?;
+
+    writeln!(file, "{}", line)
+        .map_err(|e| format!("Failed to write history: {}", e))?;
+
+    let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+    if size > max_bytes {
+        drop(file);
+        // Trim to three quarters so the next appends do not rewrite again
+        let removed = trim_to(path, max_bytes / 4 * 3)?;
+        log::info!("History reached {} bytes, dropped the {} oldest records", size, removed);
+    }
+
+    Ok(())
+}
+
+/// Drops the oldest lines until the file fits in `max_bytes`; the caller holds the lock
+fn trim_to(path: &Path, max_bytes: u64) -> Result<usize, String> {
+    let lines = read_lines(path)?;
+    let mut size = 0;
+    let kept = lines
+        .iter()
+        .rev()
+        .take_while(|line| {
+            size += line.len() as u64 + 1;
+            size <= max_bytes
+        })
+        .count();
+    let removed = lines.len() - kept;
+    write_lines(path, &lines[removed..])?;
+    Ok(removed)
+}
+
+/// Returns the records matching the query, newest first
+pub fn query(query: &HistoryQuery) -> Result<HistoryPage, String> {
+    query_in(&get_history_path()?, query)
+}
+
+fn query_in(path: &Path, query: &HistoryQuery) -> Result<HistoryPage, String> {
+    let from = parse_bound(&query.from, "from")?;
+    let to = parse_bound(&query.to, "to")?;
+    let pattern = query.pattern.as_ref()
+        .filter(|p| !p.is_empty())
+        .map(|p| p.to_lowercase());
+
+    let records = {
+        let _lock = HistoryLock::acquire(path)?;
+        read_all(path)?
+    };
+
+    let matching: Vec<ScanRecord> = records
+        .into_iter()
+        .rev()
+        .filter(|r| query.device_id.as_deref().map_or(true, |id| r.device_id == id))
+        .filter(|r| {
+            if from.is_none() && to.is_none() {
+                return true;
+            }
+            match DateTime::parse_from_rfc3339(&r.received_at) {
+                Ok(received) => {
+                    let received = received.with_timezone(&Utc);
+                    from.map_or(true, |f| received >= f) && to.map_or(true, |t| received <= t)
+                }
+                Err(_) => false,
+            }
+        })
+        .filter(|r| pattern.as_ref().map_or(true, |p| r.barcode.to_lowercase().contains(p)))
+        .collect();
+
+    let total = matching.len();
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    let records = matching.into_iter().skip(query.offset).take(limit).collect();
+
+    Ok(HistoryPage {
+        records,
+        total,
+        offset: query.offset,
+        limit,
+    })
+}
+
+/// Replaces the history file with `lines`
+fn write_lines(path: &Path, lines: &[String]) -> Result<(), String> {
+    let mut content = String::new();
+    for line in lines {
+        content.push_str(line);
+        content.push('\n');
+    }
+
+    // Write to a temporary file first so a crash never leaves a truncated history
+    let tmp_path = path.with_extension("jsonl.tmp");
+    fs::write(&tmp_path, content)
+        .map_err(|e| format!("Failed to write history: {}", e))?;
+    fs::rename(&tmp_path, path)
+        .map_err(|e| format!("Failed to replace history: {}", e))
+}
+
+/// Rewrites the history file keeping only the records accepted by `keep`.
+/// Lines that fail to parse are kept as they are.
+fn rewrite<F: Fn(&ScanRecord) -> bool>(path: &Path, keep: F) -> Result<usize, String> {
+    let _lock = HistoryLock::acquire(path)?;
+    let lines = read_lines(path)?;
+    let before = lines.len();
+    let kept: Vec<String> = lines
+        .into_iter()
+        .filter(|line| serde_json::from_str::<ScanRecord>(line).map_or(true, |r| keep(&r)))
+        .collect();
+    let removed = before - kept.len();
+
+    if removed > 0 {
+        write_lines(path, &kept)?;
+    }
+    Ok(removed)
+}
+
+/// Deletes the records with the given ids, returning how many were removed
+pub fn delete(ids: &[String]) -> Result<usize, String> {
+    let ids: HashSet<&str> = ids.iter().map(|id| id.as_str()).collect();
+    rewrite(&get_history_path()?, |r| !ids.contains(r.id.as_str()))
+}
+
+/// Deletes every record in the history; lines that fail to parse are kept
+pub fn clear() -> Result<usize, String> {
+    rewrite(&get_history_path()?, |_| false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("scanlink-history-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join(HISTORY_FILE)
+    }
+
+    fn record(id: &str, barcode: &str, device_id: &str, received_at: &str) -> ScanRecord {
+        ScanRecord {
+            id: id.to_string(),
+            barcode: barcode.to_string(),
+            original_barcode: None,
+            barcode_type: None,
+            device_id: device_id.to_string(),
+            device_name: None,
+            scanned_at: received_at.to_string(),
+            received_at: received_at.to_string(),
+            gs1: None,
+            output: OutputResult { success: true, error: None, sinks: Vec::new() },
+        }
+    }
+
+    /// Appends r1..r4 from two devices, one hour apart
+    fn sample_history() -> PathBuf {
+        let path = temp_path();
+        for (id, barcode, device, at) in [
+            ("r1", "ABC-001", "d1", "2026-01-01T10:00:00+00:00"),
+            ("r2", "abc-002", "d2", "2026-01-01T11:00:00+00:00"),
+            ("r3", "XYZ-003", "d1", "2026-01-01T12:00:00+00:00"),
+            ("r4", "ABC-004", "d1", "2026-01-01T13:00:00+00:00"),
+        ] {
+            append_to(&path, &record(id, barcode, device, at), MAX_HISTORY_BYTES).unwrap();
+        }
+        path
+    }
+
+    fn ids(page: &HistoryPage) -> Vec<&str> {
+        page.records.iter().map(|r| r.id.as_str()).collect()
+    }
+
+    fn cleanup(path: &Path) {
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_query_returns_newest_first() {
+        let path = sample_history();
+        let page = query_in(&path, &HistoryQuery::default()).unwrap();
+        assert_eq!(ids(&page), ["r4", "r3", "r2", "r1"]);
+        assert_eq!((page.total, page.offset, page.limit), (4, 0, DEFAULT_PAGE_SIZE));
+        cleanup(&path);
+    }
+
+    #[test]
+    fn test_query_filters() {
+        let path = sample_history();
+
+        let by_device = HistoryQuery { device_id: Some("d2".into()), ..Default::default() };
+        assert_eq!(ids(&query_in(&path, &by_device).unwrap()), ["r2"]);
+
+        let by_pattern = HistoryQuery { pattern: Some("abc".into()), ..Default::default() };
+        assert_eq!(ids(&query_in(&path, &by_pattern).unwrap()), ["r4", "r2", "r1"]);
+
+        let by_date = HistoryQuery {
+            from: Some("2026-01-01T11:00:00Z".into()),
+            to: Some("2026-01-01T13:30:00+01:00".into()),
+            ..Default::default()
+        };
+        assert_eq!(ids(&query_in(&path, &by_date).unwrap()), ["r3", "r2"]);
+
+        let invalid = HistoryQuery { from: Some("yesterday".into()), ..Default::default() };
+        assert!(query_in(&path, &invalid).is_err());
+
+        cleanup(&path);
+    }
+
+    #[test]
+    fn test_query_paging() {
+        let path = sample_history();
+        let page = query_in(&path, &HistoryQuery { offset: 1, limit: Some(2), ..Default::default() }).unwrap();
+        assert_eq!(ids(&page), ["r3", "r2"]);
+        assert_eq!((page.total, page.offset, page.limit), (4, 1, 2));
+
+        let past_end = query_in(&path, &HistoryQuery { offset: 10, ..Default::default() }).unwrap();
+        assert!(past_end.records.is_empty());
+        assert_eq!(past_end.total, 4);
+        cleanup(&path);
+    }
+
+    #[test]
+    fn test_query_without_history_is_empty() {
+        let path = temp_path();
+        let page = query_in(&path, &HistoryQuery::default()).unwrap();
+        assert_eq!(page.total, 0);
+        cleanup(&path);
+    }
+
+    #[test]
+    fn test_delete_and_clear_keep_unparseable_lines() {
+        let path = sample_history();
+        let unknown = r#"{"id":"from-a-newer-version"}"#;
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(format!("{}\n", unknown).as_bytes()).unwrap();
+
+        assert_eq!(rewrite(&path, |r| !["r1", "r3", "missing"].contains(&r.id.as_str())).unwrap(), 2);
+        assert_eq!(ids(&query_in(&path, &HistoryQuery::default()).unwrap()), ["r4", "r2"]);
+
+        assert_eq!(rewrite(&path, |_| false).unwrap(), 2);
+        assert_eq!(query_in(&path, &HistoryQuery::default()).unwrap().total, 0);
+        assert_eq!(fs::read_to_string(&path).unwrap(), format!("{}\n", unknown));
+        cleanup(&path);
+    }
+
+    #[test]
+    fn test_oldest_records_are_dropped_at_the_size_cap() {
+        let path = temp_path();
+        let line_len = serde_json::to_string(&record("r0", "CODE", "d1", "2026-01-01T10:00:00+00:00")).unwrap().len() as u64 + 1;
+        let max_bytes = line_len * 4;
+        for i in 0..5 {
+            append_to(&path, &record(&format!("r{}", i), "CODE", "d1", "2026-01-01T10:00:00+00:00"), max_bytes).unwrap();
+        }
+
+        // The fifth append exceeds four lines and trims to three
+        let page = query_in(&path, &HistoryQuery::default()).unwrap();
+        assert_eq!(ids(&page), ["r4", "r3", "r2"]);
+        assert!(fs::metadata(&path).unwrap().len() <= max_bytes);
+        cleanup(&path);
+    }
+
+    #[test]
+    fn test_concurrent_appends_and_rewrites_lose_nothing() {
+        let path = temp_path();
+        let writers: Vec<_> = (0..4)
+            .map(|t| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    for i in 0..25 {
+                        let id = format!("t{}-{}", t, i);
+                        append_to(&path, &record(&id, "CODE", "d1", "2026-01-01T10:00:00+00:00"), MAX_HISTORY_BYTES).unwrap();
+                        if i % 5 == 0 {
+                            assert_eq!(rewrite(&path, |r| r.id != id).unwrap(), 1);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        // Every thread deleted 5 of its 25 records
+        assert_eq!(query_in(&path, &HistoryQuery::default()).unwrap().total, 80);
+        cleanup(&path);
+    }
+}
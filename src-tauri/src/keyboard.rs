@@ -1,186 +1,504 @@
-use arboard::Clipboard;
 use enigo::{Enigo, Keyboard, Key, Direction, Settings};
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
 use std::thread;
 use std::time::Duration;
 use std::process::Command;
+use std::sync::Mutex;
+use crate::clipboard::PasteClipboard;
+use crate::template::{KeyAction, SpecialKey};
+#[cfg(target_os = "linux")]
+use crate::uinput::{self, UinputStatus, VirtualKeyboard};
+
+/// Default delay between characters in type mode
+pub const DEFAULT_KEY_DELAY_MS: u64 = 12;
+
+/// How text (as opposed to special keys) reaches the focused window
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InputMode {
+    /// Copy to the clipboard and press Ctrl+V (fast, but replaces the clipboard)
+    #[default]
+    Paste,
+    /// Emit each character as key events; works where paste is blocked
+    Type,
+}
 
-/// Check if we're running on Wayland
-fn is_wayland() -> bool {
-    std::env::var("XDG_SESSION_TYPE")
-        .map(|s| s.to_lowercase() == "wayland")
-        .unwrap_or(false)
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypingOptions {
+    pub mode: InputMode,
+    /// Pause between characters in type mode
+    pub key_delay: Duration,
+    /// Put the user's clipboard content back after pasting
+    pub preserve_clipboard: bool,
+    /// Simulator to use instead of the detected one
+    pub backend: Option<InputBackend>,
+    /// XKB layout for the uinput keyboard, e.g. "de(nodeadkeys)", instead of the system one
+    pub keyboard_layout: Option<String>,
 }
 
-/// Check if ydotool daemon is running and ydotool is available
-fn is_ydotool_available() -> bool {
-    Command::new("which")
-        .arg("ydotool")
-        .output()
-        .map(|o| o.status.success())
-        .unwrap_or(false)
+impl Default for TypingOptions {
+    fn default() -> Self {
+        Self {
+            mode: InputMode::Paste,
+            key_delay: Duration::from_millis(DEFAULT_KEY_DELAY_MS),
+            preserve_clipboard: true,
+            backend: None,
+            keyboard_layout: None,
+        }
+    }
 }
 
-/// Check if wl-copy/wl-paste are available for Wayland clipboard
-fn is_wl_clipboard_available() -> bool {
-    Command::new("which")
-        .arg("wl-copy")
-        .output()
-        .map(|o| o.status.success())
-        .unwrap_or(false)
+/// Piece of text as a backend handles it in type mode
+#[derive(Debug, Clone, PartialEq)]
+enum TypeChunk {
+    Type(String),
+    Key(SpecialKey),
+    /// Characters the backend cannot type, pasted instead
+    Paste(String),
 }
 
-/// Check if xdotool is available for X11
-fn is_xdotool_available() -> bool {
+/// Splits text into runs the backend can type, line breaks/tabs (pressed as keys)
+/// and runs that have to be pasted
+fn split_typeable(text: &str, can_type: impl Fn(char) -> bool) -> Vec<TypeChunk> {
+    let mut chunks: Vec<TypeChunk> = Vec::new();
+
+    for c in text.chars() {
+        let key = match c {
+            '\n' | '\r' => Some(SpecialKey::Enter),
+            '\t' => Some(SpecialKey::Tab),
+            _ => None,
+        };
+        if let Some(key) = key {
+            chunks.push(TypeChunk::Key(key));
+            continue;
+        }
+
+        let typeable = can_type(c);
+        match chunks.last_mut() {
+            Some(TypeChunk::Type(run)) if typeable => run.push(c),
+            Some(TypeChunk::Paste(run)) if !typeable => run.push(c),
+            _ if typeable => chunks.push(TypeChunk::Type(c.to_string())),
+            _ => chunks.push(TypeChunk::Paste(c.to_string())),
+        }
+    }
+
+    chunks
+}
+
+/// xdotool maps any printable character (including Unicode) to a keysym
+fn is_xdotool_typeable(c: char) -> bool {
+    !c.is_control()
+}
+
+/// ydotool 0.1.x only knows the US keyboard layout
+fn is_ydotool_typeable(c: char) -> bool {
+    c.is_ascii_graphic() || c == ' '
+}
+
+/// Simulator used by `type_actions`, chosen from the detected environment
+/// unless the config names one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InputBackend {
+    Uinput,
+    Ydotool,
+    Xdotool,
+    Enigo,
+}
+
+/// Input settings from the config that replace the detected ones
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InputOverrides {
+    /// Simulator to use regardless of the environment (None = automatic)
+    pub backend: Option<InputBackend>,
+    /// XKB layout for the uinput keyboard, e.g. "de(nodeadkeys)" (None = system layout)
+    pub keyboard_layout: Option<String>,
+}
+
+/// What this machine offers for input simulation. Probed once and cached,
+/// since every scan needs it; `reprobe` refreshes it after installing tools.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InputEnvironment {
+    /// `XDG_SESSION_TYPE`, e.g. "x11", "wayland" or "tty"
+    pub session_type: Option<String>,
+    pub wayland: bool,
+    pub ydotool: bool,
+    pub xdotool: bool,
+    /// wl-copy/wl-paste for the Wayland clipboard
+    pub wl_clipboard: bool,
+    pub xclip: bool,
+    /// The native virtual keyboard could be created
+    pub uinput: bool,
+    /// Why uinput cannot be used and how to fix it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uinput_problem: Option<String>,
+    pub backend: InputBackend,
+}
+
+/// Outcome of exercising the selected backend without typing anything
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DryRunResult {
+    pub success: bool,
+    pub message: String,
+}
+
+/// Report for support staff when typing does not work
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InputDiagnostics {
+    #[serde(flatten)]
+    pub environment: InputEnvironment,
+    pub dry_run: DryRunResult,
+}
+
+static ENVIRONMENT: Mutex<Option<InputEnvironment>> = Mutex::new(None);
+
+fn is_command_available(program: &str) -> bool {
     Command::new("which")
-        .arg("xdotool")
+        .arg(program)
         .output()
         .map(|o| o.status.success())
         .unwrap_or(false)
 }
 
-/// Type barcode using ydotool (works on Wayland with GNOME)
-fn type_barcode_ydotool(barcode: &str) -> Result<(), String> {
-    log::info!("Using ydotool for Wayland input simulation");
+/// Checks that /dev/uinput is writable. Returns the problem, if any.
+/// The virtual keyboard itself is only created once the backend is used.
+#[cfg(target_os = "linux")]
+fn probe_uinput() -> Option<String> {
+    match uinput::status() {
+        UinputStatus::Available => None,
+        UinputStatus::PermissionDenied => Some(format!(
+            "/dev/uinput is not writable. {}",
+            UinputStatus::PermissionDenied.hint().unwrap_or_default()
+        )),
+        UinputStatus::Missing => Some(format!(
+            "/dev/uinput not found. {}",
+            UinputStatus::Missing.hint().unwrap_or_default()
+        )),
+    }
+}
 
-    // Use wl-copy for clipboard if available, otherwise try arboard
-    let clipboard_set = if is_wl_clipboard_available() {
-        log::debug!("Using wl-copy for clipboard");
-        Command::new("wl-copy")
-            .arg(barcode)
-            .output()
-            .map(|o| o.status.success())
-            .unwrap_or(false)
+#[cfg(not(target_os = "linux"))]
+fn probe_uinput() -> Option<String> {
+    Some("uinput is only available on Linux".to_string())
+}
+
+fn select_backend(env: &InputEnvironment) -> InputBackend {
+    if !env.wayland && env.xdotool {
+        // xdotool types with the live X session's layout; uinput only knows the system one
+        InputBackend::Xdotool
+    } else if env.uinput {
+        // The native virtual keyboard works everywhere when /dev/uinput is writable
+        InputBackend::Uinput
+    } else if env.wayland && env.ydotool {
+        InputBackend::Ydotool
+    } else if env.wayland {
+        // enigo might work with some compositors
+        InputBackend::Enigo
+    } else if env.xdotool {
+        InputBackend::Xdotool
+    } else {
+        InputBackend::Enigo
+    }
+}
+
+fn probe_environment() -> InputEnvironment {
+    let session_type = std::env::var("XDG_SESSION_TYPE").ok().filter(|s| !s.is_empty());
+    let wayland = session_type.as_deref().map(|s| s.eq_ignore_ascii_case("wayland")).unwrap_or(false);
+    let uinput_problem = probe_uinput();
+
+    let mut env = InputEnvironment {
+        session_type,
+        wayland,
+        ydotool: is_command_available("ydotool"),
+        xdotool: is_command_available("xdotool"),
+        wl_clipboard: is_command_available("wl-copy") && is_command_available("wl-paste"),
+        xclip: is_command_available("xclip"),
+        uinput: uinput_problem.is_none(),
+        uinput_problem,
+        backend: InputBackend::Enigo,
+    };
+    env.backend = select_backend(&env);
+
+    log::info!(
+        "Input environment - session: {}, ydotool: {}, xdotool: {}, wl-clipboard: {}, xclip: {}, uinput: {}, backend: {:?}",
+        env.session_type.as_deref().unwrap_or("unknown"),
+        env.ydotool, env.xdotool, env.wl_clipboard, env.xclip, env.uinput, env.backend
+    );
+    if let Some(ref problem) = env.uinput_problem {
+        log::info!("Native virtual keyboard unavailable, falling back to external tools: {}", problem);
+    }
+    if env.wayland && env.backend == InputBackend::Enigo {
+        log::warn!("Wayland detected but ydotool not available. For best Wayland support, install ydotool: sudo apt install ydotool");
+    }
+    env
+}
+
+/// Detected input environment, probing on first use
+pub fn environment() -> InputEnvironment {
+    let mut guard = ENVIRONMENT.lock().unwrap();
+    guard.get_or_insert_with(probe_environment).clone()
+}
+
+/// Probes the environment again, e.g. after the user installed ydotool or a udev rule
+pub fn reprobe() -> InputEnvironment {
+    let mut guard = ENVIRONMENT.lock().unwrap();
+    guard.insert(probe_environment()).clone()
+}
+
+/// Check if we're running on Wayland
+pub(crate) fn is_wayland() -> bool {
+    environment().wayland
+}
+
+/// Runs a harmless command and reports whether it worked
+fn dry_run_command(program: &str, args: &[&str]) -> Result<String, String> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|e| format!("Failed to run {}: {}", program, e))?;
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    if output.status.success() {
+        Ok(if stderr.is_empty() { format!("{} works", program) } else { format!("{} works: {}", program, stderr) })
     } else {
-        log::debug!("Using arboard for clipboard");
-        // Fallback to arboard
-        Clipboard::new()
-            .and_then(|mut cb| cb.set_text(barcode))
-            .is_ok()
+        Err(format!("{} failed: {}", program, if stderr.is_empty() { output.status.to_string() } else { stderr }))
+    }
+}
+
+/// Exercises the backend without sending any key
+fn dry_run(backend: InputBackend) -> Result<String, String> {
+    match backend {
+        #[cfg(target_os = "linux")]
+        InputBackend::Uinput => uinput::with_keyboard(|_| Ok("Virtual keyboard is ready".to_string())),
+        #[cfg(not(target_os = "linux"))]
+        InputBackend::Uinput => Err("uinput is only available on Linux".to_string()),
+        // Typing nothing still connects to ydotoold (1.x) or opens /dev/uinput (0.1.x)
+        InputBackend::Ydotool => dry_run_command("ydotool", &["type", ""]),
+        // Needs a working connection to the X server
+        InputBackend::Xdotool => dry_run_command("xdotool", &["getmouselocation"]),
+        InputBackend::Enigo => Enigo::new(&Settings::default())
+            .map(|_| "Keyboard simulator initialized".to_string())
+            .map_err(|e| format!("Failed to initialize keyboard simulator: {}", e)),
+    }
+}
+
+/// Environment, chosen backend and a dry-run of it, optionally re-probing first
+pub fn diagnostics(reprobe_first: bool) -> InputDiagnostics {
+    let environment = if reprobe_first { reprobe() } else { environment() };
+    let dry_run = match dry_run(environment.backend) {
+        Ok(message) => DryRunResult { success: true, message },
+        Err(message) => DryRunResult { success: false, message },
     };
+    InputDiagnostics { environment, dry_run }
+}
 
-    if !clipboard_set {
-        return Err("Failed to set clipboard content".to_string());
+/// Runs an input tool, failing with its stderr when it exits with an error so
+/// the sink does not report keys that were never sent
+fn run_input_tool<S: AsRef<OsStr>>(program: &str, args: &[S]) -> Result<(), String> {
+    let subcommand = args.first().map(|arg| arg.as_ref().to_string_lossy().into_owned()).unwrap_or_default();
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|e| format!("Failed to execute {} {}: {}", program, subcommand, e))?;
+    if output.status.success() {
+        return Ok(());
     }
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    Err(format!(
+        "{} {} failed: {}",
+        program,
+        subcommand,
+        if stderr.is_empty() { output.status.to_string() } else { stderr }
+    ))
+}
+
+/// Runs `ydotool key` (v0.1.x syntax uses key names like xdotool)
+fn ydotool_key(key: &str) -> Result<(), String> {
+    run_input_tool("ydotool", &["key", key])?;
+    log::debug!("ydotool key {} executed successfully", key);
+    Ok(())
+}
+
+/// Pastes text using wl-copy/arboard and ydotool (works on Wayland with GNOME)
+fn paste_text_ydotool(clipboard: &mut PasteClipboard, text: &str) -> Result<(), String> {
+    clipboard.set_text(text)?;
 
     // Wait for clipboard to be ready
     thread::sleep(Duration::from_millis(200));
 
-    // Simulate Ctrl+V using ydotool (v0.1.x syntax uses key names like xdotool)
-    let paste_result = Command::new("ydotool")
-        .args(["key", "ctrl+v"])
-        .output();
+    // Simulate Ctrl+V
+    ydotool_key("ctrl+v")
+}
 
-    match paste_result {
-        Ok(output) if output.status.success() => {
-            log::debug!("ydotool paste command executed successfully");
-        }
-        Ok(output) => {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            log::warn!("ydotool paste output - stdout: {}, stderr: {}", stdout, stderr);
-        }
-        Err(e) => {
-            return Err(format!("Failed to execute ydotool paste: {}", e));
+/// Arguments of `ydotool type`; `--` keeps text starting with `-` from being read as an option
+fn ydotool_type_args(text: &str, key_delay: Duration) -> Vec<String> {
+    vec![
+        "type".to_string(),
+        "--key-delay".to_string(),
+        key_delay.as_millis().to_string(),
+        "--".to_string(),
+        text.to_string(),
+    ]
+}
+
+/// Types text as key events using ydotool, pasting what it cannot type
+fn type_text_ydotool(clipboard: &mut PasteClipboard, text: &str, key_delay: Duration) -> Result<(), String> {
+    for chunk in split_typeable(text, is_ydotool_typeable) {
+        match chunk {
+            TypeChunk::Type(run) => run_input_tool("ydotool", &ydotool_type_args(&run, key_delay))?,
+            TypeChunk::Key(key) => ydotool_key(&key.ydotool_name())?,
+            TypeChunk::Paste(run) => {
+                log::debug!("ydotool cannot type {:?}, pasting it instead", run);
+                paste_text_ydotool(clipboard, &run)?;
+            }
         }
     }
+    Ok(())
+}
 
-    // Wait a bit before Enter
-    thread::sleep(Duration::from_millis(100));
+/// Types key actions using ydotool (works on Wayland with GNOME)
+fn type_actions_ydotool(actions: &[KeyAction], options: &TypingOptions) -> Result<(), String> {
+    log::info!("Using ydotool for Wayland input simulation ({:?} mode)", options.mode);
 
-    // Simulate Enter key
-    let enter_result = Command::new("ydotool")
-        .args(["key", "enter"])
-        .output();
+    let mut clipboard = PasteClipboard::new(options.preserve_clipboard);
 
-    match enter_result {
-        Ok(output) if output.status.success() => {
-            log::debug!("ydotool enter command executed successfully");
-        }
-        Ok(output) => {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            log::warn!("ydotool enter returned non-zero: {}", stderr);
+    let result = actions.iter().enumerate().try_for_each(|(i, action)| {
+        // Wait a bit between steps so the target app keeps up
+        if i > 0 {
+            thread::sleep(Duration::from_millis(100));
         }
-        Err(e) => {
-            return Err(format!("Failed to execute ydotool enter: {}", e));
+
+        match action {
+            KeyAction::Text(text) => match options.mode {
+                InputMode::Paste => paste_text_ydotool(&mut clipboard, text),
+                InputMode::Type => type_text_ydotool(&mut clipboard, text, options.key_delay),
+            },
+            KeyAction::Key(key) => ydotool_key(&key.ydotool_name()),
         }
-    }
+    });
+
+    clipboard.restore();
+    result?;
 
-    log::info!("Successfully simulated barcode input via ydotool: {}", barcode);
+    log::info!("Successfully simulated input via ydotool ({} actions)", actions.len());
     Ok(())
 }
 
-/// Type barcode using xdotool (works on X11)
-fn type_barcode_xdotool(barcode: &str) -> Result<(), String> {
-    log::info!("Using xdotool for X11 input simulation");
-
-    // Set clipboard using xclip or arboard
-    let mut clipboard = Clipboard::new()
-        .map_err(|e| format!("Failed to access clipboard: {}", e))?;
+/// Runs `xdotool key`
+fn xdotool_key(key: &str) -> Result<(), String> {
+    run_input_tool("xdotool", &["key", key])?;
+    log::debug!("xdotool key {} executed successfully", key);
+    Ok(())
+}
 
-    clipboard
-        .set_text(barcode)
-        .map_err(|e| format!("Failed to set clipboard: {}", e))?;
+/// Pastes text using arboard and xdotool
+fn paste_text_xdotool(clipboard: &mut PasteClipboard, text: &str) -> Result<(), String> {
+    clipboard.set_text(text)?;
 
     // Wait for clipboard
     thread::sleep(Duration::from_millis(100));
 
     // Simulate Ctrl+V
-    let paste_result = Command::new("xdotool")
-        .args(["key", "ctrl+v"])
-        .output();
+    xdotool_key("ctrl+v")
+}
 
-    match paste_result {
-        Ok(output) if output.status.success() => {
-            log::debug!("xdotool paste command executed successfully");
-        }
-        Err(e) => {
-            return Err(format!("Failed to execute xdotool paste: {}", e));
+/// Types text as key events using xdotool, pasting what it cannot type
+fn type_text_xdotool(clipboard: &mut PasteClipboard, text: &str, key_delay: Duration) -> Result<(), String> {
+    for chunk in split_typeable(text, is_xdotool_typeable) {
+        match chunk {
+            TypeChunk::Type(run) => {
+                let delay = key_delay.as_millis().to_string();
+                run_input_tool("xdotool", &["type", "--delay", delay.as_str(), "--", run.as_str()])?;
+            }
+            TypeChunk::Key(key) => xdotool_key(&key.xdotool_name())?,
+            TypeChunk::Paste(run) => {
+                log::debug!("xdotool cannot type {:?}, pasting it instead", run);
+                paste_text_xdotool(clipboard, &run)?;
+            }
         }
-        _ => {}
     }
+    Ok(())
+}
 
-    thread::sleep(Duration::from_millis(50));
+/// Types key actions using xdotool (works on X11)
+fn type_actions_xdotool(actions: &[KeyAction], options: &TypingOptions) -> Result<(), String> {
+    log::info!("Using xdotool for X11 input simulation ({:?} mode)", options.mode);
 
-    // Simulate Enter
-    let enter_result = Command::new("xdotool")
-        .args(["key", "Return"])
-        .output();
+    let mut clipboard = PasteClipboard::new(options.preserve_clipboard);
 
-    match enter_result {
-        Ok(output) if output.status.success() => {
-            log::debug!("xdotool enter command executed successfully");
+    let result = actions.iter().enumerate().try_for_each(|(i, action)| {
+        if i > 0 {
+            thread::sleep(Duration::from_millis(50));
         }
-        Err(e) => {
-            return Err(format!("Failed to execute xdotool enter: {}", e));
+
+        match action {
+            KeyAction::Text(text) => match options.mode {
+                InputMode::Paste => paste_text_xdotool(&mut clipboard, text),
+                InputMode::Type => type_text_xdotool(&mut clipboard, text, options.key_delay),
+            },
+            KeyAction::Key(key) => xdotool_key(&key.xdotool_name()),
         }
-        _ => {}
-    }
+    });
+
+    clipboard.restore();
+    result?;
 
-    log::info!("Successfully simulated barcode input via xdotool: {}", barcode);
+    log::info!("Successfully simulated input via xdotool ({} actions)", actions.len());
     Ok(())
 }
 
-/// Type barcode using enigo library (cross-platform, but may not work on all Wayland compositors)
-fn type_barcode_enigo(barcode: &str) -> Result<(), String> {
-    log::info!("Using enigo for input simulation");
-
-    // Save current clipboard content
-    let mut clipboard = Clipboard::new()
-        .map_err(|e| format!("Failed to access clipboard: {}", e))?;
-
-    let previous_content = clipboard.get_text().ok();
+fn enigo_key(key: SpecialKey) -> Key {
+    match key {
+        SpecialKey::Enter => Key::Return,
+        SpecialKey::Tab => Key::Tab,
+        SpecialKey::Escape => Key::Escape,
+        SpecialKey::Backspace => Key::Backspace,
+        SpecialKey::Delete => Key::Delete,
+        SpecialKey::Space => Key::Space,
+        SpecialKey::Up => Key::UpArrow,
+        SpecialKey::Down => Key::DownArrow,
+        SpecialKey::Left => Key::LeftArrow,
+        SpecialKey::Right => Key::RightArrow,
+        SpecialKey::Home => Key::Home,
+        SpecialKey::End => Key::End,
+        SpecialKey::PageUp => Key::PageUp,
+        SpecialKey::PageDown => Key::PageDown,
+        SpecialKey::F(n) => match n {
+            1 => Key::F1,
+            2 => Key::F2,
+            3 => Key::F3,
+            4 => Key::F4,
+            5 => Key::F5,
+            6 => Key::F6,
+            7 => Key::F7,
+            8 => Key::F8,
+            9 => Key::F9,
+            10 => Key::F10,
+            11 => Key::F11,
+            12 => Key::F12,
+            13 => Key::F13,
+            14 => Key::F14,
+            15 => Key::F15,
+            16 => Key::F16,
+            17 => Key::F17,
+            18 => Key::F18,
+            19 => Key::F19,
+            _ => Key::F20,
+        },
+    }
+}
 
-    // Set barcode to clipboard
-    clipboard
-        .set_text(barcode)
-        .map_err(|e| format!("Failed to set clipboard: {}", e))?;
+/// Pastes text using the clipboard and Ctrl+V pressed through enigo
+fn paste_text_enigo(enigo: &mut Enigo, clipboard: &mut PasteClipboard, text: &str) -> Result<(), String> {
+    clipboard.set_text(text)?;
 
     // Delay to ensure clipboard is ready
     thread::sleep(Duration::from_millis(150));
 
-    let mut enigo = Enigo::new(&Settings::default())
-        .map_err(|e| format!("Failed to initialize keyboard simulator: {}", e))?;
-
     // Paste with Ctrl+V
     enigo.key(Key::Control, Direction::Press)
         .map_err(|e| format!("Failed to press Ctrl: {}", e))?;
@@ -189,58 +507,212 @@ fn type_barcode_enigo(barcode: &str) -> Result<(), String> {
         .map_err(|e| format!("Failed to press V: {}", e))?;
     thread::sleep(Duration::from_millis(30));
     enigo.key(Key::Control, Direction::Release)
-        .map_err(|e| format!("Failed to release Ctrl: {}", e))?;
+        .map_err(|e| format!("Failed to release Ctrl: {}", e))
+}
+
+/// Types text one character at a time with enigo, pasting characters it fails to type
+fn type_text_enigo(enigo: &mut Enigo, clipboard: &mut PasteClipboard, text: &str, key_delay: Duration) -> Result<(), String> {
+    for (i, c) in text.chars().enumerate() {
+        if i > 0 {
+            thread::sleep(key_delay);
+        }
+
+        let result = match c {
+            '\n' | '\r' => enigo.key(Key::Return, Direction::Click),
+            '\t' => enigo.key(Key::Tab, Direction::Click),
+            // enigo picks the keycode and modifiers (Shift, AltGr) for the character
+            c => enigo.text(&c.to_string()),
+        };
 
-    // Delay before pressing Enter
+        if let Err(e) = result {
+            log::debug!("enigo cannot type {:?} ({}), pasting it instead", c, e);
+            paste_text_enigo(enigo, clipboard, &c.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+/// Types key actions using enigo library (cross-platform, but may not work on all Wayland compositors)
+fn type_actions_enigo(actions: &[KeyAction], options: &TypingOptions) -> Result<(), String> {
+    log::info!("Using enigo for input simulation ({:?} mode)", options.mode);
+
+    let mut enigo = Enigo::new(&Settings::default())
+        .map_err(|e| format!("Failed to initialize keyboard simulator: {}", e))?;
+    let mut clipboard = PasteClipboard::new(options.preserve_clipboard);
+
+    let result = actions.iter().enumerate().try_for_each(|(i, action)| {
+        // Delay between steps
+        if i > 0 {
+            thread::sleep(Duration::from_millis(100));
+        }
+
+        match action {
+            KeyAction::Text(text) => match options.mode {
+                InputMode::Paste => paste_text_enigo(&mut enigo, &mut clipboard, text),
+                InputMode::Type => type_text_enigo(&mut enigo, &mut clipboard, text, options.key_delay),
+            },
+            KeyAction::Key(key) => enigo.key(enigo_key(*key), Direction::Click)
+                .map_err(|e| format!("Failed to press {:?}: {}", key, e)),
+        }
+    });
+
+    clipboard.restore();
+    result?;
+
+    log::info!("Successfully simulated input via enigo ({} actions)", actions.len());
+    Ok(())
+}
+
+/// Pastes text using the clipboard and Ctrl+V on the virtual keyboard
+#[cfg(target_os = "linux")]
+fn paste_text_uinput(keyboard: &VirtualKeyboard, clipboard: &mut PasteClipboard, text: &str) -> Result<(), String> {
+    clipboard.set_text(text)?;
+
+    // Wait for clipboard
     thread::sleep(Duration::from_millis(100));
 
-    // Press Enter key
-    enigo.key(Key::Return, Direction::Click)
-        .map_err(|e| format!("Failed to press Enter: {}", e))?;
+    keyboard.paste_shortcut()
+}
 
-    // Restore previous clipboard content after a short delay
-    thread::sleep(Duration::from_millis(150));
-    if let Some(prev) = previous_content {
-        let _ = clipboard.set_text(&prev);
+/// Types text on the virtual keyboard, pasting what the keyboard layout cannot produce
+#[cfg(target_os = "linux")]
+fn type_text_uinput(keyboard: &VirtualKeyboard, clipboard: &mut PasteClipboard, text: &str, key_delay: Duration) -> Result<(), String> {
+    for chunk in split_typeable(text, |c| keyboard.can_type(c)) {
+        match chunk {
+            TypeChunk::Type(run) => {
+                for c in run.chars() {
+                    keyboard.type_char(c)?;
+                    thread::sleep(key_delay);
+                }
+            }
+            TypeChunk::Key(key) => keyboard.press_key(key)?,
+            TypeChunk::Paste(run) => {
+                log::debug!("Keyboard layout cannot type {:?}, pasting it instead", run);
+                paste_text_uinput(keyboard, clipboard, &run)?;
+            }
+        }
     }
+    Ok(())
+}
+
+/// Types key actions on a uinput virtual keyboard (X11 and Wayland, no external tools)
+#[cfg(target_os = "linux")]
+fn type_actions_uinput(actions: &[KeyAction], options: &TypingOptions) -> Result<(), String> {
+    log::info!("Using uinput virtual keyboard for input simulation ({:?} mode)", options.mode);
+
+    let mut clipboard = PasteClipboard::new(options.preserve_clipboard);
+
+    let result = uinput::with_keyboard_layout(options.keyboard_layout.as_deref(), |keyboard| {
+        actions.iter().enumerate().try_for_each(|(i, action)| {
+            if i > 0 {
+                thread::sleep(Duration::from_millis(20));
+            }
+
+            match action {
+                KeyAction::Text(text) => match options.mode {
+                    InputMode::Paste => paste_text_uinput(keyboard, &mut clipboard, text),
+                    InputMode::Type => type_text_uinput(keyboard, &mut clipboard, text, options.key_delay),
+                },
+                KeyAction::Key(key) => keyboard.press_key(*key),
+            }
+        })
+    });
+
+    clipboard.restore();
+    result?;
 
-    log::info!("Successfully simulated barcode input via enigo: {}", barcode);
+    log::info!("Successfully simulated input via uinput ({} actions)", actions.len());
     Ok(())
 }
 
-/// Simulates typing a barcode followed by Enter key, just like a physical barcode scanner.
-/// Uses clipboard paste (Ctrl+V) for instant input, then presses Enter.
+/// Executes a key action sequence (usually rendered from a keystroke template).
+/// Text is pasted via the clipboard (Ctrl+V) or typed key by key depending on the mode;
+/// special keys are pressed individually.
 /// Automatically selects the best method based on the environment.
-pub fn type_barcode(barcode: &str) -> Result<(), String> {
-    log::debug!("Starting barcode input simulation for: {}", barcode);
-    log::debug!("Environment - Wayland: {}, ydotool: {}, xdotool: {}",
-        is_wayland(), is_ydotool_available(), is_xdotool_available());
-
-    // On Wayland (especially GNOME), try ydotool first
-    if is_wayland() {
-        if is_ydotool_available() {
-            log::info!("Wayland detected, using ydotool");
-            return type_barcode_ydotool(barcode);
-        } else {
-            log::warn!("Wayland detected but ydotool not available. Trying enigo as fallback...");
-            log::warn!("For best Wayland support, install ydotool: sudo apt install ydotool");
-            // Try enigo anyway, it might work with some compositors
-            let enigo_result = type_barcode_enigo(barcode);
-            if enigo_result.is_ok() {
-                return enigo_result;
-            }
-            log::error!("enigo failed on Wayland. Please install ydotool for Wayland support.");
-            return Err("Input simulation not available on Wayland without ydotool. Install with: sudo apt install ydotool".to_string());
+pub fn type_actions(actions: &[KeyAction], options: &TypingOptions) -> Result<(), String> {
+    log::debug!("Starting input simulation for: {:?}", actions);
+
+    let backend = options.backend.unwrap_or_else(|| environment().backend);
+    match backend {
+        #[cfg(target_os = "linux")]
+        InputBackend::Uinput => type_actions_uinput(actions, options),
+        #[cfg(not(target_os = "linux"))]
+        InputBackend::Uinput => type_actions_enigo(actions, options),
+        InputBackend::Ydotool => type_actions_ydotool(actions, options),
+        InputBackend::Xdotool => type_actions_xdotool(actions, options),
+        InputBackend::Enigo if is_wayland() => {
+            type_actions_enigo(actions, options).map_err(|e| {
+                log::error!("enigo failed on Wayland: {}", e);
+                "Input simulation not available on Wayland without ydotool. Install with: sudo apt install ydotool".to_string()
+            })
         }
+        InputBackend::Enigo => type_actions_enigo(actions, options),
     }
+}
 
-    // On X11, try xdotool first, then enigo
-    if is_xdotool_available() {
-        log::info!("X11 detected, using xdotool");
-        return type_barcode_xdotool(barcode);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_typeable() {
+        let chunks = split_typeable("AB-1\u{1d}é\n", is_ydotool_typeable);
+        assert_eq!(chunks, vec![
+            TypeChunk::Type("AB-1".to_string()),
+            TypeChunk::Paste("\u{1d}é".to_string()),
+            TypeChunk::Key(SpecialKey::Enter),
+        ]);
+
+        // xdotool types Unicode but not control characters
+        let chunks = split_typeable("é\u{1d}", is_xdotool_typeable);
+        assert_eq!(chunks, vec![
+            TypeChunk::Type("é".to_string()),
+            TypeChunk::Paste("\u{1d}".to_string()),
+        ]);
     }
 
-    // Fallback to enigo (works on X11 and Windows/macOS)
-    log::info!("Using enigo as default input simulator");
-    type_barcode_enigo(barcode)
+    #[test]
+    fn test_failing_input_tools_report_their_error() {
+        assert!(run_input_tool("sh", &["-c", "exit 0"]).is_ok());
+        let error = run_input_tool("sh", &["-c", "echo 'failed to connect to socket' >&2; exit 1"]).unwrap_err();
+        assert_eq!(error, "sh -c failed: failed to connect to socket");
+        assert!(run_input_tool("scanlink-no-such-tool", &["key"]).unwrap_err().starts_with("Failed to execute"));
+    }
+
+    #[test]
+    fn test_ydotool_type_args_end_options() {
+        let args = ydotool_type_args("-5 units", Duration::from_millis(12));
+        assert_eq!(args, ["type", "--key-delay", "12", "--", "-5 units"]);
+    }
+
+    #[test]
+    fn test_select_backend() {
+        let mut env = InputEnvironment {
+            session_type: Some("wayland".to_string()),
+            wayland: true,
+            ydotool: true,
+            xdotool: true,
+            wl_clipboard: true,
+            xclip: false,
+            uinput: true,
+            uinput_problem: None,
+            backend: InputBackend::Enigo,
+        };
+        assert_eq!(select_backend(&env), InputBackend::Uinput);
+
+        env.uinput = false;
+        assert_eq!(select_backend(&env), InputBackend::Ydotool);
+
+        env.ydotool = false;
+        assert_eq!(select_backend(&env), InputBackend::Enigo, "xdotool does not work on Wayland");
+
+        env.wayland = false;
+        assert_eq!(select_backend(&env), InputBackend::Xdotool);
+
+        env.uinput = true;
+        assert_eq!(select_backend(&env), InputBackend::Xdotool, "xdotool follows the live X11 layout");
+
+        env.xdotool = false;
+        assert_eq!(select_backend(&env), InputBackend::Uinput);
+    }
 }
@@ -1,10 +1,32 @@
+mod clipboard;
+pub mod daemon;
+mod dedup;
+mod gs1;
+mod history;
 mod keyboard;
 mod mdns_service;
 mod models;
+mod output;
+mod pipeline;
+mod policy;
+mod profile;
+mod protocol;
+mod provisioning;
 mod qr_service;
+mod rate_limit;
 mod security;
+#[cfg(target_os = "linux")]
+mod serial;
+mod session;
 mod storage;
+mod template;
+mod tls;
+mod transform;
+#[cfg(target_os = "linux")]
+mod uinput;
+mod vault;
 mod websocket;
+mod window;
 
 use std::sync::{Arc, Mutex};
 use tauri::{AppHandle, Emitter, State, Manager, WindowEvent};
@@ -12,11 +34,18 @@ use tauri::menu::{Menu, MenuItem, PredefinedMenuItem};
 use tauri::tray::{TrayIconBuilder, TrayIconEvent};
 use tokio::sync::mpsc;
 use tokio::task::JoinHandle;
-use models::{BarcodeMessage, ConnectionInfo, QRCodeData, ServerState, DeviceInfo, AppSettings};
-use qr_service::{generate_qr_code, generate_token, get_local_ip};
-use websocket::WebSocketServer;
+use models::{BarcodeMessage, ConfigRecovery, ConnectionInfo, DeviceCommand, QRCodeData, ServerState, DeviceInfo, AppSettings, KeystrokeTemplates};
+use qr_service::{advertised_ip, generate_qr_code};
+use websocket::{NetworkSettings, ServerEvent, WebSocketServer};
 use storage::AppConfig;
-use security::AuthorizedDevice;
+use security::{AuthorizedDevice, TokenRotation};
+use history::{HistoryPage, HistoryQuery};
+use output::SinkConfig;
+use transform::{RuleTestResult, TransformRule};
+use profile::OutputProfiles;
+use policy::PolicyInfo;
+use provisioning::{Bundle, BundleImportResult};
+use window::FocusedWindow;
 use mdns_service::MdnsService;
 use serde::Serialize;
 
@@ -26,6 +55,10 @@ struct BarcodeEvent {
     timestamp: String,
     device_id: String,
     device_name: Option<String>,
+    barcode_type: Option<String>,
+    history_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gs1: Option<gs1::Gs1Data>,
 }
 
 struct AppState {
@@ -34,6 +67,8 @@ struct AppState {
     server_task: Arc<Mutex<Option<JoinHandle<()>>>>,
     starting: Arc<Mutex<bool>>,  // Prevents concurrent start_server calls
     config: Arc<Mutex<AppConfig>>,
+    /// Corrupt config quarantined at startup, until the user restores or dismisses it
+    config_recovery: Arc<Mutex<Option<ConfigRecovery>>>,
     #[allow(dead_code)] // Reserved for future mDNS discovery feature
     mdns: Arc<Mutex<Option<MdnsService>>>,
 }
@@ -58,19 +93,30 @@ async fn start_server(
         // Wait a bit and return current QR data if available
         tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
 
-        let connection_info_clone = state.connection_info.lock().unwrap().clone();
-        if let Some(connection_info) = connection_info_clone {
-            let qr_data = generate_qr_code(&connection_info)?;
+        if let Some(qr_data) = current_qr_data(&state)? {
             return Ok(qr_data);
         }
         return Err("Server is already starting".to_string());
     }
 
-    // Helper to release the starting lock
-    let release_lock = |state: &State<'_, AppState>| {
-        let mut starting = state.starting.lock().unwrap();
-        *starting = false;
-    };
+    let result = launch_server(&state, &app_handle).await;
+
+    // Release the starting lock, also when the start failed
+    *state.starting.lock().unwrap() = false;
+
+    match &result {
+        Ok(_) => log::info!("=== START_SERVER COMPLETED SUCCESSFULLY ==="),
+        Err(e) => log::error!("Failed to start server: {}", e),
+    }
+    result
+}
+
+/// Stops any running server and starts a new one; `start_server` holds the starting lock
+async fn launch_server(state: &AppState, app_handle: &AppHandle) -> Result<QRCodeData, String> {
+    // Devices paired now could not be saved and the defaults would hand out a new token
+    if storage::is_locked() {
+        return Err("The config is locked, unlock it before starting the server".to_string());
+    }
 
     // Stop existing server if running
     let should_wait = {
@@ -97,61 +143,62 @@ async fn start_server(
         tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
     }
 
-    // Generate token and get IP
-    let token = generate_token();
-    let ip = get_local_ip()?;
-    let port = 47592; // Porta incomum para evitar conflitos
-
-    let connection_info = ConnectionInfo {
-        ip: ip.clone(),
-        port,
-        token: token.clone(),
-        secret_key: None,  // Secret key is not exposed in QR code for security
+    // Reuse the persisted master token (rotating it if due) and make sure the
+    // TLS certificate exists before advertising both in the QR code
+    let (token, cert_fingerprint) = {
+        let mut config = state.config.lock().unwrap();
+        let token_changed = config.ensure_master_token(chrono::Utc::now().timestamp());
+        if config.ensure_tls_identity()? || token_changed {
+            if let Err(e) = storage::save(&config) {
+                log::error!("Failed to save config: {}", e);
+            }
+        }
+        (
+            config.master_token.clone().unwrap_or_default(),
+            config.active_tls_identity().map(|identity| identity.fingerprint.clone()),
+        )
     };
 
-    // Generate QR code
-    let qr_data = generate_qr_code(&connection_info)?;
-
-    // Store connection info
-    *state.connection_info.lock().unwrap() = Some(connection_info.clone());
+    // Create WebSocket server sharing the app config and bind it, so the QR
+    // code carries the port actually in use
+    let ws_server = WebSocketServer::new(state.config.clone());
+    let (barcode_tx, mut barcode_rx) = mpsc::unbounded_channel::<BarcodeMessage>();
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel::<ServerEvent>();
+    let bound = ws_server.bind(barcode_tx, event_tx)?;
+    let addr = bound.addr;
 
-    // Load config for WebSocket server
-    let config = state.config.lock().unwrap().clone();
+    let ip = advertised_ip(addr)?;
+    let connection_info = ConnectionInfo::new(ip.clone(), addr.port(), token, cert_fingerprint);
 
-    // Create WebSocket server with config
-    let ws_server = WebSocketServer::new(token.clone(), port, config);
+    // Store connection info and generate QR code
+    *state.connection_info.lock().unwrap() = Some(connection_info);
+    let qr_data = current_qr_data(state)?.ok_or("Connection info is missing")?;
 
     // Store server instance
-    *state.server.lock().unwrap() = Some(ws_server.clone());
-
-    // Create channel for barcode messages
-    let (barcode_tx, mut barcode_rx) = mpsc::unbounded_channel::<BarcodeMessage>();
+    *state.server.lock().unwrap() = Some(ws_server);
 
     // Spawn task to handle barcode messages and emit to frontend
     let app_handle_clone = app_handle.clone();
+    let config = state.config.clone();
     tokio::spawn(async move {
         log::debug!("Barcode handler task started");
         while let Some(barcode_msg) = barcode_rx.recv().await {
-            log::info!("Received barcode: {} from device: {}", barcode_msg.barcode, barcode_msg.device_id);
-
-            // Simulate keyboard typing (like a physical barcode scanner)
-            let barcode_for_typing = barcode_msg.barcode.clone();
-            tokio::task::spawn_blocking(move || {
-                if let Err(e) = keyboard::type_barcode(&barcode_for_typing) {
-                    log::error!("Failed to simulate keyboard input: {}", e);
-                }
-            });
+            let record = pipeline::process_barcode(&barcode_msg, &config).await;
 
             // Convert timestamp to ISO 8601 string
             let timestamp_str = chrono::DateTime::from_timestamp(barcode_msg.timestamp, 0)
                 .map(|dt| dt.to_rfc3339())
                 .unwrap_or_else(|| chrono::Utc::now().to_rfc3339());
 
+            // Report the barcode as it was output, after transform rules
             let event = BarcodeEvent {
-                barcode: barcode_msg.barcode,
+                barcode: record.barcode,
                 timestamp: timestamp_str,
                 device_id: barcode_msg.device_id,
                 device_name: barcode_msg.device_name,
+                barcode_type: barcode_msg.barcode_type,
+                history_id: record.id,
+                gs1: record.gs1,
             };
 
             if let Err(e) = app_handle_clone.emit("barcode-received", event) {
@@ -160,31 +207,74 @@ async fn start_server(
         }
     });
 
-    // Start WebSocket server in background
-    let server_handle = tokio::spawn(async move {
-        log::info!("WebSocket server task started with token: {} on port: {}", ws_server.token, ws_server.port);
-        if let Err(e) = ws_server.start(barcode_tx).await {
-            log::error!("WebSocket server error: {}", e);
+    // Forward other server events to the frontend
+    let app_handle_clone = app_handle.clone();
+    tokio::spawn(async move {
+        while let Some(event) = event_rx.recv().await {
+            let result = match event {
+                ServerEvent::CommandAck(ack) => app_handle_clone.emit("device-command-ack", ack),
+                ServerEvent::Lockout(lockout) => app_handle_clone.emit("client-locked-out", lockout),
+                ServerEvent::MasterTokenRotated => emit_qr_update(&app_handle_clone, &app_handle_clone.state::<AppState>()),
+            };
+            if let Err(e) = result {
+                log::error!("Failed to emit server event: {}", e);
+            }
         }
+    });
+
+    // Serve connections in background
+    let server_handle = tokio::spawn(async move {
+        log::info!("WebSocket server task started on {}", addr);
+        bound.run().await;
         log::info!("WebSocket server task ended");
     });
 
     // Store the server task handle
     *state.server_task.lock().unwrap() = Some(server_handle);
 
-    log::info!("Server started on {}:{}", ip, port);
+    log::info!("Server started on {}:{}", ip, addr.port());
 
     // Emit event to frontend with QR data (keeps frontend in sync)
     if let Err(e) = app_handle.emit("server-started", &qr_data) {
         log::error!("Failed to emit server-started event: {}", e);
     }
 
-    // Release the starting lock
-    release_lock(&state);
+    Ok(qr_data)
+}
 
-    log::info!("=== START_SERVER COMPLETED SUCCESSFULLY ===");
+/// Pairing QR code of the running server, with the current master token and its validity
+fn current_qr_data(state: &AppState) -> Result<Option<QRCodeData>, String> {
+    let (token, validity) = {
+        let config = state.config.lock().unwrap();
+        (config.master_token.clone(), config.master_token_validity())
+    };
 
-    Ok(qr_data)
+    let mut connection_info = state.connection_info.lock().unwrap();
+    let Some(connection_info) = connection_info.as_mut() else {
+        return Ok(None);
+    };
+    if let Some(token) = token {
+        connection_info.token = token;
+    }
+
+    let mut qr_data = generate_qr_code(connection_info)?;
+    qr_data.validity = Some(validity);
+    Ok(Some(qr_data))
+}
+
+/// Sends the frontend a fresh QR code after the master token or its policy changed
+fn emit_qr_update(app_handle: &AppHandle, state: &AppState) -> tauri::Result<()> {
+    if state.server.lock().unwrap().is_none() {
+        return Ok(());
+    }
+    match current_qr_data(state) {
+        Ok(Some(qr_data)) => app_handle.emit("qr-updated", qr_data),
+        Ok(None) => Ok(()),
+        Err(e) => {
+            log::error!("Failed to generate QR code: {}", e);
+            Ok(())
+        }
+    }
 }
 
 #[tauri::command]
@@ -235,27 +325,35 @@ async fn get_server_state(state: State<'_, AppState>) -> Result<ServerState, Str
 
 #[tauri::command]
 async fn get_current_qr_data(state: State<'_, AppState>) -> Result<Option<QRCodeData>, String> {
-    let server_lock = state.server.lock().unwrap();
-    let connection_info_lock = state.connection_info.lock().unwrap();
-
     // Only return QR data if server is running AND we have connection info
-    if server_lock.is_some() {
-        if let Some(connection_info) = connection_info_lock.as_ref() {
-            let qr_data = generate_qr_code(connection_info)?;
-            return Ok(Some(qr_data));
-        }
+    if state.server.lock().unwrap().is_none() {
+        return Ok(None);
     }
-
-    Ok(None)
+    current_qr_data(&state)
 }
 
 #[tauri::command]
 async fn get_authorized_devices(state: State<'_, AppState>) -> Result<Vec<AuthorizedDevice>, String> {
     let config = state.config.lock().unwrap();
-    let devices: Vec<AuthorizedDevice> = config.authorized_devices.values().cloned().collect();
+    let devices: Vec<AuthorizedDevice> = config.authorized_devices
+        .values()
+        .map(|d| d.without_secrets())
+        .collect();
     Ok(devices)
 }
 
+/// Paired devices still on the shared key; they move to their own key when they
+/// reconnect with app protocol 3 or newer, older apps have to pair again
+#[tauri::command]
+async fn get_legacy_key_devices(state: State<'_, AppState>) -> Result<Vec<AuthorizedDevice>, String> {
+    let config = state.config.lock().unwrap();
+    Ok(config.authorized_devices
+        .values()
+        .filter(|d| config.uses_legacy_key(&d.device_id))
+        .map(|d| d.without_secrets())
+        .collect())
+}
+
 #[tauri::command]
 async fn get_connected_devices(state: State<'_, AppState>) -> Result<Vec<DeviceInfo>, String> {
     let server_lock = state.server.lock().unwrap();
@@ -268,44 +366,108 @@ async fn get_connected_devices(state: State<'_, AppState>) -> Result<Vec<DeviceI
 
 #[tauri::command]
 async fn revoke_device(state: State<'_, AppState>, device_id: String) -> Result<(), String> {
-    let mut config = state.config.lock().unwrap();
-    config.remove_device(&device_id);
-    storage::save(&config).map_err(|e| e.to_string())?;
+    {
+        // Removing the device also destroys its key, so its tokens stop validating
+        let mut config = state.config.lock().unwrap();
+        config.remove_device(&device_id);
+        storage::save(&config).map_err(|e| e.to_string())?;
+    }
+
+    if let Some(server) = state.server.lock().unwrap().as_ref() {
+        server.disconnect_device(&device_id);
+    }
+
     log::info!("Device {} revoked", device_id);
     Ok(())
 }
 
 #[tauri::command]
 async fn revoke_all_devices(state: State<'_, AppState>) -> Result<(), String> {
-    let mut config = state.config.lock().unwrap();
-    config.revoke_all_devices();
-    storage::save(&config).map_err(|e| e.to_string())?;
+    let device_ids: Vec<String> = {
+        let mut config = state.config.lock().unwrap();
+        let device_ids = config.authorized_devices.keys().cloned().collect();
+        config.revoke_all_devices();
+        storage::save(&config).map_err(|e| e.to_string())?;
+        device_ids
+    };
+
+    if let Some(server) = state.server.lock().unwrap().as_ref() {
+        for device_id in &device_ids {
+            server.disconnect_device(device_id);
+        }
+    }
+
     log::info!("All devices revoked");
     Ok(())
 }
 
+/// Pushes a command to a connected phone; acks arrive as "device-command-ack" events
 #[tauri::command]
-async fn regenerate_token(state: State<'_, AppState>, app_handle: AppHandle) -> Result<QRCodeData, String> {
-    // This generates a new master token, invalidating all existing pairing tokens
-    // Existing paired devices will still work (they use auth_token, not master_token)
+async fn send_device_command(
+    state: State<'_, AppState>,
+    device_id: String,
+    command: DeviceCommand,
+) -> Result<String, String> {
+    let server = state.server.lock().unwrap().clone();
+    match server {
+        Some(server) => server.send_command(&device_id, command),
+        None => Err("Server is not running".to_string()),
+    }
+}
 
-    // First stop the server if running
-    {
-        let mut server_lock = state.server.lock().unwrap();
-        let mut task_lock = state.server_task.lock().unwrap();
+/// Replaces the master token, invalidating printed QR codes.
+/// Paired devices keep working (they use auth_token, not master_token).
+#[tauri::command]
+async fn regenerate_token(state: State<'_, AppState>, app_handle: AppHandle) -> Result<QRCodeData, String> {
+    update_config(&state, |config| config.rotate_master_token(chrono::Utc::now().timestamp()))?;
 
-        if let Some(server) = server_lock.take() {
-            server.shutdown();
-        }
-        if let Some(task) = task_lock.take() {
-            task.abort();
-        }
+    // A running server reads the token from the config, only the QR code changes
+    let running = state.server.lock().unwrap().is_some();
+    if !running {
+        return start_server(state, app_handle).await;
+    }
+    let qr_data = current_qr_data(&state)?.ok_or("Connection info is missing")?;
+    if let Err(e) = app_handle.emit("qr-updated", &qr_data) {
+        log::error!("Failed to emit qr-updated event: {}", e);
     }
+    Ok(qr_data)
+}
 
-    tokio::time::sleep(tokio::time::Duration::from_millis(300)).await;
+#[tauri::command]
+async fn get_require_encryption(state: State<'_, AppState>) -> Result<bool, String> {
+    Ok(state.config.lock().unwrap().require_encryption)
+}
 
-    // Start server with new token
-    start_server(state, app_handle).await
+/// Rejects plaintext scans from now on, including from devices that cannot encrypt
+#[tauri::command]
+async fn set_require_encryption(state: State<'_, AppState>, required: bool) -> Result<(), String> {
+    update_config(&state, |config| config.require_encryption = required)
+}
+
+#[tauri::command]
+async fn get_token_rotation(state: State<'_, AppState>) -> Result<TokenRotation, String> {
+    Ok(state.config.lock().unwrap().token_rotation)
+}
+
+/// Changes when the master token rotates; applies right away to the current token
+#[tauri::command]
+async fn set_token_rotation(
+    state: State<'_, AppState>,
+    app_handle: AppHandle,
+    rotation: TokenRotation,
+) -> Result<(), String> {
+    if matches!(rotation, TokenRotation::AfterPairings { count: 0 }) {
+        return Err("The number of pairings must be at least 1".to_string());
+    }
+    update_config(&state, |config| {
+        config.token_rotation = rotation;
+        config.ensure_master_token(chrono::Utc::now().timestamp());
+    })?;
+
+    if let Err(e) = emit_qr_update(&app_handle, &state) {
+        log::error!("Failed to emit qr-updated event: {}", e);
+    }
+    Ok(())
 }
 
 #[tauri::command]
@@ -315,22 +477,179 @@ async fn get_settings(state: State<'_, AppState>) -> Result<AppSettings, String>
         auto_start: config.auto_start,
         minimize_to_tray: config.minimize_to_tray,
         start_minimized: config.start_minimized,
+        input_mode: config.input_mode,
+        type_delay_ms: config.type_delay_ms,
+        preserve_clipboard: config.preserve_clipboard,
     })
 }
 
+#[tauri::command]
+async fn get_output_sinks(state: State<'_, AppState>) -> Result<Vec<SinkConfig>, String> {
+    Ok(state.config.lock().unwrap().output_sinks.clone())
+}
+
+/// Applies a settings change and saves it, unless it touches a setting locked by policy
+fn update_config(state: &AppState, change: impl FnOnce(&mut AppConfig)) -> Result<(), String> {
+    let mut config = state.config.lock().unwrap();
+    let mut updated = config.clone();
+    change(&mut updated);
+    policy::current().check(&updated)?;
+    storage::save(&updated)?;
+    *config = updated;
+    Ok(())
+}
+
+/// Replaces the output sinks; takes effect from the next scan
+#[tauri::command]
+async fn set_output_sinks(state: State<'_, AppState>, sinks: Vec<SinkConfig>) -> Result<(), String> {
+    update_config(&state, |config| config.output_sinks = sinks.clone())?;
+    output::prepare_sinks(&sinks);
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_transform_rules(state: State<'_, AppState>) -> Result<Vec<TransformRule>, String> {
+    Ok(state.config.lock().unwrap().transform_rules.clone())
+}
+
+/// Replaces the transform rules after validating all of them; takes effect from the next scan
+#[tauri::command]
+async fn set_transform_rules(state: State<'_, AppState>, rules: Vec<TransformRule>) -> Result<(), String> {
+    for rule in &rules {
+        rule.compile()?;
+    }
+
+    update_config(&state, |config| config.transform_rules = rules)
+}
+
+/// Runs one rule against a sample barcode without saving it
+#[tauri::command]
+async fn test_transform_rule(
+    rule: TransformRule,
+    barcode: String,
+    device_id: Option<String>,
+    barcode_type: Option<String>,
+) -> Result<RuleTestResult, String> {
+    transform::test_rule(&rule, &barcode, device_id.as_deref(), barcode_type.as_deref())
+}
+
+#[tauri::command]
+async fn get_output_profiles(state: State<'_, AppState>) -> Result<OutputProfiles, String> {
+    let config = state.config.lock().unwrap();
+    Ok(OutputProfiles {
+        profiles: config.output_profiles.clone(),
+        block_unmatched_windows: config.block_unmatched_windows,
+    })
+}
+
+/// Replaces the per-application output profiles after validating them
+#[tauri::command]
+async fn set_output_profiles(state: State<'_, AppState>, profiles: OutputProfiles) -> Result<(), String> {
+    for profile in &profiles.profiles {
+        profile.validate()?;
+    }
+
+    update_config(&state, |config| {
+        config.output_profiles = profiles.profiles;
+        config.block_unmatched_windows = profiles.block_unmatched_windows;
+    })
+}
+
+/// Window that currently has focus, to help write profile patterns (None on Wayland)
+#[tauri::command]
+async fn get_focused_window() -> Result<Option<FocusedWindow>, String> {
+    tokio::task::spawn_blocking(window::focused_window)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Input backend report for support; `reprobe` detects tools again after installing them
+#[tauri::command]
+async fn get_input_diagnostics(reprobe: Option<bool>) -> Result<keyboard::InputDiagnostics, String> {
+    let reprobe = reprobe.unwrap_or(false);
+    tokio::task::spawn_blocking(move || keyboard::diagnostics(reprobe))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_keystroke_templates(state: State<'_, AppState>) -> Result<KeystrokeTemplates, String> {
+    let config = state.config.lock().unwrap();
+    Ok(KeystrokeTemplates {
+        template: config.keystroke_template.clone(),
+        device_templates: config.device_keystroke_templates.clone(),
+    })
+}
+
+/// Sets the global template (no device_id) or a device override.
+/// Clearing a device override (no template) falls back to the global one.
+#[tauri::command]
+async fn set_keystroke_template(
+    state: State<'_, AppState>,
+    device_id: Option<String>,
+    template: Option<String>,
+) -> Result<(), String> {
+    if let Some(ref source) = template {
+        template::Template::parse(source)?;
+    }
+
+    update_config(&state, |config| match (device_id, template) {
+        (Some(device_id), Some(template)) => {
+            config.device_keystroke_templates.insert(device_id, template);
+        }
+        (Some(device_id), None) => {
+            config.device_keystroke_templates.remove(&device_id);
+        }
+        (None, template) => {
+            config.keystroke_template = template.unwrap_or_else(|| template::DEFAULT_TEMPLATE.to_string());
+        }
+    })
+}
+
+#[tauri::command]
+async fn get_network_settings(state: State<'_, AppState>) -> Result<NetworkSettings, String> {
+    Ok(state.config.lock().unwrap().network_settings())
+}
+
+#[tauri::command]
+async fn get_input_overrides(state: State<'_, AppState>) -> Result<keyboard::InputOverrides, String> {
+    Ok(state.config.lock().unwrap().input_overrides())
+}
+
+/// Forces an input simulator and/or keyboard layout; applies from the next scan
+#[tauri::command]
+async fn set_input_overrides(state: State<'_, AppState>, overrides: keyboard::InputOverrides) -> Result<(), String> {
+    let keyboard_layout = overrides.keyboard_layout.map(|layout| layout.trim().to_string()).filter(|layout| !layout.is_empty());
+    if let Some(layout) = &keyboard_layout {
+        if !layout.chars().all(|c| c.is_ascii_alphanumeric() || "()_-".contains(c)) {
+            return Err(format!("'{}' is not an XKB layout name, e.g. de or de(nodeadkeys)", layout));
+        }
+    }
+    let overrides = keyboard::InputOverrides { keyboard_layout, ..overrides };
+    update_config(&state, |config| config.set_input_overrides(overrides))
+}
+
+/// Changes where the server listens; takes effect the next time it starts
+#[tauri::command]
+async fn set_network_settings(state: State<'_, AppState>, settings: NetworkSettings) -> Result<(), String> {
+    settings.validate()?;
+    update_config(&state, |config| config.set_network_settings(settings))
+}
+
 #[tauri::command]
 async fn update_settings(
     state: State<'_, AppState>,
     app_handle: AppHandle,
     settings: AppSettings,
 ) -> Result<(), String> {
-    {
-        let mut config = state.config.lock().unwrap();
+    update_config(&state, |config| {
         config.auto_start = settings.auto_start;
         config.minimize_to_tray = settings.minimize_to_tray;
         config.start_minimized = settings.start_minimized;
-        storage::save(&config).map_err(|e| e.to_string())?;
-    }
+        config.input_mode = settings.input_mode;
+        config.type_delay_ms = settings.type_delay_ms;
+        config.preserve_clipboard = settings.preserve_clipboard;
+    })?;
 
     // Update autostart
     #[cfg(desktop)]
@@ -349,10 +668,124 @@ async fn update_settings(
     Ok(())
 }
 
+#[tauri::command]
+async fn get_scan_history(query: Option<HistoryQuery>) -> Result<HistoryPage, String> {
+    let query = query.unwrap_or_default();
+    tokio::task::spawn_blocking(move || history::query(&query))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+async fn delete_scan_history(ids: Vec<String>) -> Result<usize, String> {
+    let removed = tokio::task::spawn_blocking(move || history::delete(&ids))
+        .await
+        .map_err(|e| e.to_string())??;
+    log::info!("Deleted {} scan history records", removed);
+    Ok(removed)
+}
+
+#[tauri::command]
+async fn clear_scan_history() -> Result<usize, String> {
+    let removed = tokio::task::spawn_blocking(history::clear)
+        .await
+        .map_err(|e| e.to_string())??;
+    log::info!("Scan history cleared ({} records)", removed);
+    Ok(removed)
+}
+
+/// System policy and the settings it locks, so the UI can disable them
+#[tauri::command]
+async fn get_policy() -> Result<PolicyInfo, String> {
+    Ok(policy::current().info())
+}
+
+/// Writes a signed bundle of this machine's settings (and optionally its paired
+/// devices, including their keys) for provisioning other machines
+#[tauri::command]
+async fn export_config_bundle(
+    state: State<'_, AppState>,
+    path: String,
+    signing_key_path: String,
+    include_devices: bool,
+) -> Result<(), String> {
+    let signing_key = std::fs::read_to_string(&signing_key_path)
+        .map_err(|e| format!("Failed to read signing key: {}", e))?;
+    let bundle = {
+        let config = state.config.lock().unwrap();
+        provisioning::export(&config, &signing_key, include_devices)?
+    };
+    let content = serde_json::to_string_pretty(&bundle).map_err(|e| e.to_string())?;
+    std::fs::write(&path, content).map_err(|e| format!("Failed to write bundle: {}", e))
+}
+
+/// Applies a signed bundle; the signer must be trusted by the policy or `trusted_key`
+#[tauri::command]
+async fn import_config_bundle(
+    state: State<'_, AppState>,
+    path: String,
+    trusted_key: Option<String>,
+) -> Result<BundleImportResult, String> {
+    let content = std::fs::read_to_string(&path).map_err(|e| format!("Failed to read bundle: {}", e))?;
+    let bundle: Bundle = serde_json::from_str(&content).map_err(|e| format!("Invalid bundle: {}", e))?;
+
+    let mut config = state.config.lock().unwrap();
+    let (updated, result) = provisioning::import(&bundle, &config, policy::current(), trusted_key.as_deref())?;
+    storage::save(&updated)?;
+    output::prepare_sinks(&updated.output_sinks);
+    *config = updated;
+    Ok(result)
+}
+
+/// Pending recovery offer after the config file was found corrupt at startup
+#[tauri::command]
+async fn get_config_recovery(state: State<'_, AppState>) -> Result<Option<ConfigRecovery>, String> {
+    Ok(state.config_recovery.lock().unwrap().clone())
+}
+
+/// Replaces the defaults loaded after a corrupt config with the last good backup
+#[tauri::command]
+async fn restore_config_backup(state: State<'_, AppState>) -> Result<(), String> {
+    let restored = storage::restore_backup()?;
+    output::prepare_sinks(&restored.output_sinks);
+    *state.config.lock().unwrap() = restored;
+    *state.config_recovery.lock().unwrap() = None;
+    Ok(())
+}
+
+/// Retries loading a config whose secrets could not be decrypted at startup,
+/// with the given passphrase or after the user unlocked the keyring.
+/// Returns the recovery offer if the unlocked file turned out corrupt.
+#[tauri::command]
+async fn unlock_config(
+    state: State<'_, AppState>,
+    passphrase: Option<String>,
+) -> Result<Option<ConfigRecovery>, String> {
+    if let Some(passphrase) = passphrase.filter(|p| !p.is_empty()) {
+        vault::set_passphrase(passphrase);
+    }
+    let (config, recovery) = tauri::async_runtime::spawn_blocking(storage::unlock)
+        .await
+        .map_err(|e| e.to_string())??;
+    output::prepare_sinks(&config.output_sinks);
+    *state.config.lock().unwrap() = config;
+    *state.config_recovery.lock().unwrap() = recovery.clone();
+    Ok(recovery)
+}
+
+/// Keeps the current settings; the backup is replaced on the next save
+#[tauri::command]
+async fn dismiss_config_recovery(state: State<'_, AppState>) -> Result<(), String> {
+    storage::discard_backup();
+    *state.config_recovery.lock().unwrap() = None;
+    Ok(())
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     // Load configuration
-    let config = storage::load();
+    let (config, config_recovery) = storage::load_with_recovery();
+    output::prepare_sinks(&config.output_sinks);
 
     tauri::Builder::default()
         .plugin(tauri_plugin_single_instance::init(|app, _args, _cwd| {
@@ -407,6 +840,16 @@ pub fn run() {
                 });
             }
 
+            // Offer to restore the backup if the config was corrupt; the UI also asks on mount
+            if let Some(recovery) = state.config_recovery.lock().unwrap().clone() {
+                if let Err(e) = app.emit("config-recovery", recovery) {
+                    log::error!("Failed to emit config-recovery event: {}", e);
+                }
+            }
+
+            // Detect the input backend now rather than delaying the first scan
+            tauri::async_runtime::spawn_blocking(keyboard::environment);
+
             // Auto-start server on launch
             let app_handle = app.handle().clone();
             tauri::async_runtime::spawn(async move {
@@ -491,6 +934,7 @@ pub fn run() {
             server_task: Arc::new(Mutex::new(None)),
             starting: Arc::new(Mutex::new(false)),
             config: Arc::new(Mutex::new(config)),
+            config_recovery: Arc::new(Mutex::new(config_recovery)),
             mdns: Arc::new(Mutex::new(None)),
         })
         .invoke_handler(tauri::generate_handler![
@@ -499,12 +943,43 @@ pub fn run() {
             get_server_state,
             get_current_qr_data,
             get_authorized_devices,
+            get_legacy_key_devices,
             get_connected_devices,
             revoke_device,
             revoke_all_devices,
+            send_device_command,
             regenerate_token,
             get_settings,
             update_settings,
+            get_output_sinks,
+            set_output_sinks,
+            get_transform_rules,
+            set_transform_rules,
+            test_transform_rule,
+            get_output_profiles,
+            set_output_profiles,
+            get_focused_window,
+            get_input_diagnostics,
+            get_config_recovery,
+            restore_config_backup,
+            unlock_config,
+            dismiss_config_recovery,
+            get_policy,
+            export_config_bundle,
+            import_config_bundle,
+            get_keystroke_templates,
+            set_keystroke_template,
+            get_network_settings,
+            get_input_overrides,
+            set_input_overrides,
+            set_network_settings,
+            get_token_rotation,
+            set_token_rotation,
+            get_require_encryption,
+            set_require_encryption,
+            get_scan_history,
+            delete_scan_history,
+            clear_scan_history,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
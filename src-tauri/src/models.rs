@@ -1,4 +1,7 @@
 use serde::{Deserialize, Serialize};
+use crate::gs1::Gs1Data;
+use crate::keyboard::{self, InputMode};
+use crate::security::TokenRotation;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConnectionInfo {
@@ -8,6 +11,31 @@ pub struct ConnectionInfo {
     /// Secret key for encryption (only included in QR for initial pairing)
     #[serde(rename = "secretKey", skip_serializing_if = "Option::is_none")]
     pub secret_key: Option<String>,
+    /// "wss" when TLS is enabled, "ws" for legacy plain connections
+    #[serde(default = "default_scheme")]
+    pub scheme: String,
+    /// SHA-256 fingerprint of the server certificate, pinned by the phone at pairing
+    #[serde(rename = "certFingerprint", skip_serializing_if = "Option::is_none")]
+    pub cert_fingerprint: Option<String>,
+}
+
+fn default_scheme() -> String {
+    "ws".to_string()
+}
+
+impl ConnectionInfo {
+    /// Builds the pairing info; a certificate fingerprint switches the scheme to wss
+    pub fn new(ip: String, port: u16, token: String, cert_fingerprint: Option<String>) -> Self {
+        let scheme = if cert_fingerprint.is_some() { "wss" } else { "ws" };
+        Self {
+            ip,
+            port,
+            token,
+            secret_key: None,  // Secret key is not exposed in QR code for security
+            scheme: scheme.to_string(),
+            cert_fingerprint,
+        }
+    }
 }
 
 // Barcode message (internal use)
@@ -19,6 +47,11 @@ pub struct BarcodeMessage {
     pub device_id: String,
     #[serde(rename = "deviceName")]
     pub device_name: Option<String>,
+    #[serde(rename = "barcodeType", skip_serializing_if = "Option::is_none")]
+    pub barcode_type: Option<String>,
+    /// GS1 fields, filled in by the scan pipeline when the barcode is GS1 data
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gs1: Option<Gs1Data>,
 }
 
 // Scan payload from mobile app
@@ -32,7 +65,6 @@ pub struct ScanPayload {
 // Scan message from mobile app
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ScanMessage {
-    pub action: String,
     #[serde(rename = "deviceId")]
     pub device_id: String,
     #[serde(rename = "deviceName", skip_serializing_if = "Option::is_none")]
@@ -48,12 +80,49 @@ pub struct ScanMessage {
     /// Encrypted auth token (for reconnection)
     #[serde(rename = "authToken", skip_serializing_if = "Option::is_none")]
     pub auth_token: Option<String>,
+    /// Client-generated id; a scan with an already delivered id is not typed again
+    #[serde(rename = "scanId", skip_serializing_if = "Option::is_none")]
+    pub scan_id: Option<String>,
+}
+
+// One scan queued on the phone while it was offline
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanBatchItem {
+    #[serde(rename = "scanId")]
+    pub scan_id: String,
+    /// When the scan was made on the phone
+    pub timestamp: i64,
+    pub payload: ScanPayload,
+}
+
+// Batch of queued scans uploaded after reconnecting
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanBatchMessage {
+    #[serde(rename = "deviceId")]
+    pub device_id: String,
+    #[serde(rename = "deviceName", skip_serializing_if = "Option::is_none")]
+    pub device_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(rename = "authToken", skip_serializing_if = "Option::is_none")]
+    pub auth_token: Option<String>,
+    pub scans: Vec<ScanBatchItem>,
+}
+
+// Handshake from mobile app (connection check and protocol negotiation)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HandshakeRequest {
+    /// Newest protocol version the client speaks
+    #[serde(rename = "protocolVersion", default, skip_serializing_if = "Option::is_none")]
+    pub protocol_version: Option<u32>,
+    /// Oldest protocol version the client still accepts
+    #[serde(rename = "minProtocolVersion", default, skip_serializing_if = "Option::is_none")]
+    pub min_protocol_version: Option<u32>,
 }
 
 // Pair request from mobile app (first-time connection)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PairRequest {
-    pub action: String,
     #[serde(rename = "deviceId")]
     pub device_id: String,
     #[serde(rename = "deviceName")]
@@ -62,22 +131,148 @@ pub struct PairRequest {
     pub device_model: Option<String>,
     #[serde(rename = "masterToken")]
     pub master_token: String,
+    /// Client nonce requesting an encrypted session after pairing
+    #[serde(rename = "sessionNonce", default, skip_serializing_if = "Option::is_none")]
+    pub session_nonce: Option<String>,
 }
 
 // Reconnect request from mobile app (returning device)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ReconnectRequest {
-    pub action: String,
     #[serde(rename = "deviceId")]
     pub device_id: String,
     #[serde(rename = "authToken")]
     pub auth_token: String,
+    /// Client nonce requesting an encrypted session after reconnecting
+    #[serde(rename = "sessionNonce", default, skip_serializing_if = "Option::is_none")]
+    pub session_nonce: Option<String>,
+}
+
+// Token refresh from an authenticated mobile app (before the token expires)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RefreshTokenRequest {
+    #[serde(rename = "deviceId")]
+    pub device_id: String,
+    #[serde(rename = "authToken")]
+    pub auth_token: String,
+}
+
+// Encrypted envelope wrapping another message once a session is established
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptedFrame {
+    pub seq: u64,
+    /// Base64 of nonce followed by AES-256-GCM ciphertext
+    pub payload: String,
+}
+
+// Command pushed from the desktop to a connected phone
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum DeviceCommand {
+    /// Start a single scan as if the user pressed the scan button
+    TriggerScan,
+    /// Show a toast on the phone
+    ShowMessage {
+        message: String,
+        #[serde(rename = "durationMs", default, skip_serializing_if = "Option::is_none")]
+        duration_ms: Option<u32>,
+    },
+    Vibrate {
+        #[serde(rename = "durationMs", default, skip_serializing_if = "Option::is_none")]
+        duration_ms: Option<u32>,
+    },
+    /// Keep scanning after each barcode instead of returning to idle
+    SetContinuousScan {
+        enabled: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandAckStatus {
+    /// Received by the phone
+    Delivered,
+    /// Carried out successfully
+    Executed,
+    Failed,
+    /// The app does not know this command
+    Unsupported,
+    /// Reported by the desktop when no final ack arrived in time
+    TimedOut,
+}
+
+// Acknowledgement of a DeviceCommand from the phone
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandAck {
+    #[serde(rename = "commandId")]
+    pub command_id: String,
+    pub status: CommandAckStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+// Temporary ban after repeated failed pairing/authentication attempts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockoutEvent {
+    /// Banned client address
+    pub ip: String,
+    pub failures: u32,
+    /// Unix time when the ban ends
+    #[serde(rename = "lockedUntil")]
+    pub locked_until: i64,
+}
+
+// Config file that failed to load. A corrupt one was quarantined and the UI offers
+// to restore the backup; one whose key is unavailable is left alone and the UI asks for it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigRecovery {
+    pub error: String,
+    /// Where the corrupt file was moved (None if it could not be moved)
+    #[serde(rename = "quarantinedPath", skip_serializing_if = "Option::is_none")]
+    pub quarantined_path: Option<String>,
+    #[serde(rename = "backupAvailable")]
+    pub backup_available: bool,
+    /// RFC 3339 time the backup was written
+    #[serde(rename = "backupSavedAt", skip_serializing_if = "Option::is_none")]
+    pub backup_saved_at: Option<String>,
+    /// The file is intact but its secrets are sealed with a locked or missing
+    /// key (keyring or passphrase); nothing is saved until it is unlocked
+    #[serde(rename = "keyUnavailable", default)]
+    pub key_unavailable: bool,
+}
+
+// Command acknowledgement forwarded to the frontend
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandAckEvent {
+    #[serde(rename = "commandId")]
+    pub command_id: String,
+    #[serde(rename = "deviceId")]
+    pub device_id: String,
+    pub status: CommandAckStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    pub timestamp: i64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QRCodeData {
     pub qr_base64: String,
     pub connection_info: ConnectionInfo,
+    /// How long the token in the QR code keeps pairing new devices
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub validity: Option<TokenValidity>,
+}
+
+// Validity of the master token, shown next to the QR code
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenValidity {
+    pub rotation: TokenRotation,
+    /// Unix time the token is replaced (daily rotation)
+    #[serde(rename = "expiresAt", skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+    /// Pairings left before the token is replaced
+    #[serde(rename = "remainingPairings", skip_serializing_if = "Option::is_none")]
+    pub remaining_pairings: Option<u32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -101,6 +296,19 @@ pub struct DeviceInfo {
     pub last_seen: Option<String>,
     #[serde(rename = "isConnected", default)]
     pub is_connected: bool,
+    /// Still on the shared key from before per-device keys: it cannot encrypt
+    /// scans until it moves to its own key on reconnect, or pairs again
+    #[serde(rename = "legacyKey", default)]
+    pub legacy_key: bool,
+}
+
+// Keystroke templates for frontend
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeystrokeTemplates {
+    pub template: String,
+    /// Overrides by device id
+    #[serde(rename = "deviceTemplates")]
+    pub device_templates: std::collections::HashMap<String, String>,
 }
 
 // App settings for frontend
@@ -112,19 +320,19 @@ pub struct AppSettings {
     pub minimize_to_tray: bool,
     #[serde(rename = "startMinimized")]
     pub start_minimized: bool,
+    #[serde(rename = "inputMode", default)]
+    pub input_mode: InputMode,
+    #[serde(rename = "typeDelayMs", default = "default_type_delay_ms")]
+    pub type_delay_ms: u64,
+    /// Restores one format of the previous clipboard content, see `AppConfig::preserve_clipboard`
+    #[serde(rename = "preserveClipboard", default = "default_true")]
+    pub preserve_clipboard: bool,
 }
 
-// WebSocket response messages
-#[allow(dead_code)] // Reserved for future WebSocket response handling
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct WsResponse {
-    pub action: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub status: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub message: Option<String>,
-    #[serde(rename = "authToken", skip_serializing_if = "Option::is_none")]
-    pub auth_token: Option<String>,
-    #[serde(rename = "deviceId", skip_serializing_if = "Option::is_none")]
-    pub device_id: Option<String>,
+fn default_true() -> bool {
+    true
+}
+
+fn default_type_delay_ms() -> u64 {
+    keyboard::DEFAULT_KEY_DELAY_MS
 }
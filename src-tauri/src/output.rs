@@ -0,0 +1,462 @@
+use arboard::Clipboard;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+use crate::keyboard::{self, TypingOptions};
+use crate::models::BarcodeMessage;
+#[cfg(target_os = "linux")]
+use crate::serial;
+use crate::template::Template;
+use crate::transform::{self, CompiledRule};
+
+const DEFAULT_WEBHOOK_TIMEOUT_SECS: u64 = 5;
+
+/// Keeps the clipboard owned between scans. On Linux the content is only
+/// served while a `Clipboard` instance is alive.
+static CLIPBOARD: Mutex<Option<Clipboard>> = Mutex::new(None);
+
+/// Line format for the file sink
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileFormat {
+    /// Just the barcode
+    #[default]
+    Line,
+    /// One JSON object per scan, with device and timestamp
+    Jsonl,
+}
+
+/// End-of-scan marker written by the serial sink
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Terminator {
+    #[default]
+    Cr,
+    CrLf,
+    Lf,
+    /// ASCII End of Text (0x03)
+    Etx,
+    None,
+}
+
+impl Terminator {
+    fn bytes(&self) -> &'static [u8] {
+        match self {
+            Terminator::Cr => b"\r",
+            Terminator::CrLf => b"\r\n",
+            Terminator::Lf => b"\n",
+            Terminator::Etx => b"\x03",
+            Terminator::None => b"",
+        }
+    }
+}
+
+/// One configured output, as stored in `AppConfig::output_sinks`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SinkConfig {
+    /// Types the barcode into the focused window, like a keyboard wedge scanner
+    Keyboard,
+    /// Only copies the barcode to the clipboard
+    Clipboard,
+    /// Appends each scan to a file
+    File {
+        path: PathBuf,
+        #[serde(default)]
+        format: FileFormat,
+    },
+    /// Prints each barcode on its own line (useful with the headless daemon)
+    Stdout,
+    /// POSTs each scan as JSON
+    Webhook {
+        url: String,
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        headers: HashMap<String, String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timeout_secs: Option<u64>,
+    },
+    /// Emulates a serial scanner on a pseudo-terminal (Linux)
+    Serial {
+        /// Symlink to the terminal device, `~/.scanlink/ttyScanner0` by default
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        link: Option<PathBuf>,
+        #[serde(default)]
+        terminator: Terminator,
+        /// Prefix each scan with its AIM symbology identifier, e.g. `]E0`
+        #[serde(default)]
+        aim_prefix: bool,
+    },
+}
+
+/// AIM symbology identifier for the barcode types reported by the phone.
+/// GS1 data uses the GS1 modifier of its symbology.
+pub fn aim_identifier(scan: &BarcodeMessage) -> &'static str {
+    let gs1 = scan.gs1.is_some();
+    let barcode_type = scan.barcode_type.as_deref().unwrap_or_default().to_ascii_uppercase();
+
+    match barcode_type.replace(['-', ' '], "_").as_str() {
+        "CODE_128" | "GS1_128" if gs1 => "]C1",
+        "CODE_128" | "GS1_128" => "]C0",
+        "EAN_13" | "UPC_A" | "UPC_E" => "]E0",
+        "EAN_8" => "]E4",
+        "CODE_39" => "]A0",
+        "CODE_93" => "]G0",
+        "CODABAR" => "]F0",
+        "ITF" => "]I0",
+        "QR_CODE" if gs1 => "]Q3",
+        "QR_CODE" => "]Q1",
+        "DATA_MATRIX" | "GS1_DATAMATRIX" if gs1 => "]d2",
+        "DATA_MATRIX" | "GS1_DATAMATRIX" => "]d1",
+        "PDF_417" => "]L0",
+        "AZTEC" => "]z0",
+        "DATABAR" | "RSS_14" | "RSS_EXPANDED" => "]e0",
+        _ => "]X0",
+    }
+}
+
+fn serial_frame(scan: &BarcodeMessage, terminator: Terminator, aim_prefix: bool) -> Vec<u8> {
+    let mut frame = Vec::new();
+    // Scanners configured to transmit identifiers already include one
+    if aim_prefix && !scan.barcode.starts_with(']') {
+        frame.extend_from_slice(aim_identifier(scan).as_bytes());
+    }
+    frame.extend_from_slice(scan.barcode.as_bytes());
+    frame.extend_from_slice(terminator.bytes());
+    frame
+}
+
+/// Default output: type into the focused window, as before sinks existed
+pub fn default_sinks() -> Vec<SinkConfig> {
+    vec![SinkConfig::Keyboard]
+}
+
+/// Outcome of delivering one scan to one sink
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SinkResult {
+    pub sink: String,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Destination for scanned barcodes. Deliveries are blocking and run off the async runtime.
+pub trait OutputSink: Send + Sync {
+    /// Short label used in logs and scan results, e.g. "file:/tmp/scans.txt"
+    fn name(&self) -> String;
+
+    fn deliver(&self, scan: &BarcodeMessage) -> Result<(), String>;
+}
+
+/// Settings of the keyboard sink, resolved per scan for the scanning device
+#[derive(Debug, Clone, Default)]
+pub struct KeyboardOptions {
+    pub template: Template,
+    pub typing: TypingOptions,
+    /// Output profile rules, applied on top of the global transform rules
+    pub transforms: Vec<CompiledRule>,
+    /// Why typing is not allowed into the focused window, if it is not
+    pub blocked: Option<String>,
+}
+
+pub struct KeyboardSink {
+    pub options: KeyboardOptions,
+}
+
+impl OutputSink for KeyboardSink {
+    fn name(&self) -> String {
+        "keyboard".to_string()
+    }
+
+    fn deliver(&self, scan: &BarcodeMessage) -> Result<(), String> {
+        if let Some(ref reason) = self.options.blocked {
+            return Err(reason.clone());
+        }
+
+        let actions = if self.options.transforms.is_empty() {
+            self.options.template.render(scan, chrono::Local::now())
+        } else {
+            let scan = BarcodeMessage {
+                barcode: transform::apply_rules(&self.options.transforms, scan),
+                ..scan.clone()
+            };
+            self.options.template.render(&scan, chrono::Local::now())
+        };
+        keyboard::type_actions(&actions, &self.options.typing)
+    }
+}
+
+pub struct ClipboardSink;
+
+impl OutputSink for ClipboardSink {
+    fn name(&self) -> String {
+        "clipboard".to_string()
+    }
+
+    fn deliver(&self, scan: &BarcodeMessage) -> Result<(), String> {
+        let mut guard = CLIPBOARD.lock().unwrap();
+        if guard.is_none() {
+            *guard = Some(Clipboard::new().map_err(|e| format!("Failed to access clipboard: {}", e))?);
+        }
+
+        match guard.as_mut() {
+            Some(clipboard) => clipboard
+                .set_text(scan.barcode.as_str())
+                .map_err(|e| format!("Failed to set clipboard: {}", e)),
+            None => Err("Clipboard not available".to_string()),
+        }
+    }
+}
+
+pub struct FileSink {
+    pub path: PathBuf,
+    pub format: FileFormat,
+}
+
+impl OutputSink for FileSink {
+    fn name(&self) -> String {
+        format!("file:{}", self.path.display())
+    }
+
+    fn deliver(&self, scan: &BarcodeMessage) -> Result<(), String> {
+        let line = match self.format {
+            FileFormat::Line => scan.barcode.clone(),
+            FileFormat::Jsonl => serde_json::to_string(scan)
+                .map_err(|e| format!("Failed to serialize scan: {}", e))?,
+        };
+
+        if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create directory {:?}: {}", parent, e))?;
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| format!("Failed to open {:?}: {}", self.path, e))?;
+
+        writeln!(file, "{}", line)
+            .map_err(|e| format!("Failed to write {:?}: {}", self.path, e))
+    }
+}
+
+pub struct StdoutSink;
+
+impl OutputSink for StdoutSink {
+    fn name(&self) -> String {
+        "stdout".to_string()
+    }
+
+    fn deliver(&self, scan: &BarcodeMessage) -> Result<(), String> {
+        let mut stdout = std::io::stdout().lock();
+        writeln!(stdout, "{}", scan.barcode)
+            .and_then(|_| stdout.flush())
+            .map_err(|e| format!("Failed to write to stdout: {}", e))
+    }
+}
+
+pub struct WebhookSink {
+    pub url: String,
+    pub headers: HashMap<String, String>,
+    pub timeout: Duration,
+}
+
+impl OutputSink for WebhookSink {
+    fn name(&self) -> String {
+        format!("webhook:{}", self.url)
+    }
+
+    fn deliver(&self, scan: &BarcodeMessage) -> Result<(), String> {
+        let agent = ureq::AgentBuilder::new().timeout(self.timeout).build();
+        let mut request = agent.post(&self.url);
+        for (name, value) in &self.headers {
+            request = request.set(name, value);
+        }
+
+        match request.send_json(scan) {
+            Ok(_) => Ok(()),
+            Err(ureq::Error::Status(code, _)) => Err(format!("Webhook returned HTTP {}", code)),
+            Err(e) => Err(format!("Webhook request failed: {}", e)),
+        }
+    }
+}
+
+pub struct SerialSink {
+    pub link: PathBuf,
+    pub terminator: Terminator,
+    pub aim_prefix: bool,
+}
+
+impl OutputSink for SerialSink {
+    fn name(&self) -> String {
+        format!("serial:{}", self.link.display())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn deliver(&self, scan: &BarcodeMessage) -> Result<(), String> {
+        serial::write(&self.link, &serial_frame(scan, self.terminator, self.aim_prefix))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn deliver(&self, _scan: &BarcodeMessage) -> Result<(), String> {
+        Err("Serial scanner emulation is only supported on Linux".to_string())
+    }
+}
+
+fn serial_link(link: &Option<PathBuf>) -> PathBuf {
+    #[cfg(target_os = "linux")]
+    if link.is_none() {
+        if let Ok(default) = serial::default_link() {
+            return default;
+        }
+    }
+    link.clone().unwrap_or_else(|| PathBuf::from("ttyScanner0"))
+}
+
+/// Opens long-lived resources of the configured sinks (serial ports) so they exist
+/// before the first scan, and releases those of sinks that were removed
+pub fn prepare_sinks(configs: &[SinkConfig]) {
+    let links: Vec<PathBuf> = configs
+        .iter()
+        .filter_map(|config| match config {
+            SinkConfig::Serial { link, .. } => Some(serial_link(link)),
+            _ => None,
+        })
+        .collect();
+
+    #[cfg(target_os = "linux")]
+    {
+        serial::close_unused(&links);
+        for link in &links {
+            if let Err(e) = serial::ensure_port(link) {
+                log::error!("Failed to open serial scanner port {:?}: {}", link, e);
+            }
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    if !links.is_empty() {
+        log::warn!("Serial scanner emulation is only supported on Linux");
+    }
+}
+
+/// Instantiates the configured sinks, in order
+pub fn build_sinks(configs: &[SinkConfig], keyboard: &KeyboardOptions) -> Vec<Box<dyn OutputSink>> {
+    configs
+        .iter()
+        .map(|config| -> Box<dyn OutputSink> {
+            match config {
+                SinkConfig::Keyboard => Box::new(KeyboardSink { options: keyboard.clone() }),
+                SinkConfig::Clipboard => Box::new(ClipboardSink),
+                SinkConfig::File { path, format } => Box::new(FileSink {
+                    path: path.clone(),
+                    format: *format,
+                }),
+                SinkConfig::Stdout => Box::new(StdoutSink),
+                SinkConfig::Webhook { url, headers, timeout_secs } => Box::new(WebhookSink {
+                    url: url.clone(),
+                    headers: headers.clone(),
+                    timeout: Duration::from_secs(timeout_secs.unwrap_or(DEFAULT_WEBHOOK_TIMEOUT_SECS)),
+                }),
+                SinkConfig::Serial { link, terminator, aim_prefix } => Box::new(SerialSink {
+                    link: serial_link(link),
+                    terminator: *terminator,
+                    aim_prefix: *aim_prefix,
+                }),
+            }
+        })
+        .collect()
+}
+
+/// Delivers a scan to every sink in order. A failing sink does not stop the others.
+pub fn deliver_all(sinks: &[Box<dyn OutputSink>], scan: &BarcodeMessage) -> Vec<SinkResult> {
+    sinks
+        .iter()
+        .map(|sink| {
+            let name = sink.name();
+            match sink.deliver(scan) {
+                Ok(()) => {
+                    log::debug!("Delivered scan to {}", name);
+                    SinkResult { sink: name, success: true, error: None }
+                }
+                Err(e) => {
+                    log::error!("Failed to deliver scan to {}: {}", name, e);
+                    SinkResult { sink: name, success: false, error: Some(e) }
+                }
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scan(barcode: &str) -> BarcodeMessage {
+        BarcodeMessage {
+            barcode: barcode.to_string(),
+            timestamp: 1,
+            device_id: "d1".to_string(),
+            device_name: None,
+            barcode_type: None,
+            gs1: None,
+        }
+    }
+
+    struct FailingSink;
+
+    impl OutputSink for FailingSink {
+        fn name(&self) -> String {
+            "failing".to_string()
+        }
+
+        fn deliver(&self, _scan: &BarcodeMessage) -> Result<(), String> {
+            Err("boom".to_string())
+        }
+    }
+
+    #[test]
+    fn test_fan_out_continues_after_failure() {
+        let path = std::env::temp_dir().join(format!("scanlink-sink-{}.txt", uuid::Uuid::new_v4()));
+        let sinks: Vec<Box<dyn OutputSink>> = vec![
+            Box::new(FailingSink),
+            Box::new(FileSink { path: path.clone(), format: FileFormat::Line }),
+        ];
+
+        let results = deliver_all(&sinks, &scan("123"));
+        let _ = deliver_all(&sinks, &scan("456"));
+        let written = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+
+        assert!(!results[0].success);
+        assert_eq!(results[0].error.as_deref(), Some("boom"));
+        assert!(results[1].success);
+        assert_eq!(written, "123\n456\n");
+    }
+
+    #[test]
+    fn test_serial_frame() {
+        let mut ean = scan("7891234567895");
+        ean.barcode_type = Some("EAN_13".to_string());
+        assert_eq!(serial_frame(&ean, Terminator::CrLf, true), b"]E07891234567895\r\n");
+        assert_eq!(serial_frame(&ean, Terminator::Etx, false), b"7891234567895\x03");
+
+        let mut gs1 = scan("0109501101530003");
+        gs1.barcode_type = Some("DATA_MATRIX".to_string());
+        gs1.gs1 = crate::gs1::parse(&gs1.barcode).ok();
+        assert_eq!(aim_identifier(&gs1), "]d2");
+        assert_eq!(aim_identifier(&scan("x")), "]X0");
+    }
+
+    #[test]
+    fn test_sink_config_format() {
+        let json = r#"[{"type":"keyboard"},{"type":"file","path":"/tmp/scans.jsonl","format":"jsonl"},{"type":"webhook","url":"http://erp.local/scan"}]"#;
+        let configs: Vec<SinkConfig> = serde_json::from_str(json).unwrap();
+        assert_eq!(configs[0], SinkConfig::Keyboard);
+        assert!(matches!(&configs[1], SinkConfig::File { format: FileFormat::Jsonl, .. }));
+        assert_eq!(build_sinks(&configs, &KeyboardOptions::default())[2].name(), "webhook:http://erp.local/scan");
+    }
+}
@@ -0,0 +1,122 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crate::gs1;
+use crate::history::{self, OutputResult, ScanRecord};
+use crate::models::BarcodeMessage;
+use crate::keyboard::TypingOptions;
+use crate::output::{self, KeyboardOptions, SinkConfig};
+use crate::profile;
+use crate::storage::AppConfig;
+use crate::template::Template;
+use crate::transform;
+use crate::window::{self, FocusedWindow};
+
+/// Resolves the keyboard sink settings for a device and the focused window.
+/// A matching output profile takes precedence over device and global settings.
+fn keyboard_options(config: &AppConfig, device_id: &str, window: Option<&FocusedWindow>) -> KeyboardOptions {
+    let selected = profile::select(&config.output_profiles, window);
+    let profile = selected.as_ref();
+    if let Some(profile) = profile {
+        log::debug!("Using output profile '{}'", profile.name);
+    }
+
+    let blocked = match profile {
+        Some(profile) if profile.block => Some(format!("Typing blocked by output profile '{}'", profile.name)),
+        Some(_) => None,
+        None if config.block_unmatched_windows => Some(match window {
+            Some(_) => "Typing blocked: the focused window matches no output profile".to_string(),
+            None => "Typing blocked: the focused window could not be detected".to_string(),
+        }),
+        None => None,
+    };
+
+    let source = profile
+        .and_then(|profile| profile.keystroke_template.as_deref())
+        .unwrap_or_else(|| config.keystroke_template_for(device_id));
+    let template = Template::parse(source).unwrap_or_else(|e| {
+        // Templates are validated when set, so this only happens with hand-edited configs
+        log::error!("Invalid keystroke template '{}': {}. Using the default.", source, e);
+        Template::default()
+    });
+
+    let typing = TypingOptions {
+        mode: profile.and_then(|profile| profile.input_mode).unwrap_or(config.input_mode),
+        key_delay: Duration::from_millis(config.type_delay_ms),
+        preserve_clipboard: config.preserve_clipboard,
+        backend: config.input_backend,
+        keyboard_layout: config.keyboard_layout.clone(),
+    };
+
+    let transforms = profile
+        .map(|profile| transform::compile_rules(&profile.transform_rules))
+        .unwrap_or_default();
+
+    KeyboardOptions { template, typing, transforms, blocked }
+}
+
+/// Rewrites the scan's barcode with the configured transform rules, then delivers it
+/// to the configured outputs and records it in the scan history.
+/// Shared by the desktop app and the headless daemon so both behave the same.
+pub async fn process_barcode(barcode_msg: &BarcodeMessage, config: &Arc<Mutex<AppConfig>>) -> ScanRecord {
+    log::info!("Received barcode: {} from device: {}", barcode_msg.barcode, barcode_msg.device_id);
+
+    // Only look at the focused window when a profile could depend on it
+    let needs_window = {
+        let cfg = config.lock().unwrap();
+        cfg.output_sinks.contains(&SinkConfig::Keyboard)
+            && (!cfg.output_profiles.is_empty() || cfg.block_unmatched_windows)
+    };
+    let window = if needs_window {
+        tokio::task::spawn_blocking(window::focused_window).await.unwrap_or(None)
+    } else {
+        None
+    };
+
+    // Read the settings per scan so configuration changes apply immediately
+    let (sink_configs, keyboard, rules) = {
+        let cfg = config.lock().unwrap();
+        (
+            cfg.output_sinks.clone(),
+            keyboard_options(&cfg, &barcode_msg.device_id, window.as_ref()),
+            transform::compile_rules(&cfg.transform_rules),
+        )
+    };
+
+    // GS1 fields come from the barcode as scanned, before rules rewrite it
+    let scanned = BarcodeMessage {
+        gs1: gs1::detect(&barcode_msg.barcode, barcode_msg.barcode_type.as_deref()),
+        ..barcode_msg.clone()
+    };
+    let transformed = BarcodeMessage {
+        barcode: transform::apply_rules(&rules, &scanned),
+        ..scanned
+    };
+    if transformed.barcode != barcode_msg.barcode {
+        log::info!("Barcode transformed to: {}", transformed.barcode);
+    }
+
+    let scan = transformed.clone();
+    let delivery = tokio::task::spawn_blocking(move || {
+        let sinks = output::build_sinks(&sink_configs, &keyboard);
+        output::deliver_all(&sinks, &scan)
+    }).await;
+
+    let output = match delivery {
+        Ok(results) => OutputResult::from_sinks(results),
+        Err(e) => {
+            log::error!("Output task failed: {}", e);
+            OutputResult::failed(format!("Output task failed: {}", e))
+        }
+    };
+
+    // Persist the scan so it survives restarts
+    let mut record = ScanRecord::new(&transformed, output);
+    if transformed.barcode != barcode_msg.barcode {
+        record.original_barcode = Some(barcode_msg.barcode.clone());
+    }
+    if let Err(e) = history::append(&record) {
+        log::error!("Failed to record scan history: {}", e);
+    }
+
+    record
+}
@@ -0,0 +1,250 @@
+//! System-wide policy for managed installs, e.g. `/etc/scanlink/policy.toml`:
+//!
+//! ```toml
+//! # Applied when a user starts ScanLink for the first time
+//! [defaults]
+//! keystroke_template = "{barcode}{TAB}"
+//!
+//! # Applied over the user's config on every start and not editable
+//! [locked]
+//! require_encryption = true
+//! input_mode = "type"
+//!
+//! # Ed25519 public keys (base64) allowed to sign provisioning bundles
+//! trusted_bundle_keys = ["..."]
+//! ```
+//!
+//! Keys are `AppConfig` field names. Secrets and paired devices cannot be set here.
+
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use crate::storage::AppConfig;
+
+/// Overrides the policy location, mainly for testing deployments
+pub const POLICY_PATH_ENV: &str = "SCANLINK_POLICY_FILE";
+
+/// Fields a policy or bundle must never carry
+pub const PROTECTED_FIELDS: &[&str] = &[
+    "schema_version",
+    "master_token",
+    "master_token_issued_at",
+    "master_token_pairings",
+    "secret_key",
+    "authorized_devices",
+    "tls_identity",
+];
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyFile {
+    #[serde(default)]
+    defaults: Map<String, Value>,
+    #[serde(default)]
+    locked: Map<String, Value>,
+    #[serde(default)]
+    trusted_bundle_keys: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Policy {
+    /// File the policy came from, None when there is none
+    pub path: Option<PathBuf>,
+    /// Why the policy file was ignored
+    pub error: Option<String>,
+    defaults: Map<String, Value>,
+    locked: Map<String, Value>,
+    pub trusted_bundle_keys: Vec<String>,
+}
+
+/// What the settings UI needs to know about the policy
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PolicyInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub locked_settings: Vec<String>,
+}
+
+fn default_path() -> PathBuf {
+    if let Ok(path) = std::env::var(POLICY_PATH_ENV) {
+        return PathBuf::from(path);
+    }
+    if cfg!(target_os = "windows") {
+        let program_data = std::env::var("ProgramData").unwrap_or_else(|_| "C:\\ProgramData".to_string());
+        PathBuf::from(program_data).join("ScanLink").join("policy.toml")
+    } else if cfg!(target_os = "macos") {
+        PathBuf::from("/Library/Application Support/ScanLink/policy.toml")
+    } else {
+        PathBuf::from("/etc/scanlink/policy.toml")
+    }
+}
+
+/// Returns the config with one field replaced, or why the value does not fit
+pub fn with_field(config: &AppConfig, key: &str, value: &Value) -> Result<AppConfig, String> {
+    let mut fields = serde_json::to_value(config).map_err(|e| e.to_string())?;
+    let object = fields.as_object_mut().ok_or("Config is not an object")?;
+    if PROTECTED_FIELDS.contains(&key) {
+        return Err(format!("'{}' cannot be managed", key));
+    }
+    if !object.contains_key(key) {
+        return Err(format!("Unknown setting '{}'", key));
+    }
+    object.insert(key.to_string(), value.clone());
+    serde_json::from_value(fields).map_err(|e| format!("Invalid value for '{}': {}", key, e))
+}
+
+/// Drops (and logs) entries that are not valid settings
+fn valid_settings(table: Map<String, Value>, section: &str) -> Map<String, Value> {
+    let defaults = AppConfig::default();
+    table
+        .into_iter()
+        .filter(|(key, value)| match with_field(&defaults, key, value) {
+            Ok(_) => true,
+            Err(e) => {
+                log::error!("Ignoring [{}] {} in policy: {}", section, key, e);
+                false
+            }
+        })
+        .collect()
+}
+
+impl Policy {
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let file: PolicyFile = toml::from_str(content).map_err(|e| format!("Invalid policy: {}", e))?;
+        Ok(Self {
+            path: None,
+            error: None,
+            defaults: valid_settings(file.defaults, "defaults"),
+            locked: valid_settings(file.locked, "locked"),
+            trusted_bundle_keys: file.trusted_bundle_keys,
+        })
+    }
+
+    /// Reads the policy file; a missing file is an empty policy
+    fn load() -> Self {
+        let path = default_path();
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                log::error!("Failed to read policy {:?}: {}", path, e);
+                return Self { error: Some(e.to_string()), path: Some(path), ..Self::default() };
+            }
+        };
+
+        match Self::parse(&content) {
+            Ok(policy) => {
+                log::info!("Policy loaded from {:?}, locked settings: {:?}", path, policy.locked_settings());
+                Self { path: Some(path), ..policy }
+            }
+            Err(e) => {
+                log::error!("Ignoring policy {:?}: {}", path, e);
+                Self { error: Some(e), path: Some(path), ..Self::default() }
+            }
+        }
+    }
+
+    pub fn locked_settings(&self) -> Vec<String> {
+        self.locked.keys().cloned().collect()
+    }
+
+    pub fn is_locked(&self, key: &str) -> bool {
+        self.locked.contains_key(key)
+    }
+
+    fn apply_table(config: &mut AppConfig, table: &Map<String, Value>) {
+        for (key, value) in table {
+            match with_field(config, key, value) {
+                Ok(updated) => *config = updated,
+                Err(e) => log::error!("Failed to apply policy setting: {}", e),
+            }
+        }
+    }
+
+    /// Seeds a config created on first start
+    pub fn apply_defaults(&self, config: &mut AppConfig) {
+        Self::apply_table(config, &self.defaults);
+    }
+
+    /// Enforces the locked settings
+    pub fn apply_locked(&self, config: &mut AppConfig) {
+        Self::apply_table(config, &self.locked);
+    }
+
+    /// Fails if a change to `config` touched a locked setting
+    pub fn check(&self, config: &AppConfig) -> Result<(), String> {
+        let mut enforced = config.clone();
+        self.apply_locked(&mut enforced);
+
+        let actual = serde_json::to_value(config).map_err(|e| e.to_string())?;
+        let expected = serde_json::to_value(&enforced).map_err(|e| e.to_string())?;
+        match self.locked.keys().find(|key| actual.get(key.as_str()) != expected.get(key.as_str())) {
+            Some(key) => Err(format!("'{}' is locked by the administrator's policy", key)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn info(&self) -> PolicyInfo {
+        PolicyInfo {
+            path: self.path.as_ref().map(|p| p.to_string_lossy().into_owned()),
+            error: self.error.clone(),
+            locked_settings: self.locked_settings(),
+        }
+    }
+}
+
+/// Policy of this machine, read once per process
+pub fn current() -> &'static Policy {
+    static POLICY: OnceLock<Policy> = OnceLock::new();
+    POLICY.get_or_init(Policy::load)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyboard::InputMode;
+
+    const POLICY: &str = r#"
+        trusted_bundle_keys = ["key"]
+
+        [defaults]
+        keystroke_template = "{barcode}{TAB}"
+
+        [locked]
+        require_encryption = true
+        input_mode = "type"
+        master_token = "nope"
+        no_such_setting = 1
+        type_delay_ms = "fast"
+    "#;
+
+    #[test]
+    fn test_parse_drops_invalid_settings() {
+        let policy = Policy::parse(POLICY).unwrap();
+        assert_eq!(policy.locked_settings(), vec!["input_mode".to_string(), "require_encryption".to_string()]);
+        assert_eq!(policy.trusted_bundle_keys, vec!["key".to_string()]);
+        assert!(Policy::parse("[unknown]").is_err());
+    }
+
+    #[test]
+    fn test_apply_and_check_locked() {
+        let policy = Policy::parse(POLICY).unwrap();
+        let mut config = AppConfig::default();
+        policy.apply_defaults(&mut config);
+        policy.apply_locked(&mut config);
+        assert_eq!(config.keystroke_template, "{barcode}{TAB}");
+        assert!(config.require_encryption);
+        assert_eq!(config.input_mode, InputMode::Type);
+        assert!(policy.check(&config).is_ok());
+
+        config.keystroke_template = "{barcode}".to_string();
+        assert!(policy.check(&config).is_ok(), "defaults stay editable");
+
+        config.input_mode = InputMode::Paste;
+        assert!(policy.check(&config).unwrap_err().contains("input_mode"));
+    }
+}
@@ -0,0 +1,187 @@
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use crate::keyboard::InputMode;
+use crate::template::Template;
+use crate::transform::TransformRule;
+use crate::window::FocusedWindow;
+
+/// Keyboard output settings for one application, chosen by the focused window.
+/// Stored in `AppConfig::output_profiles`; the first matching profile wins.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutputProfile {
+    pub name: String,
+    /// Regex matched case-insensitively against the window class
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window_class: Option<String>,
+    /// Regex matched case-insensitively against the window title
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window_title: Option<String>,
+    /// Never type into matching windows
+    #[serde(default)]
+    pub block: bool,
+    /// Overrides `AppConfig::input_mode`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_mode: Option<InputMode>,
+    /// Overrides the global and per-device keystroke templates
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keystroke_template: Option<String>,
+    /// Extra rules applied after the global transform rules, for typing only
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transform_rules: Vec<TransformRule>,
+}
+
+/// Profiles as read and written by the settings UI
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputProfiles {
+    pub profiles: Vec<OutputProfile>,
+    /// Only type into windows matched by a (non-blocking) profile
+    pub block_unmatched_windows: bool,
+}
+
+fn window_regex(pattern: &str) -> Result<Regex, String> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .build()
+        .map_err(|e| format!("Invalid window pattern '{}': {}", pattern, e))
+}
+
+fn field_matches(regex: Option<&Regex>, value: Option<&str>) -> bool {
+    match regex {
+        Some(regex) => value.is_some_and(|value| regex.is_match(value)),
+        None => true,
+    }
+}
+
+/// An output profile with its window patterns compiled
+#[derive(Debug)]
+pub struct CompiledProfile {
+    profile: OutputProfile,
+    window_class: Option<Regex>,
+    window_title: Option<Regex>,
+}
+
+impl OutputProfile {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.window_class.is_none() && self.window_title.is_none() {
+            return Err(format!("Profile '{}' needs a window class or title pattern", self.name));
+        }
+        self.compile()?;
+        if let Some(ref template) = self.keystroke_template {
+            Template::parse(template)?;
+        }
+        for rule in &self.transform_rules {
+            rule.compile()?;
+        }
+        Ok(())
+    }
+
+    /// Compiles the window patterns
+    pub fn compile(&self) -> Result<CompiledProfile, String> {
+        let compile = |pattern: &Option<String>| pattern.as_deref().map(window_regex).transpose();
+        Ok(CompiledProfile {
+            profile: self.clone(),
+            window_class: compile(&self.window_class)?,
+            window_title: compile(&self.window_title)?,
+        })
+    }
+}
+
+impl CompiledProfile {
+    pub fn matches(&self, window: &FocusedWindow) -> bool {
+        (self.window_class.is_some() || self.window_title.is_some())
+            && field_matches(self.window_class.as_ref(), window.class.as_deref())
+            && field_matches(self.window_title.as_ref(), window.title.as_deref())
+    }
+}
+
+/// Compiles the profiles, skipping (and logging) invalid ones.
+/// Patterns are validated when set, so this only skips profiles from hand-edited configs.
+pub fn compile_profiles(profiles: &[OutputProfile]) -> Vec<CompiledProfile> {
+    profiles
+        .iter()
+        .filter_map(|profile| match profile.compile() {
+            Ok(compiled) => Some(compiled),
+            Err(e) => {
+                log::error!("Skipping output profile '{}': {}", profile.name, e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// The last profile list seen and its compiled form, so scans do not rebuild the patterns
+static COMPILED: Mutex<Option<(Vec<OutputProfile>, Vec<CompiledProfile>)>> = Mutex::new(None);
+
+/// First profile matching the focused window
+pub fn select(profiles: &[OutputProfile], window: Option<&FocusedWindow>) -> Option<OutputProfile> {
+    let window = window?;
+    let mut guard = COMPILED.lock().unwrap();
+    if !matches!(guard.as_ref(), Some((source, _)) if source == profiles) {
+        *guard = Some((profiles.to_vec(), compile_profiles(profiles)));
+    }
+    let (_, compiled) = guard.as_ref()?;
+    compiled.iter().find(|profile| profile.matches(window)).map(|profile| profile.profile.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(name: &str, class: Option<&str>, title: Option<&str>, block: bool) -> OutputProfile {
+        OutputProfile {
+            name: name.to_string(),
+            window_class: class.map(String::from),
+            window_title: title.map(String::from),
+            block,
+            input_mode: None,
+            keystroke_template: None,
+            transform_rules: Vec::new(),
+        }
+    }
+
+    fn window(class: &str, title: &str) -> FocusedWindow {
+        FocusedWindow { class: Some(class.to_string()), title: Some(title.to_string()) }
+    }
+
+    #[test]
+    fn test_select_first_matching_profile() {
+        let profiles = vec![
+            profile("chat", Some("^(slack|discord)$"), None, true),
+            profile("erp", Some("firefox"), Some("ERP"), false),
+            profile("browser", Some("firefox"), None, false),
+        ];
+
+        let name = |w: FocusedWindow| select(&profiles, Some(&w)).map(|p| p.name.clone());
+        assert_eq!(name(window("Slack", "general")), Some("chat".to_string()));
+        assert_eq!(name(window("firefox", "Orders - erp.local")), Some("erp".to_string()));
+        assert_eq!(name(window("Firefox", "News")), Some("browser".to_string()));
+        assert_eq!(name(window("gnome-terminal", "bash")), None);
+        assert!(select(&profiles, None).is_none());
+    }
+
+    #[test]
+    fn test_select_skips_invalid_profiles() {
+        let profiles = vec![
+            profile("broken", Some("("), None, true),
+            profile("erp", Some("firefox"), None, false),
+        ];
+        let selected = select(&profiles, Some(&window("firefox", "Orders")));
+        assert_eq!(selected.map(|p| p.name), Some("erp".to_string()));
+
+        // A changed profile list is compiled again
+        let profiles = vec![profile("office", Some("libreoffice"), None, false)];
+        assert!(select(&profiles, Some(&window("firefox", "Orders"))).is_none());
+        assert!(select(&profiles, Some(&window("libreoffice", "Sheet"))).is_some());
+    }
+
+    #[test]
+    fn test_validate_profile() {
+        assert!(profile("any", None, None, false).validate().is_err());
+        assert!(profile("bad", Some("("), None, false).validate().is_err());
+
+        let mut with_template = profile("ok", Some("libreoffice"), None, false);
+        with_template.keystroke_template = Some("{barcode}{TAB}".to_string());
+        assert!(with_template.validate().is_ok());
+    }
+}
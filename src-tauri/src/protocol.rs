@@ -0,0 +1,339 @@
+use serde::{Deserialize, Serialize};
+use crate::models::{CommandAck, DeviceCommand, EncryptedFrame, HandshakeRequest, PairRequest, ReconnectRequest, RefreshTokenRequest, ScanBatchMessage, ScanMessage};
+
+/// Oldest protocol version the desktop still speaks
+pub const PROTOCOL_VERSION_MIN: u32 = 1;
+/// Newest protocol version the desktop speaks
+///
+/// 1: handshake, pair, reconnect, scan
+/// 2: auth token expiry and `refresh_token`
+/// 3: `reconnect_ack` may carry a rotated `auth_token` (per-device key migration)
+/// 4: `device_secret` in acks and `encrypted` envelopes after pair/reconnect
+/// 5: `scan_batch` for offline queues and `scanId` de-duplication
+/// 6: desktop-to-phone `command` messages answered with `command_ack`
+pub const PROTOCOL_VERSION_MAX: u32 = 6;
+
+/// First protocol version whose clients understand `command` messages
+pub const COMMANDS_MIN_PROTOCOL_VERSION: u32 = 6;
+
+/// First protocol version whose clients accept a rotated token in `reconnect_ack`
+pub const KEY_ROTATION_MIN_PROTOCOL_VERSION: u32 = 3;
+
+/// Most scans accepted in one `scan_batch`; phones split larger queues
+pub const MAX_SCAN_BATCH_SIZE: usize = 500;
+
+/// Messages sent by the mobile app, tagged by their `action` field
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Handshake(HandshakeRequest),
+    Pair(PairRequest),
+    Reconnect(ReconnectRequest),
+    Scan(ScanMessage),
+    ScanBatch(ScanBatchMessage),
+    RefreshToken(RefreshTokenRequest),
+    CommandAck(CommandAck),
+    Encrypted(EncryptedFrame),
+    #[serde(other)]
+    Unknown,
+}
+
+/// Why an incoming frame could not be turned into a `ClientMessage`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// Not JSON, or no string `action` field
+    InvalidMessage(String),
+    /// The action is known but its fields are missing or malformed
+    InvalidAction { action: String, reason: String },
+    UnknownAction(String),
+}
+
+impl ClientMessage {
+    pub fn parse(text: &str) -> Result<Self, ParseError> {
+        let json: serde_json::Value = serde_json::from_str(text)
+            .map_err(|e| ParseError::InvalidMessage(format!("Invalid JSON: {}", e)))?;
+
+        let action = json.get("action")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| ParseError::InvalidMessage("Missing 'action' field".to_string()))?
+            .to_string();
+
+        match serde_json::from_value::<ClientMessage>(json) {
+            Ok(ClientMessage::Unknown) => Err(ParseError::UnknownAction(action)),
+            Ok(message) => Ok(message),
+            Err(e) => Err(ParseError::InvalidAction { action, reason: e.to_string() }),
+        }
+    }
+}
+
+/// Machine-readable error codes carried by `ServerMessage::Error`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    InvalidMessage,
+    UnknownAction,
+    UnsupportedProtocolVersion,
+    InvalidPairingToken,
+    InvalidToken,
+    TokenExpired,
+    NotAuthenticated,
+    EncryptionRequired,
+    InvalidEnvelope,
+    ReplayDetected,
+    /// Too many attempts or messages; see `retryAfterSecs`
+    RateLimited,
+    MissingPayload,
+    ServerError,
+}
+
+/// Outcome of one item in a `scan_batch`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanItemStatus {
+    /// Delivered for output
+    Accepted,
+    /// Already delivered earlier; safe for the phone to drop
+    Duplicate,
+    /// Not delivered (e.g. empty barcode); retrying will not help
+    Rejected,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanItemResult {
+    #[serde(rename = "scanId")]
+    pub scan_id: String,
+    pub status: ScanItemStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReconnectStatus {
+    Connected,
+    Unauthorized,
+    InvalidToken,
+    TokenExpired,
+}
+
+/// Messages sent by the desktop, tagged by their `action` field
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum ServerMessage {
+    HandshakeAck {
+        status: &'static str,
+        #[serde(rename = "clientId")]
+        client_id: usize,
+        #[serde(rename = "protocolVersion")]
+        protocol_version: u32,
+        #[serde(rename = "minProtocolVersion")]
+        min_protocol_version: u32,
+        #[serde(rename = "maxProtocolVersion")]
+        max_protocol_version: u32,
+        timestamp: i64,
+    },
+    PairAck {
+        status: &'static str,
+        auth_token: String,
+        device_id: String,
+        /// Unix time when `auth_token` expires (absent = never)
+        #[serde(skip_serializing_if = "Option::is_none")]
+        expires_at: Option<i64>,
+        /// Secret the device stores to derive encrypted session keys
+        #[serde(skip_serializing_if = "Option::is_none")]
+        device_secret: Option<String>,
+        /// Server nonce; present when an encrypted session starts after this ack
+        #[serde(skip_serializing_if = "Option::is_none")]
+        session_nonce: Option<String>,
+        timestamp: i64,
+    },
+    ReconnectAck {
+        status: ReconnectStatus,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        device_id: Option<String>,
+        /// Replacement token the device must store from now on
+        #[serde(skip_serializing_if = "Option::is_none")]
+        auth_token: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        expires_at: Option<i64>,
+        /// Sent together with a rotated token, as the secret changes with the key
+        #[serde(skip_serializing_if = "Option::is_none")]
+        device_secret: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        session_nonce: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        timestamp: Option<i64>,
+    },
+    ScanAck {
+        status: &'static str,
+        barcode: String,
+        /// Echo of the client's `scanId`; status is "duplicate" if it was already delivered
+        #[serde(rename = "scanId", skip_serializing_if = "Option::is_none")]
+        scan_id: Option<String>,
+    },
+    /// One result per batch item, in the order they were sent
+    ScanBatchAck {
+        results: Vec<ScanItemResult>,
+        timestamp: i64,
+    },
+    RefreshTokenAck {
+        status: &'static str,
+        auth_token: String,
+        device_id: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        expires_at: Option<i64>,
+        /// Sent when the refresh moved the device onto its own key
+        #[serde(skip_serializing_if = "Option::is_none")]
+        device_secret: Option<String>,
+        timestamp: i64,
+    },
+    /// Desktop-initiated command, answered by the phone with `command_ack`
+    Command {
+        #[serde(rename = "commandId")]
+        command_id: String,
+        #[serde(flatten)]
+        command: DeviceCommand,
+        timestamp: i64,
+    },
+    Encrypted {
+        seq: u64,
+        payload: String,
+    },
+    Error {
+        code: ErrorCode,
+        message: String,
+        #[serde(rename = "minProtocolVersion", skip_serializing_if = "Option::is_none")]
+        min_protocol_version: Option<u32>,
+        #[serde(rename = "maxProtocolVersion", skip_serializing_if = "Option::is_none")]
+        max_protocol_version: Option<u32>,
+        #[serde(rename = "retryAfterSecs", skip_serializing_if = "Option::is_none")]
+        retry_after_secs: Option<i64>,
+    },
+}
+
+impl ServerMessage {
+    pub fn error(code: ErrorCode, message: impl Into<String>) -> Self {
+        ServerMessage::Error {
+            code,
+            message: message.into(),
+            min_protocol_version: None,
+            max_protocol_version: None,
+            retry_after_secs: None,
+        }
+    }
+
+    /// Rejection sent while a client is throttled or banned
+    pub fn rate_limited(message: impl Into<String>, retry_after_secs: i64) -> Self {
+        ServerMessage::Error {
+            code: ErrorCode::RateLimited,
+            message: message.into(),
+            min_protocol_version: None,
+            max_protocol_version: None,
+            retry_after_secs: Some(retry_after_secs),
+        }
+    }
+
+    /// Rejection sent when no common protocol version exists
+    pub fn unsupported_version(message: impl Into<String>) -> Self {
+        ServerMessage::Error {
+            code: ErrorCode::UnsupportedProtocolVersion,
+            message: message.into(),
+            min_protocol_version: Some(PROTOCOL_VERSION_MIN),
+            max_protocol_version: Some(PROTOCOL_VERSION_MAX),
+            retry_after_secs: None,
+        }
+    }
+}
+
+/// Picks the highest version both sides support. `protocolVersion` is the newest
+/// version the client speaks; clients that predate versioning send none and are
+/// treated as version 1, and a missing `minProtocolVersion` means 1.
+pub fn negotiate_version(request: &HandshakeRequest) -> Result<u32, String> {
+    let client_max = request.protocol_version.unwrap_or(1);
+    let client_min = request.min_protocol_version.unwrap_or(1).min(client_max);
+
+    let version = client_max.min(PROTOCOL_VERSION_MAX);
+    if version < client_min.max(PROTOCOL_VERSION_MIN) {
+        return Err(format!(
+            "Client supports protocol versions {}-{}, server supports {}-{}",
+            client_min, client_max, PROTOCOL_VERSION_MIN, PROTOCOL_VERSION_MAX
+        ));
+    }
+
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handshake(min: Option<u32>, max: Option<u32>) -> HandshakeRequest {
+        HandshakeRequest {
+            protocol_version: max,
+            min_protocol_version: min,
+        }
+    }
+
+    #[test]
+    fn test_negotiate_legacy_client() {
+        assert_eq!(negotiate_version(&handshake(None, None)), Ok(1));
+    }
+
+    #[test]
+    fn test_negotiate_newer_client_downgrades() {
+        let request = handshake(Some(1), Some(PROTOCOL_VERSION_MAX + 5));
+        assert_eq!(negotiate_version(&request), Ok(PROTOCOL_VERSION_MAX));
+    }
+
+    #[test]
+    fn test_negotiate_unsupported_version() {
+        let request = handshake(Some(PROTOCOL_VERSION_MAX + 1), Some(PROTOCOL_VERSION_MAX + 2));
+        assert!(negotiate_version(&request).is_err());
+    }
+
+    #[test]
+    fn test_parse_messages() {
+        let scan = r#"{"action":"scan","deviceId":"d1","timestamp":1,"payload":{"barcode":"123"}}"#;
+        assert!(matches!(ClientMessage::parse(scan), Ok(ClientMessage::Scan(_))));
+
+        let batch = r#"{"action":"scan_batch","deviceId":"d1","scans":[{"scanId":"s1","timestamp":1,"payload":{"barcode":"123"}}]}"#;
+        assert!(matches!(ClientMessage::parse(batch), Ok(ClientMessage::ScanBatch(b)) if b.scans.len() == 1));
+
+        let unknown = r#"{"action":"teleport"}"#;
+        assert_eq!(ClientMessage::parse(unknown).unwrap_err(), ParseError::UnknownAction("teleport".to_string()));
+
+        let invalid = r#"{"action":"pair","deviceId":"d1"}"#;
+        assert!(matches!(ClientMessage::parse(invalid), Err(ParseError::InvalidAction { .. })));
+
+        assert!(matches!(ClientMessage::parse("not json"), Err(ParseError::InvalidMessage(_))));
+
+        let ack = r#"{"action":"command_ack","commandId":"c1","status":"executed"}"#;
+        assert!(matches!(ClientMessage::parse(ack), Ok(ClientMessage::CommandAck(_))));
+    }
+
+    #[test]
+    fn test_serialize_command() {
+        let message = ServerMessage::Command {
+            command_id: "c1".to_string(),
+            command: DeviceCommand::Vibrate { duration_ms: Some(200) },
+            timestamp: 1,
+        };
+        let json = serde_json::to_value(&message).unwrap();
+        assert_eq!(json, serde_json::json!({
+            "action": "command",
+            "commandId": "c1",
+            "command": "vibrate",
+            "durationMs": 200,
+            "timestamp": 1,
+        }));
+
+        let message = ServerMessage::Command {
+            command_id: "c2".to_string(),
+            command: DeviceCommand::TriggerScan,
+            timestamp: 1,
+        };
+        assert_eq!(serde_json::to_value(&message).unwrap()["command"], "trigger_scan");
+    }
+}
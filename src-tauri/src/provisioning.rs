@@ -0,0 +1,222 @@
+//! Signed settings bundles for provisioning many machines alike. An admin exports
+//! a bundle from a configured machine with an Ed25519 signing key; machines trust
+//! the matching public key through the policy file (or an explicit key) on import.
+
+use std::collections::HashMap;
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use crate::policy::{self, Policy};
+use crate::security::AuthorizedDevice;
+use crate::storage::AppConfig;
+
+const BUNDLE_FORMAT: &str = "scanlink-bundle";
+const BUNDLE_VERSION: u32 = 1;
+
+/// Bundle file. The payload is kept as the exact signed JSON text.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Bundle {
+    pub format: String,
+    pub version: u32,
+    /// `BundlePayload` as JSON
+    pub payload: String,
+    /// Signer's Ed25519 public key (base64)
+    pub public_key: String,
+    /// Ed25519 signature of `payload` (base64)
+    pub signature: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BundlePayload {
+    created_at: String,
+    /// `AppConfig` fields without secrets
+    settings: Map<String, Value>,
+    /// Pre-authorized devices with their token keys
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    authorized_devices: HashMap<String, AuthorizedDevice>,
+}
+
+/// Outcome of an import, for the UI and the CLI
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleImportResult {
+    pub applied_settings: Vec<String>,
+    /// Settings left alone because the policy locks them
+    pub locked_settings: Vec<String>,
+    pub devices_added: usize,
+}
+
+/// New signing key: (PKCS#8 private key, public key), both base64
+pub fn generate_signing_key() -> Result<(String, String), String> {
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+        .map_err(|e| format!("Failed to generate signing key: {}", e))?;
+    let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref())
+        .map_err(|e| format!("Failed to generate signing key: {}", e))?;
+    Ok((BASE64.encode(pkcs8.as_ref()), BASE64.encode(pair.public_key().as_ref())))
+}
+
+fn key_pair(signing_key: &str) -> Result<Ed25519KeyPair, String> {
+    let pkcs8 = BASE64.decode(signing_key.trim())
+        .map_err(|e| format!("Invalid signing key: {}", e))?;
+    Ed25519KeyPair::from_pkcs8(&pkcs8).map_err(|e| format!("Invalid signing key: {}", e))
+}
+
+/// Signs the config's settings and, if requested, its paired devices
+pub fn export(config: &AppConfig, signing_key: &str, include_devices: bool) -> Result<Bundle, String> {
+    let pair = key_pair(signing_key)?;
+
+    let mut settings = match serde_json::to_value(config).map_err(|e| e.to_string())? {
+        Value::Object(settings) => settings,
+        _ => return Err("Config is not an object".to_string()),
+    };
+    settings.retain(|key, _| !policy::PROTECTED_FIELDS.contains(&key.as_str()));
+
+    let mut authorized_devices = HashMap::new();
+    if include_devices {
+        for (device_id, device) in &config.authorized_devices {
+            if device.secret_key.is_some() {
+                authorized_devices.insert(device_id.clone(), device.clone());
+            } else {
+                log::warn!("Not exporting device {}: it still uses the legacy shared key", device_id);
+            }
+        }
+        log::warn!("The bundle contains the token keys of {} device(s), keep it secret", authorized_devices.len());
+    }
+
+    let payload = BundlePayload {
+        created_at: chrono::Utc::now().to_rfc3339(),
+        settings,
+        authorized_devices,
+    };
+    let payload = serde_json::to_string_pretty(&payload).map_err(|e| e.to_string())?;
+
+    Ok(Bundle {
+        format: BUNDLE_FORMAT.to_string(),
+        version: BUNDLE_VERSION,
+        signature: BASE64.encode(pair.sign(payload.as_bytes()).as_ref()),
+        public_key: BASE64.encode(pair.public_key().as_ref()),
+        payload,
+    })
+}
+
+/// Checks the signature and that the signer is trusted
+fn verify(bundle: &Bundle, trusted_keys: &[String]) -> Result<(), String> {
+    if bundle.format != BUNDLE_FORMAT || bundle.version != BUNDLE_VERSION {
+        return Err(format!("Unsupported bundle format {} v{}", bundle.format, bundle.version));
+    }
+    if trusted_keys.is_empty() {
+        return Err("No trusted bundle keys: add the signer's public key to trusted_bundle_keys in the policy".to_string());
+    }
+    if !trusted_keys.iter().any(|key| key.trim() == bundle.public_key) {
+        return Err("Bundle is signed by an untrusted key".to_string());
+    }
+
+    let public_key = BASE64.decode(&bundle.public_key).map_err(|e| format!("Invalid public key: {}", e))?;
+    let signature = BASE64.decode(&bundle.signature).map_err(|e| format!("Invalid signature: {}", e))?;
+    UnparsedPublicKey::new(&ED25519, public_key)
+        .verify(bundle.payload.as_bytes(), &signature)
+        .map_err(|_| "Bundle signature is invalid, the file was modified or corrupted".to_string())
+}
+
+/// Verifies the bundle and returns the config with its settings and devices
+/// merged in. Settings locked by the policy keep their value.
+pub fn import(
+    bundle: &Bundle,
+    config: &AppConfig,
+    policy: &Policy,
+    trusted_key: Option<&str>,
+) -> Result<(AppConfig, BundleImportResult), String> {
+    let mut trusted_keys = policy.trusted_bundle_keys.clone();
+    trusted_keys.extend(trusted_key.map(String::from));
+    verify(bundle, &trusted_keys)?;
+
+    let payload: BundlePayload = serde_json::from_str(&bundle.payload)
+        .map_err(|e| format!("Invalid bundle payload: {}", e))?;
+
+    let mut updated = config.clone();
+    let mut result = BundleImportResult::default();
+    for (key, value) in &payload.settings {
+        if policy.is_locked(key) {
+            result.locked_settings.push(key.clone());
+            continue;
+        }
+        match policy::with_field(&updated, key, value) {
+            Ok(with_setting) => {
+                updated = with_setting;
+                result.applied_settings.push(key.clone());
+            }
+            // Bundles from newer versions may carry settings this one does not know
+            Err(e) => log::warn!("Skipping bundle setting: {}", e),
+        }
+    }
+
+    for (device_id, device) in payload.authorized_devices {
+        if device.secret_key.is_none() || device.device_id != device_id {
+            log::warn!("Skipping invalid bundle device {}", device_id);
+            continue;
+        }
+        updated.add_device(device);
+        result.devices_added += 1;
+    }
+
+    log::info!(
+        "Imported bundle created {}: {} settings, {} locked, {} devices",
+        payload.created_at, result.applied_settings.len(), result.locked_settings.len(), result.devices_added
+    );
+    Ok((updated, result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source_config() -> AppConfig {
+        let mut config = AppConfig {
+            master_token: Some("master".to_string()),
+            require_encryption: true,
+            keystroke_template: "{barcode}{TAB}".to_string(),
+            ..AppConfig::default()
+        };
+        config.add_device(AuthorizedDevice::new("phone".to_string(), "Phone".to_string(), None));
+        config
+    }
+
+    #[test]
+    fn test_export_import_round_trip() {
+        let (signing_key, public_key) = generate_signing_key().unwrap();
+        let bundle = export(&source_config(), &signing_key, true).unwrap();
+        assert!(!bundle.payload.contains("master"), "master token is never exported");
+
+        let policy = Policy::parse(&format!(
+            "trusted_bundle_keys = [\"{}\"]\n[locked]\nkeystroke_template = \"{{barcode}}\"",
+            public_key
+        ))
+        .unwrap();
+        let (imported, result) = import(&bundle, &AppConfig::default(), &policy, None).unwrap();
+
+        assert!(imported.require_encryption);
+        assert_eq!(imported.keystroke_template, AppConfig::default().keystroke_template);
+        assert_eq!(result.locked_settings, vec!["keystroke_template".to_string()]);
+        assert_eq!(result.devices_added, 1);
+        assert!(imported.device_secret_key("phone").is_some());
+        assert!(imported.master_token.is_none());
+    }
+
+    #[test]
+    fn test_import_rejects_untrusted_or_tampered() {
+        let (signing_key, public_key) = generate_signing_key().unwrap();
+        let (_, other_key) = generate_signing_key().unwrap();
+        let policy = Policy::default();
+        let config = AppConfig::default();
+
+        let mut bundle = export(&source_config(), &signing_key, false).unwrap();
+        assert!(import(&bundle, &config, &policy, None).is_err(), "no trusted keys");
+        assert!(import(&bundle, &config, &policy, Some(&other_key)).is_err());
+        assert!(import(&bundle, &config, &policy, Some(&public_key)).is_ok());
+
+        bundle.payload = bundle.payload.replace("\"require_encryption\": true", "\"require_encryption\": false");
+        assert!(import(&bundle, &config, &policy, Some(&public_key)).is_err());
+    }
+}
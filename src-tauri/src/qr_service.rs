@@ -1,25 +1,11 @@
 use qrcode::QrCode;
+use qrcode::render::unicode;
 use image::Luma;
 use base64::{Engine as _, engine::general_purpose};
-use rand::Rng;
+use std::net::SocketAddr;
 use local_ip_address::local_ip;
 use crate::models::{ConnectionInfo, QRCodeData};
 
-pub fn generate_token() -> String {
-    const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
-                            abcdefghijklmnopqrstuvwxyz\
-                            0123456789";
-    const TOKEN_LEN: usize = 32;
-    let mut rng = rand::thread_rng();
-
-    (0..TOKEN_LEN)
-        .map(|_| {
-            let idx = rng.gen_range(0..CHARSET.len());
-            CHARSET[idx] as char
-        })
-        .collect()
-}
-
 pub fn get_local_ip() -> Result<String, String> {
     match local_ip() {
         Ok(ip) => Ok(ip.to_string()),
@@ -27,6 +13,16 @@ pub fn get_local_ip() -> Result<String, String> {
     }
 }
 
+/// Address phones should connect to: the bound interface, or the machine's
+/// LAN address when listening on all interfaces
+pub fn advertised_ip(addr: SocketAddr) -> Result<String, String> {
+    if addr.ip().is_unspecified() {
+        get_local_ip()
+    } else {
+        Ok(addr.ip().to_string())
+    }
+}
+
 pub fn generate_qr_code(connection_info: &ConnectionInfo) -> Result<QRCodeData, String> {
     // Serialize connection info to JSON
     let json_data = serde_json::to_string(connection_info)
@@ -57,5 +53,22 @@ pub fn generate_qr_code(connection_info: &ConnectionInfo) -> Result<QRCodeData,
     Ok(QRCodeData {
         qr_base64,
         connection_info: connection_info.clone(),
+        validity: None,
     })
 }
+
+/// Renders the pairing QR code as Unicode half-blocks for printing in a terminal
+pub fn render_qr_terminal(connection_info: &ConnectionInfo) -> Result<String, String> {
+    let json_data = serde_json::to_string(connection_info)
+        .map_err(|e| format!("Failed to serialize connection info: {}", e))?;
+
+    let code = QrCode::new(json_data.as_bytes())
+        .map_err(|e| format!("Failed to generate QR code: {}", e))?;
+
+    // Inverted colors so the code scans on dark terminal backgrounds
+    Ok(code.render::<unicode::Dense1x2>()
+        .dark_color(unicode::Dense1x2::Light)
+        .light_color(unicode::Dense1x2::Dark)
+        .quiet_zone(true)
+        .build())
+}
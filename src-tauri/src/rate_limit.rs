@@ -0,0 +1,232 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use crate::models::LockoutEvent;
+
+/// Window for counting pair/reconnect/scan authentication attempts
+const ATTEMPT_WINDOW_SECS: i64 = 60;
+/// Attempts allowed per IP within the window
+const MAX_ATTEMPTS: usize = 10;
+/// Window for counting failed attempts towards a ban
+const FAILURE_WINDOW_SECS: i64 = 10 * 60;
+/// Failures within the window that trigger a temporary ban
+const MAX_FAILURES: usize = 5;
+/// First ban length; each further ban of the same key doubles it
+const BASE_BAN_SECS: i64 = 15 * 60;
+const MAX_BAN_SECS: i64 = 24 * 60 * 60;
+/// Window for messages from connections that have not authenticated yet
+const MESSAGE_WINDOW_SECS: i64 = 10;
+/// Unauthenticated messages allowed per IP within the message window
+const MAX_UNAUTHENTICATED_MESSAGES: usize = 30;
+/// Above this many tracked keys, idle ones are dropped
+const PRUNE_THRESHOLD: usize = 10_000;
+
+#[derive(Default)]
+struct Entry {
+    attempts: VecDeque<i64>,
+    failures: VecDeque<i64>,
+    messages: VecDeque<i64>,
+    banned_until: Option<i64>,
+    /// Number of bans so far, used to lengthen repeated bans
+    bans: u32,
+    last_seen: i64,
+}
+
+impl Entry {
+    fn ban_remaining(&self, now: i64) -> Option<i64> {
+        self.banned_until
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+    }
+}
+
+/// Why a request was not processed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Throttled {
+    /// Seconds until the client may try again
+    pub retry_after_secs: i64,
+    /// True for a ban after repeated failures, false for plain rate limiting
+    pub banned: bool,
+}
+
+/// Drops timestamps older than the window and returns how many remain
+fn trim_window(times: &mut VecDeque<i64>, now: i64, window: i64) -> usize {
+    while times.front().is_some_and(|t| now - t >= window) {
+        times.pop_front();
+    }
+    times.len()
+}
+
+/// Throttles authentication attempts per IP and bans addresses that keep
+/// failing, so the pairing token cannot be brute-forced.
+///
+/// Device ids are never used as keys: they are whatever the client claims, so
+/// counting failures against them would let anyone lock out a real phone.
+#[derive(Default)]
+pub struct RateLimiter {
+    entries: HashMap<IpAddr, Entry>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn entry(&mut self, ip: IpAddr, now: i64) -> &mut Entry {
+        let entry = self.entries.entry(ip).or_default();
+        entry.last_seen = now;
+        entry
+    }
+
+    /// Seconds left on a ban of this IP, if any
+    pub fn ban_remaining(&self, ip: IpAddr) -> Option<i64> {
+        self.ban_remaining_at(ip, chrono::Utc::now().timestamp())
+    }
+
+    fn ban_remaining_at(&self, ip: IpAddr, now: i64) -> Option<i64> {
+        self.entries
+            .get(&ip)
+            .and_then(|entry| entry.ban_remaining(now))
+    }
+
+    /// Counts an authentication attempt, rejecting it if the address is banned or over the limit
+    pub fn check_attempt(&mut self, ip: IpAddr) -> Result<(), Throttled> {
+        self.check_attempt_at(ip, chrono::Utc::now().timestamp())
+    }
+
+    fn check_attempt_at(&mut self, ip: IpAddr, now: i64) -> Result<(), Throttled> {
+        self.prune(now);
+
+        let entry = self.entry(ip, now);
+        if let Some(remaining) = entry.ban_remaining(now) {
+            return Err(Throttled { retry_after_secs: remaining, banned: true });
+        }
+        if trim_window(&mut entry.attempts, now, ATTEMPT_WINDOW_SECS) >= MAX_ATTEMPTS {
+            let oldest = entry.attempts.front().copied().unwrap_or(now);
+            return Err(Throttled {
+                retry_after_secs: (oldest + ATTEMPT_WINDOW_SECS - now).max(1),
+                banned: false,
+            });
+        }
+
+        entry.attempts.push_back(now);
+        Ok(())
+    }
+
+    /// Counts a failed attempt. Returns the lockout it triggered, if any.
+    /// Failures are kept after a success, since many phones may share one address.
+    pub fn record_failure(&mut self, ip: IpAddr) -> Option<LockoutEvent> {
+        self.record_failure_at(ip, chrono::Utc::now().timestamp())
+    }
+
+    fn record_failure_at(&mut self, ip: IpAddr, now: i64) -> Option<LockoutEvent> {
+        let entry = self.entry(ip, now);
+        entry.failures.push_back(now);
+        if trim_window(&mut entry.failures, now, FAILURE_WINDOW_SECS) < MAX_FAILURES {
+            return None;
+        }
+
+        let ban_secs = BASE_BAN_SECS
+            .saturating_mul(1 << entry.bans.min(16))
+            .min(MAX_BAN_SECS);
+        entry.banned_until = Some(now + ban_secs);
+        entry.bans += 1;
+        entry.failures.clear();
+
+        Some(LockoutEvent {
+            ip: ip.to_string(),
+            failures: MAX_FAILURES as u32,
+            locked_until: now + ban_secs,
+        })
+    }
+
+    /// Counts a message from a connection that has not authenticated yet
+    pub fn check_message(&mut self, ip: IpAddr) -> Result<(), Throttled> {
+        self.check_message_at(ip, chrono::Utc::now().timestamp())
+    }
+
+    fn check_message_at(&mut self, ip: IpAddr, now: i64) -> Result<(), Throttled> {
+        let entry = self.entry(ip, now);
+        if let Some(remaining) = entry.ban_remaining(now) {
+            return Err(Throttled { retry_after_secs: remaining, banned: true });
+        }
+        if trim_window(&mut entry.messages, now, MESSAGE_WINDOW_SECS) >= MAX_UNAUTHENTICATED_MESSAGES {
+            return Err(Throttled { retry_after_secs: MESSAGE_WINDOW_SECS, banned: false });
+        }
+        entry.messages.push_back(now);
+        Ok(())
+    }
+
+    /// Forgets keys that are neither banned nor recently active
+    fn prune(&mut self, now: i64) {
+        if self.entries.len() < PRUNE_THRESHOLD {
+            return;
+        }
+        self.entries.retain(|_, entry| {
+            entry.ban_remaining(now).is_some() || now - entry.last_seen < FAILURE_WINDOW_SECS
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip() -> IpAddr {
+        "192.168.1.50".parse().unwrap()
+    }
+
+    fn other_ip() -> IpAddr {
+        "192.168.1.51".parse().unwrap()
+    }
+
+    #[test]
+    fn test_attempts_are_rate_limited() {
+        let mut limiter = RateLimiter::new();
+        for _ in 0..MAX_ATTEMPTS {
+            assert!(limiter.check_attempt_at(ip(), 0).is_ok());
+        }
+        let throttled = limiter.check_attempt_at(ip(), 1).unwrap_err();
+        assert!(!throttled.banned);
+        assert!(limiter.check_attempt_at(other_ip(), 1).is_ok());
+
+        // The window slides
+        assert!(limiter.check_attempt_at(ip(), ATTEMPT_WINDOW_SECS).is_ok());
+    }
+
+    #[test]
+    fn test_repeated_failures_ban_the_address() {
+        let mut limiter = RateLimiter::new();
+        for i in 0..MAX_FAILURES - 1 {
+            assert!(limiter.record_failure_at(ip(), i as i64).is_none());
+        }
+        let lockout = limiter.record_failure_at(ip(), 10).unwrap();
+        assert_eq!(lockout.ip, ip().to_string());
+
+        let throttled = limiter.check_attempt_at(ip(), 11).unwrap_err();
+        assert!(throttled.banned);
+        assert_eq!(throttled.retry_after_secs, BASE_BAN_SECS - 1);
+        assert!(limiter.check_attempt_at(other_ip(), 11).is_ok());
+        assert!(limiter.check_attempt_at(ip(), 10 + BASE_BAN_SECS).is_ok());
+    }
+
+    #[test]
+    fn test_repeated_bans_get_longer() {
+        let mut limiter = RateLimiter::new();
+        for _ in 0..MAX_FAILURES {
+            limiter.record_failure_at(ip(), 0);
+        }
+        for _ in 0..MAX_FAILURES {
+            limiter.record_failure_at(ip(), BASE_BAN_SECS);
+        }
+        assert_eq!(limiter.ban_remaining_at(ip(), BASE_BAN_SECS), Some(2 * BASE_BAN_SECS));
+    }
+
+    #[test]
+    fn test_unauthenticated_message_flood() {
+        let mut limiter = RateLimiter::new();
+        for _ in 0..MAX_UNAUTHENTICATED_MESSAGES {
+            assert!(limiter.check_message_at(ip(), 0).is_ok());
+        }
+        assert!(limiter.check_message_at(ip(), 0).is_err());
+    }
+}
@@ -1,7 +1,9 @@
 use aes_gcm::{
-    aead::{Aead, KeyInit, OsRng},
+    aead::{Aead, KeyInit, OsRng, Payload},
     Aes256Gcm, Nonce,
 };
+use hkdf::Hkdf;
+use sha2::Sha256;
 use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
 use rand::RngCore;
 use serde::{Deserialize, Serialize};
@@ -16,10 +18,13 @@ pub struct AuthorizedDevice {
     pub device_model: Option<String>,
     pub paired_at: String,
     pub last_seen: String,
+    /// This device's own token key (base64). None for devices paired before
+    /// per-device keys, which still use the legacy shared key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret_key: Option<String>,
 }
 
 impl AuthorizedDevice {
-    #[allow(dead_code)] // Reserved for future device management feature
     pub fn new(device_id: String, device_name: String, device_model: Option<String>) -> Self {
         let now = chrono::Utc::now().to_rfc3339();
         Self {
@@ -28,6 +33,15 @@ impl AuthorizedDevice {
             device_model,
             paired_at: now.clone(),
             last_seen: now,
+            secret_key: Some(generate_secret_key()),
+        }
+    }
+
+    /// Copy without key material, safe to hand to the frontend
+    pub fn without_secrets(&self) -> Self {
+        Self {
+            secret_key: None,
+            ..self.clone()
         }
     }
 }
@@ -39,8 +53,24 @@ pub fn generate_secret_key() -> String {
     BASE64.encode(key)
 }
 
+/// When the master pairing token (the one in the QR code) is replaced
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "policy", rename_all = "snake_case")]
+pub enum TokenRotation {
+    /// Only when the user regenerates it
+    #[default]
+    Never,
+    /// 24 hours after it was issued
+    Daily,
+    /// Once this many devices paired with it
+    AfterPairings { count: u32 },
+    /// After every pairing, making each QR code single-use
+    EveryPairing,
+}
+
+pub const DAILY_ROTATION_SECS: i64 = 24 * 60 * 60;
+
 /// Generates a random 32-character alphanumeric token
-#[allow(dead_code)] // Reserved for future master token feature
 pub fn generate_master_token() -> String {
     const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
                             abcdefghijklmnopqrstuvwxyz\
@@ -56,8 +86,7 @@ pub fn generate_master_token() -> String {
         .collect()
 }
 
-/// Encrypts a message using AES-256-GCM
-pub fn encrypt(secret_key: &str, plaintext: &str) -> Result<String, String> {
+fn cipher_from_key(secret_key: &str) -> Result<Aes256Gcm, String> {
     let key_bytes = BASE64.decode(secret_key)
         .map_err(|e| format!("Invalid secret key: {}", e))?;
 
@@ -65,14 +94,24 @@ pub fn encrypt(secret_key: &str, plaintext: &str) -> Result<String, String> {
         return Err("Secret key must be 32 bytes".to_string());
     }
 
-    let cipher = Aes256Gcm::new_from_slice(&key_bytes)
-        .map_err(|e| format!("Failed to create cipher: {}", e))?;
+    Aes256Gcm::new_from_slice(&key_bytes)
+        .map_err(|e| format!("Failed to create cipher: {}", e))
+}
+
+/// Encrypts a message using AES-256-GCM
+pub fn encrypt(secret_key: &str, plaintext: &str) -> Result<String, String> {
+    encrypt_with_aad(secret_key, plaintext, b"")
+}
+
+/// Encrypts a message using AES-256-GCM, authenticating `aad` alongside it
+pub fn encrypt_with_aad(secret_key: &str, plaintext: &str, aad: &[u8]) -> Result<String, String> {
+    let cipher = cipher_from_key(secret_key)?;
 
     let mut nonce_bytes = [0u8; NONCE_SIZE];
     OsRng.fill_bytes(&mut nonce_bytes);
     let nonce = Nonce::from_slice(&nonce_bytes);
 
-    let ciphertext = cipher.encrypt(nonce, plaintext.as_bytes())
+    let ciphertext = cipher.encrypt(nonce, Payload { msg: plaintext.as_bytes(), aad })
         .map_err(|e| format!("Encryption failed: {}", e))?;
 
     // Prepend nonce to ciphertext
@@ -84,15 +123,12 @@ pub fn encrypt(secret_key: &str, plaintext: &str) -> Result<String, String> {
 
 /// Decrypts a message using AES-256-GCM
 pub fn decrypt(secret_key: &str, encrypted: &str) -> Result<String, String> {
-    let key_bytes = BASE64.decode(secret_key)
-        .map_err(|e| format!("Invalid secret key: {}", e))?;
-
-    if key_bytes.len() != 32 {
-        return Err("Secret key must be 32 bytes".to_string());
-    }
+    decrypt_with_aad(secret_key, encrypted, b"")
+}
 
-    let cipher = Aes256Gcm::new_from_slice(&key_bytes)
-        .map_err(|e| format!("Failed to create cipher: {}", e))?;
+/// Decrypts a message using AES-256-GCM, failing if `aad` does not match
+pub fn decrypt_with_aad(secret_key: &str, encrypted: &str, aad: &[u8]) -> Result<String, String> {
+    let cipher = cipher_from_key(secret_key)?;
 
     let data = BASE64.decode(encrypted)
         .map_err(|e| format!("Invalid encrypted data: {}", e))?;
@@ -104,16 +140,63 @@ pub fn decrypt(secret_key: &str, encrypted: &str) -> Result<String, String> {
     let (nonce_bytes, ciphertext) = data.split_at(NONCE_SIZE);
     let nonce = Nonce::from_slice(nonce_bytes);
 
-    let plaintext = cipher.decrypt(nonce, ciphertext)
+    let plaintext = cipher.decrypt(nonce, Payload { msg: ciphertext, aad })
         .map_err(|_| "Decryption failed - invalid token or tampered data".to_string())?;
 
     String::from_utf8(plaintext)
         .map_err(|e| format!("Invalid UTF-8: {}", e))
 }
 
+fn hkdf_sha256(ikm: &[u8], salt: &[u8], info: &[u8]) -> Result<String, String> {
+    let hk = Hkdf::<Sha256>::new(Some(salt), ikm);
+    let mut okm = [0u8; 32];
+    hk.expand(info, &mut okm)
+        .map_err(|e| format!("Key derivation failed: {}", e))?;
+    Ok(BASE64.encode(okm))
+}
+
+/// Derives the secret handed to a device at pairing from its token key.
+/// The device never learns the token key itself, so it cannot mint tokens.
+pub fn derive_device_secret(device_key: &str) -> Result<String, String> {
+    let key_bytes = BASE64.decode(device_key)
+        .map_err(|e| format!("Invalid secret key: {}", e))?;
+    hkdf_sha256(&key_bytes, b"", b"scanlink-device-secret-v1")
+}
+
+/// Derives a per-connection session key from the device secret and both nonces
+pub fn derive_session_key(device_secret: &str, client_nonce: &str, server_nonce: &str) -> Result<String, String> {
+    let secret_bytes = BASE64.decode(device_secret)
+        .map_err(|e| format!("Invalid device secret: {}", e))?;
+    let mut salt = BASE64.decode(client_nonce)
+        .map_err(|e| format!("Invalid client nonce: {}", e))?;
+    salt.extend(BASE64.decode(server_nonce)
+        .map_err(|e| format!("Invalid server nonce: {}", e))?);
+    hkdf_sha256(&secret_bytes, &salt, b"scanlink-session-v1")
+}
+
+/// Generates a random 128-bit nonce for session key derivation
+pub fn generate_session_nonce() -> String {
+    let mut nonce = [0u8; 16];
+    OsRng.fill_bytes(&mut nonce);
+    BASE64.encode(nonce)
+}
+
+/// Why an auth token was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenError {
+    /// Tampered, wrong key, wrong device or malformed payload
+    Invalid,
+    /// Genuine token that is older than the configured lifetime
+    Expired,
+}
+
 /// Creates an authentication token for a device
 pub fn create_auth_token(device_id: &str, secret_key: &str) -> String {
-    let payload = format!("scanlink:{}:{}", device_id, chrono::Utc::now().timestamp());
+    create_auth_token_at(device_id, secret_key, chrono::Utc::now().timestamp())
+}
+
+fn create_auth_token_at(device_id: &str, secret_key: &str, issued_at: i64) -> String {
+    let payload = format!("scanlink:{}:{}", device_id, issued_at);
     match encrypt(secret_key, &payload) {
         Ok(token) => token,
         Err(e) => {
@@ -123,24 +206,49 @@ pub fn create_auth_token(device_id: &str, secret_key: &str) -> String {
     }
 }
 
-/// Validates an authentication token and returns true if valid
-pub fn validate_auth_token(token: &str, expected_device_id: &str, secret_key: &str) -> bool {
-    match decrypt(secret_key, token) {
-        Ok(payload) => {
-            // Format: "scanlink:device_id:timestamp"
-            let parts: Vec<&str> = payload.splitn(3, ':').collect();
-            if parts.len() >= 2 && parts[0] == "scanlink" && parts[1] == expected_device_id {
-                true
-            } else {
-                log::warn!("Auth token validation failed: invalid format or device_id mismatch");
-                false
-            }
-        }
-        Err(e) => {
-            log::warn!("Auth token validation failed: {}", e);
-            false
+/// Returns when a token issued at `issued_at` stops being valid (None = never)
+pub fn auth_token_expires_at(issued_at: i64, lifetime_secs: u64) -> Option<i64> {
+    if lifetime_secs == 0 {
+        None
+    } else {
+        Some(issued_at.saturating_add(lifetime_secs as i64))
+    }
+}
+
+/// Validates an authentication token and returns its issue timestamp.
+/// A `lifetime_secs` of 0 disables expiry.
+pub fn validate_auth_token(
+    token: &str,
+    expected_device_id: &str,
+    secret_key: &str,
+    lifetime_secs: u64,
+) -> Result<i64, TokenError> {
+    let payload = decrypt(secret_key, token).map_err(|e| {
+        log::warn!("Auth token validation failed: {}", e);
+        TokenError::Invalid
+    })?;
+
+    // Format: "scanlink:device_id:timestamp" (device ids may contain ':')
+    let (prefix_and_device, timestamp) = payload.rsplit_once(':').ok_or(TokenError::Invalid)?;
+    let device_id = prefix_and_device.strip_prefix("scanlink:").ok_or(TokenError::Invalid)?;
+    if device_id != expected_device_id {
+        log::warn!("Auth token validation failed: invalid format or device_id mismatch");
+        return Err(TokenError::Invalid);
+    }
+
+    let issued_at: i64 = timestamp.parse().map_err(|_| {
+        log::warn!("Auth token validation failed: invalid timestamp");
+        TokenError::Invalid
+    })?;
+
+    if let Some(expires_at) = auth_token_expires_at(issued_at, lifetime_secs) {
+        if chrono::Utc::now().timestamp() >= expires_at {
+            log::warn!("Auth token for device {} expired at {}", expected_device_id, expires_at);
+            return Err(TokenError::Expired);
         }
     }
+
+    Ok(issued_at)
 }
 
 #[cfg(test)]
@@ -162,24 +270,31 @@ mod tests {
     fn test_auth_token() {
         let key = generate_secret_key();
         let device_id = "test-device-123";
-        let master_token = generate_master_token();
 
-        let auth_token = create_auth_token(&key, device_id, &master_token).unwrap();
-        let validated_device = validate_auth_token(&key, &auth_token, &master_token).unwrap();
+        let auth_token = create_auth_token(device_id, &key);
+        let result = validate_auth_token(&auth_token, device_id, &key, 3600);
 
-        assert_eq!(device_id, validated_device);
+        assert!(result.is_ok());
     }
 
     #[test]
-    fn test_invalid_master_token() {
+    fn test_auth_token_wrong_device_or_key() {
         let key = generate_secret_key();
-        let device_id = "test-device-123";
-        let master_token = generate_master_token();
-        let wrong_token = generate_master_token();
+        let wrong_key = generate_secret_key();
+        let auth_token = create_auth_token("test-device-123", &key);
+
+        assert_eq!(validate_auth_token(&auth_token, "other-device", &key, 0), Err(TokenError::Invalid));
+        assert_eq!(validate_auth_token(&auth_token, "test-device-123", &wrong_key, 0), Err(TokenError::Invalid));
+    }
 
-        let auth_token = create_auth_token(&key, device_id, &master_token).unwrap();
-        let result = validate_auth_token(&key, &auth_token, &wrong_token);
+    #[test]
+    fn test_auth_token_expiry() {
+        let key = generate_secret_key();
+        let device_id = "test-device-123";
+        let issued_at = chrono::Utc::now().timestamp() - 7200;
+        let auth_token = create_auth_token_at(device_id, &key, issued_at);
 
-        assert!(result.is_err());
+        assert_eq!(validate_auth_token(&auth_token, device_id, &key, 3600), Err(TokenError::Expired));
+        assert_eq!(validate_auth_token(&auth_token, device_id, &key, 0), Ok(issued_at));
     }
 }
@@ -0,0 +1,187 @@
+//! Pseudo-terminals that look like serial barcode scanners to legacy software.
+//! Each port is published as a symlink to its `/dev/pts/N` device.
+
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use directories::BaseDirs;
+
+/// Open ports by symlink path. They stay open for the lifetime of the app so the
+/// device does not change under the application reading it.
+static PORTS: Mutex<Option<HashMap<PathBuf, PtyPort>>> = Mutex::new(None);
+
+/// Default symlink: `~/.scanlink/ttyScanner0`
+pub fn default_link() -> Result<PathBuf, String> {
+    let dirs = BaseDirs::new().ok_or("Failed to get home directory")?;
+    Ok(dirs.home_dir().join(".scanlink").join("ttyScanner0"))
+}
+
+struct PtyPort {
+    master: File,
+    /// Our own handle on the terminal side keeps its raw settings and prevents
+    /// hangups while no application has the port open
+    _slave: File,
+    device: PathBuf,
+}
+
+fn last_error(what: &str) -> String {
+    format!("{} failed: {}", what, std::io::Error::last_os_error())
+}
+
+impl PtyPort {
+    fn open() -> Result<Self, String> {
+        // SAFETY: plain libc calls on a descriptor we own; the buffer outlives ptsname_r
+        let (master, device) = unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_NONBLOCK);
+            if fd < 0 {
+                return Err(last_error("posix_openpt"));
+            }
+            let master = File::from_raw_fd(fd);
+            if libc::grantpt(fd) != 0 {
+                return Err(last_error("grantpt"));
+            }
+            if libc::unlockpt(fd) != 0 {
+                return Err(last_error("unlockpt"));
+            }
+
+            let mut name = [0 as libc::c_char; 128];
+            if libc::ptsname_r(fd, name.as_mut_ptr(), name.len()) != 0 {
+                return Err(last_error("ptsname_r"));
+            }
+            let device = PathBuf::from(CStr::from_ptr(name.as_ptr()).to_string_lossy().into_owned());
+            (master, device)
+        };
+
+        let slave = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(&device)
+            .map_err(|e| format!("Failed to open {:?}: {}", device, e))?;
+
+        // Raw mode: no echo back to us and no CR/LF translation of terminators
+        // SAFETY: termios is plain old data and the fd is open
+        unsafe {
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(slave.as_raw_fd(), &mut termios) != 0 {
+                return Err(last_error("tcgetattr"));
+            }
+            libc::cfmakeraw(&mut termios);
+            if libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios) != 0 {
+                return Err(last_error("tcsetattr"));
+            }
+        }
+
+        Ok(Self { master, _slave: slave, device })
+    }
+
+    /// Discards whatever the application wrote to the "scanner" (e.g. configuration
+    /// commands), so its writes never block on a full buffer
+    fn drain_input(&mut self) {
+        let mut buffer = [0u8; 1024];
+        while let Ok(n) = self.master.read(&mut buffer) {
+            if n == 0 {
+                break;
+            }
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), String> {
+        self.drain_input();
+        match self.master.write_all(data) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                Err(format!("Serial port {:?} is full, no application is reading it", self.device))
+            }
+            Err(e) => Err(format!("Failed to write to {:?}: {}", self.device, e)),
+        }
+    }
+}
+
+/// Points the symlink at the device, replacing an old symlink but never a real file
+fn publish_link(link: &Path, device: &Path) -> Result<(), String> {
+    if let Some(parent) = link.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("Failed to create directory {:?}: {}", parent, e))?;
+    }
+
+    match fs::symlink_metadata(link) {
+        Ok(metadata) if metadata.file_type().is_symlink() => {
+            fs::remove_file(link).map_err(|e| format!("Failed to replace {:?}: {}", link, e))?;
+        }
+        Ok(_) => return Err(format!("{:?} exists and is not a symlink", link)),
+        Err(_) => {}
+    }
+
+    std::os::unix::fs::symlink(device, link)
+        .map_err(|e| format!("Failed to create symlink {:?}: {}", link, e))
+}
+
+/// Opens the port behind `link` unless it is already open. Returns the pts device.
+pub fn ensure_port(link: &Path) -> Result<PathBuf, String> {
+    let mut guard = PORTS.lock().unwrap();
+    let ports = guard.get_or_insert_with(HashMap::new);
+
+    if let Some(port) = ports.get(link) {
+        return Ok(port.device.clone());
+    }
+
+    let port = PtyPort::open()?;
+    publish_link(link, &port.device)?;
+    log::info!("Serial scanner port {:?} -> {:?}", link, port.device);
+
+    let device = port.device.clone();
+    ports.insert(link.to_path_buf(), port);
+    Ok(device)
+}
+
+/// Writes one scan to the port behind `link`, opening it if needed
+pub fn write(link: &Path, data: &[u8]) -> Result<(), String> {
+    ensure_port(link)?;
+
+    let mut guard = PORTS.lock().unwrap();
+    match guard.as_mut().and_then(|ports| ports.get_mut(link)) {
+        Some(port) => port.write(data),
+        None => Err(format!("Serial port {:?} is not open", link)),
+    }
+}
+
+/// Closes ports that are no longer configured and removes their symlinks
+pub fn close_unused(configured: &[PathBuf]) {
+    let mut guard = PORTS.lock().unwrap();
+    if let Some(ports) = guard.as_mut() {
+        ports.retain(|link, _| {
+            let keep = configured.contains(link);
+            if !keep {
+                log::info!("Closing serial scanner port {:?}", link);
+                let _ = fs::remove_file(link);
+            }
+            keep
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pty_round_trip() {
+        let link = std::env::temp_dir().join(format!("scanlink-tty-{}", uuid::Uuid::new_v4()));
+        let device = ensure_port(&link).unwrap();
+        assert_eq!(fs::read_link(&link).unwrap(), device);
+
+        let mut reader = OpenOptions::new().read(true).open(&link).unwrap();
+        write(&link, b"123\r").unwrap();
+        let mut buffer = [0u8; 4];
+        reader.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"123\r", "CR must not be translated");
+
+        close_unused(&[]);
+        assert!(fs::symlink_metadata(&link).is_err());
+    }
+}
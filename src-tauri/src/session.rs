@@ -0,0 +1,102 @@
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use crate::security;
+
+const MIN_NONCE_LEN: usize = 16;
+
+/// Why an encrypted frame was rejected
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionError {
+    /// Sequence number not greater than the last accepted one
+    Replay { seq: u64, last_seq: u64 },
+    /// Wrong key, tampered ciphertext or mismatched sequence number
+    Decrypt(String),
+}
+
+/// Encrypted envelope state for one connection after pair/reconnect.
+///
+/// Frames are AES-256-GCM encrypted with a key derived from the device secret
+/// and both nonces. The direction and sequence number are authenticated as
+/// associated data, and each side only accepts strictly increasing sequence numbers.
+#[derive(Clone)]
+pub struct SecureSession {
+    key: String,
+    send_seq: u64,
+    recv_seq: u64,
+}
+
+impl SecureSession {
+    /// Starts a session for a device that sent `client_nonce` in pair/reconnect.
+    /// Returns the session and the server nonce to send back in the ack.
+    pub fn establish(device_key: &str, client_nonce: &str) -> Result<(Self, String), String> {
+        let nonce_len = BASE64.decode(client_nonce)
+            .map_err(|e| format!("Invalid session nonce: {}", e))?
+            .len();
+        if nonce_len < MIN_NONCE_LEN {
+            return Err(format!("Session nonce must be at least {} bytes", MIN_NONCE_LEN));
+        }
+
+        let server_nonce = security::generate_session_nonce();
+        let device_secret = security::derive_device_secret(device_key)?;
+        let key = security::derive_session_key(&device_secret, client_nonce, &server_nonce)?;
+
+        Ok((Self { key, send_seq: 0, recv_seq: 0 }, server_nonce))
+    }
+
+    /// Encrypts an outgoing frame, returning its sequence number and payload
+    pub fn seal(&mut self, plaintext: &str) -> Result<(u64, String), String> {
+        self.send_seq += 1;
+        let aad = format!("scanlink:s2c:{}", self.send_seq);
+        let payload = security::encrypt_with_aad(&self.key, plaintext, aad.as_bytes())?;
+        Ok((self.send_seq, payload))
+    }
+
+    /// Decrypts an incoming frame, rejecting replayed or reordered sequence numbers
+    pub fn open(&mut self, seq: u64, payload: &str) -> Result<String, SessionError> {
+        if seq <= self.recv_seq {
+            return Err(SessionError::Replay { seq, last_seq: self.recv_seq });
+        }
+
+        let aad = format!("scanlink:c2s:{}", seq);
+        let plaintext = security::decrypt_with_aad(&self.key, payload, aad.as_bytes())
+            .map_err(SessionError::Decrypt)?;
+
+        self.recv_seq = seq;
+        Ok(plaintext)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds the phone's view of the session from what it learns at pairing
+    fn client_key(device_key: &str, client_nonce: &str, server_nonce: &str) -> String {
+        let device_secret = security::derive_device_secret(device_key).unwrap();
+        security::derive_session_key(&device_secret, client_nonce, server_nonce).unwrap()
+    }
+
+    #[test]
+    fn test_session_round_trip_and_replay() {
+        let device_key = security::generate_secret_key();
+        let client_nonce = security::generate_session_nonce();
+        let (mut session, server_nonce) = SecureSession::establish(&device_key, &client_nonce).unwrap();
+        let key = client_key(&device_key, &client_nonce, &server_nonce);
+
+        let frame = security::encrypt_with_aad(&key, "{\"action\":\"scan\"}", b"scanlink:c2s:1").unwrap();
+        assert_eq!(session.open(1, &frame).unwrap(), "{\"action\":\"scan\"}");
+        assert!(matches!(session.open(1, &frame), Err(SessionError::Replay { .. })));
+
+        // A frame sealed for one sequence number cannot be replayed under another
+        assert!(matches!(session.open(2, &frame), Err(SessionError::Decrypt(_))));
+
+        let (seq, payload) = session.seal("{\"action\":\"scan_ack\"}").unwrap();
+        let aad = format!("scanlink:s2c:{}", seq);
+        assert_eq!(security::decrypt_with_aad(&key, &payload, aad.as_bytes()).unwrap(), "{\"action\":\"scan_ack\"}");
+    }
+
+    #[test]
+    fn test_short_nonce_rejected() {
+        let device_key = security::generate_secret_key();
+        assert!(SecureSession::establish(&device_key, &BASE64.encode([0u8; 4])).is_err());
+    }
+}
@@ -1,13 +1,32 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::fs;
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use serde_json::Value;
 use directories::ProjectDirs;
-use crate::security::AuthorizedDevice;
+use crate::keyboard::{self, InputBackend, InputMode, InputOverrides};
+use crate::models::{ConfigRecovery, TokenValidity};
+use crate::output::{self, SinkConfig};
+use crate::policy;
+use crate::profile::OutputProfile;
+use crate::security::{self, AuthorizedDevice, TokenRotation};
+use crate::template;
+use crate::tls::{self, TlsIdentity};
+use crate::transform::TransformRule;
+use crate::vault;
+use crate::websocket::{self, NetworkSettings};
 
 const CONFIG_FILE: &str = "config.json";
 
-fn get_config_path() -> Result<PathBuf, String> {
+/// Current layout of `config.json`, see `MIGRATIONS`
+pub const SCHEMA_VERSION: u32 = 3;
+
+/// Resolves a file inside the ScanLink config directory, creating the directory if needed
+pub fn get_data_file_path(file_name: &str) -> Result<PathBuf, String> {
     let proj_dirs = ProjectDirs::from("com", "scanlink", "ScanLink")
         .ok_or("Failed to get project directories")?;
 
@@ -15,63 +34,330 @@ fn get_config_path() -> Result<PathBuf, String> {
     fs::create_dir_all(config_dir)
         .map_err(|e| format!("Failed to create config directory: {}", e))?;
 
-    Ok(config_dir.join(CONFIG_FILE))
+    Ok(config_dir.join(file_name))
 }
 
-/// Load config from disk (standalone function)
-pub fn load() -> AppConfig {
-    match get_config_path() {
-        Ok(path) => {
-            if path.exists() {
-                match fs::read_to_string(&path) {
-                    Ok(content) => {
-                        match serde_json::from_str(&content) {
-                            Ok(config) => {
-                                log::info!("Config loaded from {:?}", path);
-                                config
-                            }
-                            Err(e) => {
-                                log::warn!("Failed to parse config: {}. Using default.", e);
-                                AppConfig::default()
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        log::warn!("Failed to read config: {}. Using default.", e);
-                        AppConfig::default()
-                    }
-                }
-            } else {
-                log::info!("No config file found, using default");
-                AppConfig::default()
+pub fn get_config_path() -> Result<PathBuf, String> {
+    get_data_file_path(CONFIG_FILE)
+}
+
+/// Last good config, replaced on every successful save
+fn backup_path(path: &Path) -> PathBuf {
+    path.with_extension("json.bak")
+}
+
+/// Serializes saves, which share the temporary file
+static SAVE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Set while a quarantined config awaits a restore decision, so saves of the
+/// defaults in use meanwhile do not replace the good backup
+static BACKUP_PINNED: AtomicBool = AtomicBool::new(false);
+
+/// Set while the config on disk cannot be decrypted: the app runs on defaults
+/// and nothing may overwrite the file until `unlock` succeeds
+static LOCKED: AtomicBool = AtomicBool::new(false);
+
+/// Why a config file could not be parsed
+#[derive(Debug)]
+enum ParseError {
+    /// The secrets are sealed with a key that is not available; the file is intact
+    KeyUnavailable(String),
+    Invalid(String),
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::KeyUnavailable(e) | Self::Invalid(e) => f.write_str(e),
+        }
+    }
+}
+
+impl From<vault::UnsealError> for ParseError {
+    fn from(error: vault::UnsealError) -> Self {
+        match error {
+            vault::UnsealError::KeyUnavailable(e) => Self::KeyUnavailable(e),
+            vault::UnsealError::Invalid(e) => Self::Invalid(e),
+        }
+    }
+}
+
+/// Upgrades a raw config one schema version
+type Migration = fn(&mut Value) -> Result<(), String>;
+
+/// Migrations by source version: `MIGRATIONS[n]` turns version n into n + 1
+const MIGRATIONS: &[Migration] = &[
+    // 0 -> 1: versioning introduced, layout unchanged
+    |_| Ok(()),
+    // 1 -> 2: secrets may move into `sealed_secrets`; plaintext ones are sealed on load
+    |_| Ok(()),
+    // 2 -> 3: configs from before TLS keep serving ws:// so paired phones stay connected
+    |value| {
+        if value.get("tls_enabled").is_none() {
+            value["tls_enabled"] = Value::Bool(false);
+        }
+        Ok(())
+    },
+];
+
+const _: () = assert!(MIGRATIONS.len() == SCHEMA_VERSION as usize);
+
+/// Parses a config file, migrating older schema versions
+fn parse_config(content: &str, keys: &vault::Keys) -> Result<AppConfig, ParseError> {
+    let mut value: Value = serde_json::from_str(content).map_err(|e| ParseError::Invalid(e.to_string()))?;
+    if !value.is_object() {
+        return Err(ParseError::Invalid("Config is not a JSON object".to_string()));
+    }
+
+    let version = value.get("schema_version").and_then(Value::as_u64).unwrap_or(0) as u32;
+    if version > SCHEMA_VERSION {
+        // Keep the newer version so `save_to` leaves the file alone
+        log::warn!(
+            "Config was written by a newer version (schema {} > {}), changes will not be saved",
+            version, SCHEMA_VERSION
+        );
+    } else {
+        for (from, migrate) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            migrate(&mut value)
+                .map_err(|e| ParseError::Invalid(format!("Migration from schema {} failed: {}", from, e)))?;
+            log::info!("Migrated config from schema {} to {}", from, from + 1);
+        }
+        value["schema_version"] = Value::from(SCHEMA_VERSION);
+    }
+
+    keys.unseal(&mut value)?;
+    serde_json::from_value(value).map_err(|e| ParseError::Invalid(e.to_string()))
+}
+
+/// Rewrites a config with plaintext secrets (from before encryption, or written
+/// while no key was available) so they are encrypted, including in the backup
+fn seal_plaintext_secrets(path: &Path, content: &str, config: &AppConfig, keys: &vault::Keys) {
+    let plaintext = serde_json::from_str::<Value>(content)
+        .map(|value| vault::has_plaintext_secrets(&value))
+        .unwrap_or(false);
+    if !plaintext || !keys.is_available() {
+        return;
+    }
+
+    match save_to(path, config, keys) {
+        Ok(()) => {
+            if let Err(e) = fs::copy(path, backup_path(path)) {
+                log::warn!("Failed to replace plaintext config backup: {}", e);
             }
+            log::info!("Encrypted plaintext secrets in {:?}", path);
+        }
+        Err(e) => log::error!("Failed to encrypt config secrets: {}", e),
+    }
+}
+
+/// Moves a corrupt config aside so it is never overwritten
+fn quarantine(path: &Path) -> Result<PathBuf, String> {
+    let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+    let quarantined = path.with_extension(format!("json.corrupt-{}", timestamp));
+    fs::rename(path, &quarantined)
+        .map_err(|e| format!("Failed to quarantine {:?}: {}", path, e))?;
+    Ok(quarantined)
+}
+
+fn load_from(path: &Path, keys: &vault::Keys) -> (AppConfig, Option<ConfigRecovery>) {
+    let error = match fs::read_to_string(path) {
+        Ok(content) => match parse_config(&content, keys) {
+            Ok(config) => {
+                log::info!("Config loaded from {:?}", path);
+                seal_plaintext_secrets(path, &content, &config, keys);
+                return (config, None);
+            }
+            // The file is fine, only the key is missing: leave it untouched
+            Err(ParseError::KeyUnavailable(e)) => {
+                log::error!("{}. Using defaults without saving until the config is unlocked.", e);
+                let recovery = ConfigRecovery {
+                    error: e,
+                    quarantined_path: None,
+                    backup_available: false,
+                    backup_saved_at: None,
+                    key_unavailable: true,
+                };
+                return (AppConfig::default(), Some(recovery));
+            }
+            Err(e) => format!("Failed to parse config: {}", e),
+        },
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            log::info!("No config file found, using default");
+            return (AppConfig::default(), None);
+        }
+        Err(e) => format!("Failed to read config: {}", e),
+    };
+
+    log::error!("{}. Using default.", error);
+    let quarantined_path = match quarantine(path) {
+        Ok(quarantined) => {
+            log::warn!("Corrupt config moved to {:?}", quarantined);
+            Some(quarantined.to_string_lossy().into_owned())
         }
         Err(e) => {
-            log::warn!("Failed to get config path: {}. Using default.", e);
-            AppConfig::default()
+            log::error!("{}", e);
+            None
         }
+    };
+
+    let backup = backup_path(path);
+    let backup_saved_at = fs::read_to_string(&backup)
+        .ok()
+        .filter(|content| parse_config(content, keys).is_ok())
+        .and_then(|_| fs::metadata(&backup).and_then(|m| m.modified()).ok())
+        .map(|modified| chrono::DateTime::<chrono::Utc>::from(modified).to_rfc3339());
+    if backup_saved_at.is_some() {
+        BACKUP_PINNED.store(true, Ordering::SeqCst);
     }
+
+    let recovery = ConfigRecovery {
+        error,
+        quarantined_path,
+        backup_available: backup_saved_at.is_some(),
+        backup_saved_at,
+        key_unavailable: false,
+    };
+    (AppConfig::default(), Some(recovery))
 }
 
-/// Save config to disk (standalone function)
-pub fn save(config: &AppConfig) -> Result<(), String> {
-    let path = get_config_path()?;
+/// Load config from disk, reporting a corrupt file that was quarantined
+/// The system policy's defaults seed a first-start config and its locked settings always win.
+pub fn load_with_recovery() -> (AppConfig, Option<ConfigRecovery>) {
+    let policy = policy::current();
+    let (mut config, recovery) = match get_config_path() {
+        Ok(path) => {
+            let fresh = !path.exists();
+            let (mut config, recovery) = load_from(&path, vault::system());
+            if recovery.as_ref().is_some_and(|r| r.key_unavailable) {
+                LOCKED.store(true, Ordering::SeqCst);
+            }
+            if fresh {
+                policy.apply_defaults(&mut config);
+            }
+            (config, recovery)
+        }
+        Err(e) => {
+            log::warn!("Failed to get config path: {}. Using default.", e);
+            let mut config = AppConfig::default();
+            policy.apply_defaults(&mut config);
+            (config, None)
+        }
+    };
+    policy.apply_locked(&mut config);
+    (config, recovery)
+}
 
-    let content = serde_json::to_string_pretty(config)
+fn save_to(path: &Path, config: &AppConfig, keys: &vault::Keys) -> Result<(), String> {
+    // Saving would drop the settings this version does not know about
+    if config.schema_version > SCHEMA_VERSION {
+        return Err(format!(
+            "The config was written by a newer version of ScanLink (schema {}), changes are not saved so its settings are kept",
+            config.schema_version
+        ));
+    }
+    let mut value = serde_json::to_value(config)
         .map_err(|e| format!("Failed to serialize config: {}", e))?;
+    keys.seal(&mut value)?;
+    let content = serde_json::to_string_pretty(&value)
+        .map_err(|e| format!("Failed to serialize config: {}", e))?;
+
+    let _guard = SAVE_LOCK.lock().unwrap();
 
-    fs::write(&path, content)
+    // Write and flush a temporary file first so a crash never leaves a truncated config
+    let tmp_path = path.with_extension("json.tmp");
+    let mut file = File::create(&tmp_path)
+        .map_err(|e| format!("Failed to write config: {}", e))?;
+    file.write_all(content.as_bytes())
+        .and_then(|_| file.sync_all())
         .map_err(|e| format!("Failed to write config: {}", e))?;
 
+    // The current file was itself written atomically, so it is the last good one
+    if path.exists() && !BACKUP_PINNED.load(Ordering::SeqCst) {
+        if let Err(e) = fs::copy(path, backup_path(path)) {
+            log::warn!("Failed to back up config: {}", e);
+        }
+    }
+
+    fs::rename(&tmp_path, path)
+        .map_err(|e| format!("Failed to replace config: {}", e))
+}
+
+/// Save config to disk (standalone function)
+pub fn save(config: &AppConfig) -> Result<(), String> {
+    save_at(&get_config_path()?, config)
+}
+
+/// Saves the config to `path` instead of the one in the config directory
+pub fn save_at(path: &Path, config: &AppConfig) -> Result<(), String> {
+    if LOCKED.load(Ordering::SeqCst) {
+        return Err("The config is locked, changes are not saved until its secrets are unlocked".to_string());
+    }
+    save_to(path, config, vault::system())?;
     log::info!("Config saved to {:?}", path);
     Ok(())
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+fn restore_backup_at(path: &Path, keys: &vault::Keys) -> Result<AppConfig, String> {
+    let content = fs::read_to_string(backup_path(path))
+        .map_err(|e| format!("Failed to read config backup: {}", e))?;
+    let config = parse_config(&content, keys).map_err(|e| format!("Config backup is invalid: {}", e))?;
+
+    BACKUP_PINNED.store(false, Ordering::SeqCst);
+    save_to(path, &config, keys)?;
+    log::info!("Config restored from backup");
+    Ok(config)
+}
+
+/// Replaces the config with the last good backup after a corrupt file was quarantined
+pub fn restore_backup() -> Result<AppConfig, String> {
+    let mut config = restore_backup_at(&get_config_path()?, vault::system())?;
+    policy::current().apply_locked(&mut config);
+    Ok(config)
+}
+
+/// Whether the config on disk is waiting for its key (saves are refused meanwhile)
+pub fn is_locked() -> bool {
+    LOCKED.load(Ordering::SeqCst)
+}
+
+/// Retries loading a config whose secrets could not be decrypted, e.g. after the
+/// passphrase was given with `vault::set_passphrase` or the keyring was unlocked
+pub fn unlock() -> Result<(AppConfig, Option<ConfigRecovery>), String> {
+    let path = get_config_path()?;
+    let (mut config, recovery) = load_from(&path, vault::system());
+    if let Some(recovery) = recovery.as_ref().filter(|r| r.key_unavailable) {
+        return Err(recovery.error.clone());
+    }
+    LOCKED.store(false, Ordering::SeqCst);
+    policy::current().apply_locked(&mut config);
+    log::info!("Config unlocked");
+    Ok((config, recovery))
+}
+
+/// Keeps the current config instead of restoring; later saves roll the backup again
+pub fn discard_backup() {
+    BACKUP_PINNED.store(false, Ordering::SeqCst);
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppConfig {
-    /// Master token (persistent, only changes on explicit regeneration)
+    /// Layout version, upgraded on load by `MIGRATIONS` (absent = 0)
+    #[serde(default)]
+    pub schema_version: u32,
+    /// Master token (persistent, replaced per `token_rotation` or on explicit regeneration).
+    /// Like all key material, stored encrypted in `sealed_secrets` when a key is available.
     pub master_token: Option<String>,
-    /// Secret key for AES encryption (base64 encoded)
+    /// When the master token was issued (unix seconds)
+    #[serde(default)]
+    pub master_token_issued_at: Option<i64>,
+    /// Devices paired with the current master token
+    #[serde(default)]
+    pub master_token_pairings: u32,
+    /// When the master token is replaced automatically
+    #[serde(default)]
+    pub token_rotation: TokenRotation,
+    /// Legacy shared key (base64) for devices paired before per-device keys.
+    /// Cleared once every such device has migrated to its own key.
     pub secret_key: Option<String>,
     /// List of authorized devices
     #[serde(default)]
@@ -85,29 +371,294 @@ pub struct AppConfig {
     /// Start minimized (in tray)
     #[serde(default)]
     pub start_minimized: bool,
+    /// How long device auth tokens stay valid, in seconds (0 = never expire)
+    #[serde(default = "default_auth_token_lifetime")]
+    pub auth_token_lifetime_secs: u64,
+    /// Reject scans that are not sent inside an encrypted session envelope. Devices
+    /// that cannot open a session are locked out: those still on the legacy shared
+    /// key and those paired over plain ws://, which never receive a device secret.
+    #[serde(default)]
+    pub require_encryption: bool,
+    /// Port the server listens on
+    #[serde(default = "default_port")]
+    pub port: u16,
+    /// Interface the server listens on (0.0.0.0 = all)
+    #[serde(default = "default_bind_address")]
+    pub bind_address: IpAddr,
+    /// Following ports tried when `port` is taken (0 = report the error instead).
+    /// Paired phones remember the port, so a fallback port means scanning a new QR.
+    #[serde(default)]
+    pub port_fallback_attempts: u16,
+    /// Serve wss:// (disable to keep plain ws:// for legacy clients). New
+    /// installs start with TLS, configs from before it keep ws://.
+    #[serde(default = "default_true")]
+    pub tls_enabled: bool,
+    /// Self-signed certificate, generated on first TLS start
+    #[serde(default)]
+    pub tls_identity: Option<TlsIdentity>,
+    /// Where scans are delivered; every sink receives every scan, in order
+    #[serde(default = "output::default_sinks")]
+    pub output_sinks: Vec<SinkConfig>,
+    /// Keystrokes typed by the keyboard sink, e.g. `{barcode}{ENTER}`
+    #[serde(default = "default_keystroke_template")]
+    pub keystroke_template: String,
+    /// Per-device templates overriding `keystroke_template`, by device id
+    #[serde(default)]
+    pub device_keystroke_templates: HashMap<String, String>,
+    /// Paste text via the clipboard, or type it character by character
+    #[serde(default)]
+    pub input_mode: InputMode,
+    /// Delay between characters in type mode, in milliseconds
+    #[serde(default = "default_type_delay_ms")]
+    pub type_delay_ms: u64,
+    /// Restore the user's clipboard (text, images, other formats) after pasting a scan.
+    /// Only the most faithful format is put back (e.g. the image of a copied cell, not
+    /// its text), since wl-copy and xclip serve a single type per selection.
+    #[serde(default = "default_true")]
+    pub preserve_clipboard: bool,
+    /// Input simulator to use instead of the detected one
+    #[serde(default)]
+    pub input_backend: Option<InputBackend>,
+    /// XKB layout the uinput keyboard types with instead of the system one
+    #[serde(default)]
+    pub keyboard_layout: Option<String>,
+    /// Rewrites applied to every barcode before output, in order
+    #[serde(default)]
+    pub transform_rules: Vec<TransformRule>,
+    /// Keyboard settings per application, picked by the focused window (X11)
+    #[serde(default)]
+    pub output_profiles: Vec<OutputProfile>,
+    /// Refuse to type unless the focused window matches a non-blocking profile
+    #[serde(default)]
+    pub block_unmatched_windows: bool,
 }
 
 fn default_true() -> bool {
     true
 }
 
+fn default_keystroke_template() -> String {
+    template::DEFAULT_TEMPLATE.to_string()
+}
+
+fn default_type_delay_ms() -> u64 {
+    keyboard::DEFAULT_KEY_DELAY_MS
+}
+
+fn default_port() -> u16 {
+    websocket::DEFAULT_PORT
+}
+
+fn default_bind_address() -> IpAddr {
+    IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+}
+
+fn default_auth_token_lifetime() -> u64 {
+    30 * 24 * 60 * 60 // 30 days
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            master_token: None,
+            master_token_issued_at: None,
+            master_token_pairings: 0,
+            token_rotation: TokenRotation::default(),
+            secret_key: None,
+            authorized_devices: HashMap::new(),
+            auto_start: false,
+            minimize_to_tray: false,
+            start_minimized: false,
+            auth_token_lifetime_secs: default_auth_token_lifetime(),
+            require_encryption: false,
+            port: default_port(),
+            bind_address: default_bind_address(),
+            port_fallback_attempts: 0,
+            tls_enabled: true,
+            tls_identity: None,
+            output_sinks: output::default_sinks(),
+            keystroke_template: default_keystroke_template(),
+            device_keystroke_templates: HashMap::new(),
+            input_mode: InputMode::default(),
+            type_delay_ms: default_type_delay_ms(),
+            preserve_clipboard: true,
+            input_backend: None,
+            keyboard_layout: None,
+            transform_rules: Vec::new(),
+            output_profiles: Vec::new(),
+            block_unmatched_windows: false,
+        }
+    }
+}
+
 impl AppConfig {
+    pub fn input_overrides(&self) -> InputOverrides {
+        InputOverrides {
+            backend: self.input_backend,
+            keyboard_layout: self.keyboard_layout.clone(),
+        }
+    }
+
+    pub fn set_input_overrides(&mut self, overrides: InputOverrides) {
+        self.input_backend = overrides.backend;
+        self.keyboard_layout = overrides.keyboard_layout;
+    }
+
+    pub fn network_settings(&self) -> NetworkSettings {
+        NetworkSettings {
+            port: self.port,
+            bind_address: self.bind_address,
+            port_fallback_attempts: self.port_fallback_attempts,
+            tls_enabled: self.tls_enabled,
+        }
+    }
+
+    pub fn set_network_settings(&mut self, settings: NetworkSettings) {
+        self.port = settings.port;
+        self.bind_address = settings.bind_address;
+        self.port_fallback_attempts = settings.port_fallback_attempts;
+        self.tls_enabled = settings.tls_enabled;
+    }
+
+    /// Replaces the master token; phones must scan the new QR code to pair
+    pub fn rotate_master_token(&mut self, now: i64) {
+        self.master_token = Some(security::generate_master_token());
+        self.master_token_issued_at = Some(now);
+        self.master_token_pairings = 0;
+        log::info!("New master token issued (rotation: {:?})", self.token_rotation);
+    }
+
+    fn master_token_due(&self, now: i64) -> bool {
+        match self.token_rotation {
+            TokenRotation::Never => false,
+            TokenRotation::Daily => self
+                .master_token_issued_at
+                .is_some_and(|issued_at| now >= issued_at + security::DAILY_ROTATION_SECS),
+            TokenRotation::AfterPairings { count } => self.master_token_pairings >= count.max(1),
+            TokenRotation::EveryPairing => self.master_token_pairings > 0,
+        }
+    }
+
+    /// Creates the master token if missing and rotates it when the policy says so.
+    /// Returns whether the config changed and should be saved.
+    pub fn ensure_master_token(&mut self, now: i64) -> bool {
+        if self.master_token.is_none() || self.master_token_due(now) {
+            self.rotate_master_token(now);
+            true
+        } else if self.master_token_issued_at.is_none() {
+            // Tokens from before rotation existed start their period now
+            self.master_token_issued_at = Some(now);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Counts a pairing with the master token; true if that used it up and it was rotated
+    pub fn record_pairing(&mut self, now: i64) -> bool {
+        self.master_token_pairings = self.master_token_pairings.saturating_add(1);
+        if self.master_token_due(now) {
+            self.rotate_master_token(now);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn master_token_validity(&self) -> TokenValidity {
+        let (expires_at, remaining_pairings) = match self.token_rotation {
+            TokenRotation::Never => (None, None),
+            TokenRotation::Daily => (self.master_token_issued_at.map(|issued_at| issued_at + security::DAILY_ROTATION_SECS), None),
+            TokenRotation::AfterPairings { count } => (None, Some(count.max(1).saturating_sub(self.master_token_pairings))),
+            TokenRotation::EveryPairing => (None, Some(1)),
+        };
+        TokenValidity {
+            rotation: self.token_rotation,
+            expires_at,
+            remaining_pairings,
+        }
+    }
+
     pub fn add_device(&mut self, device: AuthorizedDevice) {
         self.authorized_devices.insert(device.device_id.clone(), device);
     }
 
+    /// Removes the device together with its key, invalidating all its tokens
     pub fn remove_device(&mut self, device_id: &str) -> bool {
-        self.authorized_devices.remove(device_id).is_some()
+        let removed = self.authorized_devices.remove(device_id).is_some();
+        self.device_keystroke_templates.remove(device_id);
+        self.drop_unused_legacy_key();
+        removed
     }
 
     pub fn revoke_all_devices(&mut self) {
         self.authorized_devices.clear();
+        self.device_keystroke_templates.clear();
+        self.secret_key = None;
+    }
+
+    /// Keystroke template for scans from this device
+    pub fn keystroke_template_for(&self, device_id: &str) -> &str {
+        self.device_keystroke_templates
+            .get(device_id)
+            .unwrap_or(&self.keystroke_template)
+    }
+
+    /// Key that signs the device's auth tokens: its own key, or the legacy
+    /// shared key for devices paired before per-device keys existed
+    pub fn device_secret_key(&self, device_id: &str) -> Option<String> {
+        let device = self.authorized_devices.get(device_id)?;
+        device.secret_key.clone().or_else(|| self.secret_key.clone())
+    }
+
+    pub fn uses_legacy_key(&self, device_id: &str) -> bool {
+        self.authorized_devices
+            .get(device_id)
+            .map(|d| d.secret_key.is_none())
+            .unwrap_or(false)
+    }
+
+    /// Gives a legacy device its own key and returns it. Tokens signed with the
+    /// shared key stop working for this device, so the caller must hand it a new one.
+    pub fn migrate_device_key(&mut self, device_id: &str) -> Option<String> {
+        let device = self.authorized_devices.get_mut(device_id)?;
+        let key = device.secret_key.get_or_insert_with(security::generate_secret_key).clone();
+        self.drop_unused_legacy_key();
+        Some(key)
+    }
+
+    /// Destroys the legacy shared key once no device depends on it
+    fn drop_unused_legacy_key(&mut self) {
+        if self.secret_key.is_some() && self.authorized_devices.values().all(|d| d.secret_key.is_some()) {
+            log::info!("All devices use their own keys, removing legacy shared key");
+            self.secret_key = None;
+        }
     }
 
     pub fn is_device_authorized(&self, device_id: &str) -> bool {
         self.authorized_devices.contains_key(device_id)
     }
 
+    /// Generates the TLS certificate if TLS is enabled and none exists yet.
+    /// Returns true when a new certificate was created and the config needs saving.
+    pub fn ensure_tls_identity(&mut self) -> Result<bool, String> {
+        if !self.tls_enabled || self.tls_identity.is_some() {
+            return Ok(false);
+        }
+        self.tls_identity = Some(tls::generate_identity()?);
+        Ok(true)
+    }
+
+    /// Certificate to serve with, or None when plain ws:// is configured
+    pub fn active_tls_identity(&self) -> Option<&TlsIdentity> {
+        if self.tls_enabled {
+            self.tls_identity.as_ref()
+        } else {
+            None
+        }
+    }
+
     #[allow(dead_code)] // Reserved for future device management feature
     pub fn get_device(&self, device_id: &str) -> Option<&AuthorizedDevice> {
         self.authorized_devices.get(device_id)
@@ -118,3 +669,179 @@ impl AppConfig {
         self.authorized_devices.get_mut(device_id)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn legacy_device(device_id: &str) -> AuthorizedDevice {
+        AuthorizedDevice {
+            secret_key: None,
+            ..AuthorizedDevice::new(device_id.to_string(), "Phone".to_string(), None)
+        }
+    }
+
+    #[test]
+    fn test_legacy_key_migration() {
+        let mut config = AppConfig {
+            secret_key: Some(security::generate_secret_key()),
+            ..AppConfig::default()
+        };
+        config.add_device(legacy_device("a"));
+        config.add_device(legacy_device("b"));
+
+        assert_eq!(config.device_secret_key("a"), config.secret_key);
+
+        let key_a = config.migrate_device_key("a").unwrap();
+        assert_eq!(config.device_secret_key("a"), Some(key_a));
+        assert!(config.secret_key.is_some(), "device b still needs the shared key");
+
+        config.migrate_device_key("b");
+        assert!(config.secret_key.is_none());
+    }
+
+    #[test]
+    fn test_remove_device_destroys_key() {
+        let mut config = AppConfig::default();
+        config.add_device(AuthorizedDevice::new("a".to_string(), "Phone".to_string(), None));
+
+        assert!(config.device_secret_key("a").is_some());
+        config.remove_device("a");
+        assert!(config.device_secret_key("a").is_none());
+    }
+
+    fn temp_config_path() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("scanlink-config-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join(CONFIG_FILE)
+    }
+
+    #[test]
+    fn test_migrates_unversioned_config() {
+        let config = parse_config(r#"{"master_token": "abc", "secret_key": null, "auto_start": true}"#, &vault::Keys::none()).unwrap();
+        assert_eq!(config.schema_version, SCHEMA_VERSION);
+        assert_eq!(config.master_token.as_deref(), Some("abc"));
+        assert!(config.auto_start);
+        assert!(parse_config("[]", &vault::Keys::none()).is_err());
+    }
+
+    #[test]
+    fn test_configs_from_before_tls_keep_ws() {
+        let config = parse_config(r#"{"schema_version": 2, "master_token": "abc"}"#, &vault::Keys::none()).unwrap();
+        assert!(!config.tls_enabled);
+
+        let config = parse_config(r#"{"schema_version": 2, "tls_enabled": true}"#, &vault::Keys::none()).unwrap();
+        assert!(config.tls_enabled);
+
+        assert!(AppConfig::default().tls_enabled, "new installs serve wss://");
+    }
+
+    #[test]
+    fn test_config_from_a_newer_version_is_never_overwritten() {
+        let keys = vault::Keys::none();
+        let path = temp_config_path();
+        let content = r#"{"schema_version": 99, "master_token": "abc", "setting_from_the_future": true}"#;
+        fs::write(&path, content).unwrap();
+
+        let (mut config, recovery) = load_from(&path, &keys);
+        assert!(recovery.is_none());
+        assert_eq!(config.master_token.as_deref(), Some("abc"));
+        assert_eq!(config.schema_version, 99);
+
+        config.auto_start = true;
+        let error = save_to(&path, &config, &keys).unwrap_err();
+        assert!(error.contains("newer version"), "{}", error);
+        assert_eq!(fs::read_to_string(&path).unwrap(), content);
+        assert!(!backup_path(&path).exists());
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_config_is_quarantined_and_restorable() {
+        let keys = vault::Keys::with_passphrase("test passphrase");
+        let path = temp_config_path();
+        let mut config = AppConfig::default();
+        config.add_device(AuthorizedDevice::new("a".to_string(), "Phone".to_string(), None));
+        save_to(&path, &config, &keys).unwrap();
+        save_to(&path, &config, &keys).unwrap();
+        assert!(backup_path(&path).exists());
+        assert!(!fs::read_to_string(&path).unwrap().contains("\"secret_key\": \""), "secrets are encrypted");
+
+        // Simulate a torn write
+        fs::write(&path, "{\"master_token\": \"ab").unwrap();
+        let (loaded, recovery) = load_from(&path, &keys);
+        let recovery = recovery.unwrap();
+        assert!(loaded.authorized_devices.is_empty());
+        assert!(recovery.backup_available);
+        let quarantined = PathBuf::from(recovery.quarantined_path.unwrap());
+        assert!(fs::read_to_string(quarantined).unwrap().starts_with("{\"master_token"));
+
+        // Saving the defaults meanwhile must not replace the good backup
+        save_to(&path, &loaded, &keys).unwrap();
+        let restored = restore_backup_at(&path, &keys).unwrap();
+        assert!(restored.is_device_authorized("a"));
+        assert!(load_from(&path, &keys).0.is_device_authorized("a"));
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_sealed_config_without_key_is_left_untouched() {
+        let path = temp_config_path();
+        let mut config = AppConfig::default();
+        config.add_device(AuthorizedDevice::new("a".to_string(), "Phone".to_string(), None));
+        save_to(&path, &config, &vault::Keys::with_passphrase("test passphrase")).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+
+        for keys in [vault::Keys::none(), vault::Keys::with_passphrase("wrong passphrase")] {
+            let (loaded, recovery) = load_from(&path, &keys);
+            let recovery = recovery.unwrap();
+            assert!(recovery.key_unavailable);
+            assert!(recovery.quarantined_path.is_none());
+            assert!(loaded.authorized_devices.is_empty());
+            assert_eq!(fs::read_to_string(&path).unwrap(), content, "file is not moved or rewritten");
+        }
+
+        let (loaded, recovery) = load_from(&path, &vault::Keys::with_passphrase("test passphrase"));
+        assert!(recovery.is_none());
+        assert!(loaded.is_device_authorized("a"));
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_master_token_rotation() {
+        let mut config = AppConfig::default();
+        assert!(config.ensure_master_token(1000));
+        let token = config.master_token.clone().unwrap();
+        assert!(!config.ensure_master_token(1000 + security::DAILY_ROTATION_SECS), "never rotates by default");
+        assert!(!config.record_pairing(1000));
+        assert_eq!(config.master_token.as_deref(), Some(token.as_str()));
+
+        config.token_rotation = TokenRotation::Daily;
+        assert_eq!(config.master_token_validity().expires_at, Some(1000 + security::DAILY_ROTATION_SECS));
+        assert!(config.ensure_master_token(1000 + security::DAILY_ROTATION_SECS));
+        assert_ne!(config.master_token.as_deref(), Some(token.as_str()));
+
+        config.token_rotation = TokenRotation::AfterPairings { count: 2 };
+        let token = config.master_token.clone().unwrap();
+        assert!(!config.record_pairing(0));
+        assert_eq!(config.master_token_validity().remaining_pairings, Some(1));
+        assert!(config.record_pairing(0));
+        assert_ne!(config.master_token.as_deref(), Some(token.as_str()));
+        assert_eq!(config.master_token_pairings, 0);
+
+        config.token_rotation = TokenRotation::EveryPairing;
+        assert!(config.record_pairing(0));
+    }
+
+    #[test]
+    fn test_existing_master_token_is_kept() {
+        let mut config = parse_config(r#"{"master_token": "abc", "secret_key": null}"#, &vault::Keys::none()).unwrap();
+        assert!(config.ensure_master_token(1000), "issue time is recorded");
+        assert_eq!(config.master_token.as_deref(), Some("abc"));
+        assert_eq!(config.master_token_issued_at, Some(1000));
+        assert!(!config.ensure_master_token(2000));
+    }
+}
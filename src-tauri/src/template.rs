@@ -0,0 +1,345 @@
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local, NaiveDate};
+use crate::models::BarcodeMessage;
+
+/// Behaviour before templates existed: paste the barcode, then press Enter
+pub const DEFAULT_TEMPLATE: &str = "{barcode}{ENTER}";
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+const DEFAULT_TIME_FORMAT: &str = "%H:%M:%S";
+
+/// Non-text keys a template can press
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpecialKey {
+    Enter,
+    Tab,
+    Escape,
+    Backspace,
+    Delete,
+    Space,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    /// F1 to F20
+    F(u8),
+}
+
+impl SpecialKey {
+    fn parse(name: &str) -> Option<Self> {
+        let key = match name.to_ascii_uppercase().as_str() {
+            "ENTER" | "RETURN" => SpecialKey::Enter,
+            "TAB" => SpecialKey::Tab,
+            "ESC" | "ESCAPE" => SpecialKey::Escape,
+            "BACKSPACE" | "BS" => SpecialKey::Backspace,
+            "DELETE" | "DEL" => SpecialKey::Delete,
+            "SPACE" => SpecialKey::Space,
+            "UP" => SpecialKey::Up,
+            "DOWN" => SpecialKey::Down,
+            "LEFT" => SpecialKey::Left,
+            "RIGHT" => SpecialKey::Right,
+            "HOME" => SpecialKey::Home,
+            "END" => SpecialKey::End,
+            "PGUP" | "PAGEUP" => SpecialKey::PageUp,
+            "PGDN" | "PAGEDOWN" => SpecialKey::PageDown,
+            upper => {
+                let number: u8 = upper.strip_prefix('F')?.parse().ok()?;
+                if !(1..=20).contains(&number) {
+                    return None;
+                }
+                SpecialKey::F(number)
+            }
+        };
+        Some(key)
+    }
+
+    /// Key name understood by `xdotool key`
+    pub fn xdotool_name(&self) -> String {
+        match self {
+            SpecialKey::Enter => "Return".to_string(),
+            SpecialKey::Tab => "Tab".to_string(),
+            SpecialKey::Escape => "Escape".to_string(),
+            SpecialKey::Backspace => "BackSpace".to_string(),
+            SpecialKey::Delete => "Delete".to_string(),
+            SpecialKey::Space => "space".to_string(),
+            SpecialKey::Up => "Up".to_string(),
+            SpecialKey::Down => "Down".to_string(),
+            SpecialKey::Left => "Left".to_string(),
+            SpecialKey::Right => "Right".to_string(),
+            SpecialKey::Home => "Home".to_string(),
+            SpecialKey::End => "End".to_string(),
+            SpecialKey::PageUp => "Prior".to_string(),
+            SpecialKey::PageDown => "Next".to_string(),
+            SpecialKey::F(n) => format!("F{}", n),
+        }
+    }
+
+    /// Key name understood by `ydotool key` (v0.1.x)
+    pub fn ydotool_name(&self) -> String {
+        match self {
+            SpecialKey::Enter => "enter".to_string(),
+            SpecialKey::Tab => "tab".to_string(),
+            SpecialKey::Escape => "esc".to_string(),
+            SpecialKey::Backspace => "backspace".to_string(),
+            SpecialKey::Delete => "delete".to_string(),
+            SpecialKey::Space => "space".to_string(),
+            SpecialKey::Up => "up".to_string(),
+            SpecialKey::Down => "down".to_string(),
+            SpecialKey::Left => "left".to_string(),
+            SpecialKey::Right => "right".to_string(),
+            SpecialKey::Home => "home".to_string(),
+            SpecialKey::End => "end".to_string(),
+            SpecialKey::PageUp => "pageup".to_string(),
+            SpecialKey::PageDown => "pagedown".to_string(),
+            SpecialKey::F(n) => format!("f{}", n),
+        }
+    }
+}
+
+/// One step a keyboard backend executes
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyAction {
+    Text(String),
+    Key(SpecialKey),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Barcode,
+    BarcodeType,
+    DeviceId,
+    DeviceName,
+    /// Local time with a strftime format
+    DateTime(String),
+    /// Value of a GS1 Application Identifier, empty for non-GS1 scans
+    Gs1(String),
+    /// GS1 expiry date (AI 17) with a strftime format
+    Expiry(String),
+    Key(SpecialKey),
+}
+
+/// Parsed keystroke template such as `{F2}{barcode}{TAB}{date:%Y-%m-%d}{ENTER}`.
+///
+/// Placeholders insert scan data, key names (any case) press keys, and
+/// `{{` / `}}` insert literal braces. Everything else is typed as-is.
+/// GS1 fields are available as `{gtin}`, `{lot}`, `{serial}`, `{expiry[:format]}`
+/// and `{gs1:<AI>}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Default for Template {
+    fn default() -> Self {
+        Self {
+            parts: vec![Part::Barcode, Part::Key(SpecialKey::Enter)],
+        }
+    }
+}
+
+fn validate_time_format(format: &str) -> Result<(), String> {
+    if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+        return Err(format!("Invalid date format '{}'", format));
+    }
+    Ok(())
+}
+
+fn parse_placeholder(name: &str) -> Result<Part, String> {
+    let (field, argument) = match name.split_once(':') {
+        Some((field, argument)) => (field, Some(argument)),
+        None => (name, None),
+    };
+
+    let part = match (field, argument) {
+        ("barcode", None) => Part::Barcode,
+        ("barcode_type", None) => Part::BarcodeType,
+        ("device_id", None) => Part::DeviceId,
+        ("device_name", None) => Part::DeviceName,
+        ("gtin", None) => Part::Gs1("01".to_string()),
+        ("lot", None) => Part::Gs1("10".to_string()),
+        ("serial", None) => Part::Gs1("21".to_string()),
+        ("gs1", Some(ai)) if (2..=4).contains(&ai.len()) && ai.chars().all(|c| c.is_ascii_digit()) => {
+            Part::Gs1(ai.to_string())
+        }
+        ("expiry", format) => {
+            let format = format.unwrap_or(DEFAULT_DATE_FORMAT);
+            validate_time_format(format)?;
+            Part::Expiry(format.to_string())
+        }
+        ("date", format) | ("time", format) => {
+            let default = if field == "date" { DEFAULT_DATE_FORMAT } else { DEFAULT_TIME_FORMAT };
+            let format = format.unwrap_or(default);
+            validate_time_format(format)?;
+            Part::DateTime(format.to_string())
+        }
+        _ => match (SpecialKey::parse(name), argument) {
+            (Some(key), None) => Part::Key(key),
+            _ => return Err(format!("Unknown placeholder '{{{}}}'", name)),
+        },
+    };
+
+    Ok(part)
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = source.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(format!("Unclosed '{{{}' in template", name)),
+                        }
+                    }
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(parse_placeholder(&name)?);
+                }
+                '}' => return Err("Unmatched '}' in template (use '}}' for a literal brace)".to_string()),
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        Ok(Self { parts })
+    }
+
+    /// Expands the template for a scan, merging adjacent text into one action
+    pub fn render(&self, scan: &BarcodeMessage, now: DateTime<Local>) -> Vec<KeyAction> {
+        let mut actions: Vec<KeyAction> = Vec::new();
+
+        for part in &self.parts {
+            let text = match part {
+                Part::Key(key) => {
+                    actions.push(KeyAction::Key(*key));
+                    continue;
+                }
+                Part::Literal(text) => text.clone(),
+                Part::Barcode => scan.barcode.clone(),
+                Part::BarcodeType => scan.barcode_type.clone().unwrap_or_default(),
+                Part::DeviceId => scan.device_id.clone(),
+                Part::DeviceName => scan.device_name.clone().unwrap_or_default(),
+                Part::DateTime(format) => now.format(format).to_string(),
+                Part::Gs1(ai) => scan.gs1.as_ref()
+                    .and_then(|gs1| gs1.get(ai))
+                    .unwrap_or_default()
+                    .to_string(),
+                Part::Expiry(format) => scan.gs1.as_ref()
+                    .and_then(|gs1| gs1.expiry.as_deref())
+                    .and_then(|expiry| NaiveDate::parse_from_str(expiry, "%Y-%m-%d").ok())
+                    .map(|date| date.format(format).to_string())
+                    .unwrap_or_default(),
+            };
+
+            if text.is_empty() {
+                continue;
+            }
+            match actions.last_mut() {
+                Some(KeyAction::Text(previous)) => previous.push_str(&text),
+                _ => actions.push(KeyAction::Text(text)),
+            }
+        }
+
+        actions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn scan() -> BarcodeMessage {
+        BarcodeMessage {
+            barcode: "7891234567895".to_string(),
+            timestamp: 1,
+            device_id: "d1".to_string(),
+            device_name: Some("Zebra".to_string()),
+            barcode_type: Some("EAN_13".to_string()),
+            gs1: None,
+        }
+    }
+
+    #[test]
+    fn test_render_template() {
+        let template = Template::parse("{F2}{barcode}{TAB}{date:%Y-%m-%d}{ENTER}{enter}").unwrap();
+        let now = Local.with_ymd_and_hms(2024, 3, 9, 10, 0, 0).unwrap();
+
+        assert_eq!(template.render(&scan(), now), vec![
+            KeyAction::Key(SpecialKey::F(2)),
+            KeyAction::Text("7891234567895".to_string()),
+            KeyAction::Key(SpecialKey::Tab),
+            KeyAction::Text("2024-03-09".to_string()),
+            KeyAction::Key(SpecialKey::Enter),
+            KeyAction::Key(SpecialKey::Enter),
+        ]);
+    }
+
+    #[test]
+    fn test_default_template_matches_constant() {
+        assert_eq!(Template::parse(DEFAULT_TEMPLATE).unwrap(), Template::default());
+    }
+
+    #[test]
+    fn test_literals_merge_with_fields() {
+        let template = Template::parse("{{{device_name}}}:{barcode}").unwrap();
+        let now = Local::now();
+        assert_eq!(template.render(&scan(), now), vec![KeyAction::Text("{Zebra}:7891234567895".to_string())]);
+    }
+
+    #[test]
+    fn test_gs1_fields() {
+        let gs1_scan = BarcodeMessage {
+            barcode: "0109501101530003172512311012AB".to_string(),
+            gs1: crate::gs1::parse("0109501101530003172512311012AB").ok(),
+            ..scan()
+        };
+        let template = Template::parse("{gtin}{TAB}{lot}{TAB}{expiry:%d/%m/%Y}{gs1:21}{ENTER}").unwrap();
+
+        assert_eq!(template.render(&gs1_scan, Local::now()), vec![
+            KeyAction::Text("09501101530003".to_string()),
+            KeyAction::Key(SpecialKey::Tab),
+            KeyAction::Text("12AB".to_string()),
+            KeyAction::Key(SpecialKey::Tab),
+            KeyAction::Text("31/12/2025".to_string()),
+            KeyAction::Key(SpecialKey::Enter),
+        ]);
+        // Non-GS1 scans leave the fields empty
+        assert_eq!(template.render(&scan(), Local::now()).len(), 3);
+    }
+
+    #[test]
+    fn test_invalid_templates() {
+        assert!(Template::parse("{barcode").is_err());
+        assert!(Template::parse("{unknown}").is_err());
+        assert!(Template::parse("{F99}").is_err());
+        assert!(Template::parse("{ENTER:2}").is_err());
+        assert!(Template::parse("a}b").is_err());
+        assert!(Template::parse("{date:%Q}").is_err());
+        assert!(Template::parse("{gs1:ab}").is_err());
+        assert!(Template::parse("{gs1}").is_err());
+    }
+}
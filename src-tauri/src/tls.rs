@@ -0,0 +1,42 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Names embedded in the self-signed certificate. Phones pin the fingerprint
+/// instead of validating the hostname, so these are informational only.
+const SUBJECT_ALT_NAMES: &[&str] = &["scanlink.local", "localhost"];
+
+/// Self-signed certificate used to serve wss://
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsIdentity {
+    pub cert_pem: String,
+    pub key_pem: String,
+    /// SHA-256 of the DER certificate, as colon-separated uppercase hex
+    pub fingerprint: String,
+    pub created_at: String,
+}
+
+/// Generates a new self-signed certificate and private key
+pub fn generate_identity() -> Result<TlsIdentity, String> {
+    let names: Vec<String> = SUBJECT_ALT_NAMES.iter().map(|s| s.to_string()).collect();
+    let certified = rcgen::generate_simple_self_signed(names)
+        .map_err(|e| format!("Failed to generate TLS certificate: {}", e))?;
+
+    let fingerprint = fingerprint_sha256(certified.cert.der().as_ref());
+    log::info!("Generated TLS certificate with fingerprint {}", fingerprint);
+
+    Ok(TlsIdentity {
+        cert_pem: certified.cert.pem(),
+        key_pem: certified.key_pair.serialize_pem(),
+        fingerprint,
+        created_at: chrono::Utc::now().to_rfc3339(),
+    })
+}
+
+/// Formats the SHA-256 digest of a DER certificate as `AB:CD:...`
+pub fn fingerprint_sha256(der: &[u8]) -> String {
+    Sha256::digest(der)
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
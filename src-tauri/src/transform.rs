@@ -0,0 +1,236 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use crate::models::BarcodeMessage;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaseChange {
+    Upper,
+    Lower,
+}
+
+/// What a rule does to the barcode
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RuleAction {
+    /// Replaces every match; `$1`/`${name}` insert capture groups
+    RegexReplace { pattern: String, replacement: String },
+    /// Removes the prefix when present
+    StripPrefix { prefix: String },
+    /// Removes the suffix when present
+    StripSuffix { suffix: String },
+    AddPrefix { prefix: String },
+    AddSuffix { suffix: String },
+    Case { case: CaseChange },
+    /// Keeps `length` characters (or the rest) starting at character `start`
+    Substring {
+        start: usize,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        length: Option<usize>,
+    },
+    /// Replaces the whole barcode when it is a key of the table
+    Lookup { table: HashMap<String, String> },
+}
+
+/// One step of the transformation pipeline, as stored in `AppConfig::transform_rules`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransformRule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Only apply to scans from this device
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
+    /// Only apply to this symbology (e.g. "EAN_13", compared case-insensitively)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub barcode_type: Option<String>,
+    #[serde(flatten)]
+    pub action: RuleAction,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Result of trying a rule against a sample barcode
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleTestResult {
+    pub barcode: String,
+    /// False when the rule is disabled or its scope does not match the sample
+    pub applied: bool,
+}
+
+/// Rule with its regex compiled, ready to run
+#[derive(Debug, Clone)]
+pub struct CompiledRule {
+    rule: TransformRule,
+    regex: Option<Regex>,
+}
+
+impl TransformRule {
+    /// Validates the rule and compiles its pattern
+    pub fn compile(&self) -> Result<CompiledRule, String> {
+        let regex = match &self.action {
+            RuleAction::RegexReplace { pattern, .. } => Some(
+                Regex::new(pattern).map_err(|e| format!("Invalid pattern '{}': {}", pattern, e))?,
+            ),
+            _ => None,
+        };
+        Ok(CompiledRule { rule: self.clone(), regex })
+    }
+
+    fn label(&self) -> String {
+        self.name.clone().unwrap_or_else(|| format!("{:?}", self.action))
+    }
+}
+
+impl CompiledRule {
+    pub fn matches(&self, device_id: &str, barcode_type: Option<&str>) -> bool {
+        if !self.rule.enabled {
+            return false;
+        }
+        if let Some(device) = &self.rule.device_id {
+            if device != device_id {
+                return false;
+            }
+        }
+        match &self.rule.barcode_type {
+            Some(wanted) => barcode_type.is_some_and(|t| t.eq_ignore_ascii_case(wanted)),
+            None => true,
+        }
+    }
+
+    /// Applies the action regardless of scope
+    pub fn apply(&self, barcode: &str) -> String {
+        match &self.rule.action {
+            RuleAction::RegexReplace { replacement, .. } => match &self.regex {
+                Some(regex) => regex.replace_all(barcode, replacement.as_str()).into_owned(),
+                None => barcode.to_string(),
+            },
+            RuleAction::StripPrefix { prefix } => barcode.strip_prefix(prefix.as_str()).unwrap_or(barcode).to_string(),
+            RuleAction::StripSuffix { suffix } => barcode.strip_suffix(suffix.as_str()).unwrap_or(barcode).to_string(),
+            RuleAction::AddPrefix { prefix } => format!("{}{}", prefix, barcode),
+            RuleAction::AddSuffix { suffix } => format!("{}{}", barcode, suffix),
+            RuleAction::Case { case: CaseChange::Upper } => barcode.to_uppercase(),
+            RuleAction::Case { case: CaseChange::Lower } => barcode.to_lowercase(),
+            RuleAction::Substring { start, length } => {
+                let rest = barcode.chars().skip(*start);
+                match length {
+                    Some(length) => rest.take(*length).collect(),
+                    None => rest.collect(),
+                }
+            }
+            RuleAction::Lookup { table } => table.get(barcode).cloned().unwrap_or_else(|| barcode.to_string()),
+        }
+    }
+}
+
+/// Compiles the configured rules, skipping (and logging) invalid ones.
+/// Rules are validated when set, so this only drops rules from hand-edited configs.
+pub fn compile_rules(rules: &[TransformRule]) -> Vec<CompiledRule> {
+    rules
+        .iter()
+        .filter_map(|rule| match rule.compile() {
+            Ok(compiled) => Some(compiled),
+            Err(e) => {
+                log::error!("Skipping transform rule {}: {}", rule.label(), e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Runs the rules in order over a scan's barcode, each rule seeing the previous one's output
+pub fn apply_rules(rules: &[CompiledRule], scan: &BarcodeMessage) -> String {
+    rules
+        .iter()
+        .filter(|rule| rule.matches(&scan.device_id, scan.barcode_type.as_deref()))
+        .fold(scan.barcode.clone(), |barcode, rule| {
+            let transformed = rule.apply(&barcode);
+            if transformed != barcode {
+                log::debug!("Rule {} rewrote '{}' to '{}'", rule.rule.label(), barcode, transformed);
+            }
+            transformed
+        })
+}
+
+/// Tries one rule against a sample, as the scan pipeline would
+pub fn test_rule(
+    rule: &TransformRule,
+    barcode: &str,
+    device_id: Option<&str>,
+    barcode_type: Option<&str>,
+) -> Result<RuleTestResult, String> {
+    let compiled = rule.compile()?;
+    // Without a sample device, a device-scoped rule is tested as if it matched
+    let device_id = device_id.or(rule.device_id.as_deref()).unwrap_or_default();
+
+    if compiled.matches(device_id, barcode_type) {
+        Ok(RuleTestResult { barcode: compiled.apply(barcode), applied: true })
+    } else {
+        Ok(RuleTestResult { barcode: barcode.to_string(), applied: false })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(action: RuleAction) -> TransformRule {
+        TransformRule { name: None, enabled: true, device_id: None, barcode_type: None, action }
+    }
+
+    fn scan(barcode: &str, barcode_type: Option<&str>) -> BarcodeMessage {
+        BarcodeMessage {
+            barcode: barcode.to_string(),
+            timestamp: 1,
+            device_id: "d1".to_string(),
+            device_name: None,
+            barcode_type: barcode_type.map(String::from),
+            gs1: None,
+        }
+    }
+
+    #[test]
+    fn test_rules_run_in_order() {
+        let rules = compile_rules(&[
+            rule(RuleAction::RegexReplace { pattern: "^0+".to_string(), replacement: String::new() }),
+            rule(RuleAction::Substring { start: 0, length: Some(6) }),
+            rule(RuleAction::Case { case: CaseChange::Upper }),
+            rule(RuleAction::AddSuffix { suffix: "-x".to_string() }),
+        ]);
+
+        assert_eq!(apply_rules(&rules, &scan("000abc1234", None)), "ABC123-x");
+    }
+
+    #[test]
+    fn test_rule_scope() {
+        let mut ean_only = rule(RuleAction::StripPrefix { prefix: "789".to_string() });
+        ean_only.barcode_type = Some("EAN_13".to_string());
+        let mut other_device = rule(RuleAction::AddPrefix { prefix: "!".to_string() });
+        other_device.device_id = Some("d2".to_string());
+        let rules = compile_rules(&[ean_only, other_device]);
+
+        assert_eq!(apply_rules(&rules, &scan("7891234", Some("ean_13"))), "1234");
+        assert_eq!(apply_rules(&rules, &scan("7891234", Some("QR_CODE"))), "7891234");
+        assert_eq!(apply_rules(&rules, &scan("7891234", None)), "7891234");
+    }
+
+    #[test]
+    fn test_rule_format_and_lookup() {
+        let json = r#"{"type":"lookup","table":{"A1":"SKU-001"},"device_id":"d1"}"#;
+        let lookup: TransformRule = serde_json::from_str(json).unwrap();
+        assert!(lookup.enabled);
+
+        let result = test_rule(&lookup, "A1", None, None).unwrap();
+        assert!(result.applied);
+        assert_eq!(result.barcode, "SKU-001");
+        assert_eq!(test_rule(&lookup, "B2", None, None).unwrap().barcode, "B2");
+        assert!(!test_rule(&lookup, "A1", Some("d2"), None).unwrap().applied);
+
+        let invalid = rule(RuleAction::RegexReplace { pattern: "(".to_string(), replacement: String::new() });
+        assert!(test_rule(&invalid, "A1", None, None).is_err());
+    }
+}
@@ -0,0 +1,461 @@
+//! Virtual keyboard on `/dev/uinput`. Works on X11 and every Wayland compositor
+//! without external tools, as the events come from a (virtual) kernel input device.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use xkbcommon::xkb;
+use crate::template::SpecialKey;
+
+const UINPUT_PATH: &str = "/dev/uinput";
+const DEVICE_NAME: &str = "ScanLink virtual keyboard";
+
+// From linux/uinput.h and linux/input-event-codes.h
+const UI_SET_EVBIT: libc::c_ulong = 0x4004_5564;
+const UI_SET_KEYBIT: libc::c_ulong = 0x4004_5565;
+const UI_DEV_SETUP: libc::c_ulong = 0x405c_5503;
+const UI_DEV_CREATE: libc::c_ulong = 0x5501;
+const UI_DEV_DESTROY: libc::c_ulong = 0x5502;
+const EV_SYN: u16 = 0x00;
+const EV_KEY: u16 = 0x01;
+const SYN_REPORT: u16 = 0;
+const BUS_VIRTUAL: u16 = 0x06;
+const KEY_LEFTCTRL: u16 = 29;
+const KEY_LEFTSHIFT: u16 = 42;
+const KEY_RIGHTALT: u16 = 100;
+const KEY_V: u16 = 47;
+/// Highest key code registered on the device (covers the whole keyboard range)
+const MAX_KEY_CODE: u16 = 255;
+
+/// Time the display server needs to pick up a freshly created input device
+const DEVICE_SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// US layout, used when no XKB keymap can be compiled: each character of
+/// the two strings is produced by the key code at the same position
+const US_PLAIN: &str = "`1234567890-=qwertyuiop[]\\asdfghjkl;'zxcvbnm,./";
+const US_SHIFTED: &str = "~!@#$%^&*()_+QWERTYUIOP{}|ASDFGHJKL:\"ZXCVBNM<>?";
+const US_CODES: [u16; 47] = [
+    41, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13,
+    16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 43,
+    30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 40,
+    44, 45, 46, 47, 48, 49, 50, 51, 52, 53,
+];
+
+/// Whether the native backend can be used
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UinputStatus {
+    Available,
+    /// The uinput kernel module is not loaded
+    Missing,
+    /// The device exists but this user may not write to it
+    PermissionDenied,
+}
+
+impl UinputStatus {
+    /// How to fix the problem, for logs and diagnostics
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            UinputStatus::Available => None,
+            UinputStatus::Missing => Some("Load the uinput module: sudo modprobe uinput"),
+            UinputStatus::PermissionDenied => Some(
+                "Allow access to /dev/uinput with a udev rule, e.g. \
+                 KERNEL==\"uinput\", GROUP=\"input\", MODE=\"0660\", TAG+=\"uaccess\" \
+                 in /etc/udev/rules.d/60-scanlink-uinput.rules, then add yourself to the input group",
+            ),
+        }
+    }
+}
+
+pub fn status() -> UinputStatus {
+    let path = Path::new(UINPUT_PATH);
+    if !path.exists() {
+        return UinputStatus::Missing;
+    }
+
+    let Ok(c_path) = CString::new(path.as_os_str().as_bytes()) else {
+        return UinputStatus::Missing;
+    };
+    // SAFETY: c_path is a valid NUL-terminated string
+    if unsafe { libc::access(c_path.as_ptr(), libc::W_OK) } == 0 {
+        UinputStatus::Available
+    } else {
+        UinputStatus::PermissionDenied
+    }
+}
+
+/// Physical key (evdev code) and modifiers producing a character
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct KeyStroke {
+    code: u16,
+    shift: bool,
+    altgr: bool,
+}
+
+fn us_keymap() -> HashMap<char, KeyStroke> {
+    let mut map = HashMap::new();
+    for (i, (plain, shifted)) in US_PLAIN.chars().zip(US_SHIFTED.chars()).enumerate() {
+        map.insert(plain, KeyStroke { code: US_CODES[i], shift: false, altgr: false });
+        map.insert(shifted, KeyStroke { code: US_CODES[i], shift: true, altgr: false });
+    }
+    map.insert(' ', KeyStroke { code: 57, shift: false, altgr: false });
+    map
+}
+
+/// Reads the first layout and variant from Debian's /etc/default/keyboard
+fn parse_default_keyboard(content: &str) -> Option<(String, String)> {
+    let value = |key: &str| {
+        content
+            .lines()
+            .find_map(|line| line.trim().strip_prefix(key)?.strip_prefix('='))
+            .map(|v| v.trim().trim_matches('"').split(',').next().unwrap_or_default().to_string())
+    };
+    let layout = value("XKBLAYOUT").filter(|l| !l.is_empty())?;
+    Some((layout, value("XKBVARIANT").unwrap_or_default()))
+}
+
+/// Reads the layout from `localectl status` (systemd)
+fn parse_localectl(output: &str) -> Option<(String, String)> {
+    let value = |key: &str| {
+        output
+            .lines()
+            .find_map(|line| line.trim().strip_prefix(key))
+            .map(|v| v.trim().split(',').next().unwrap_or_default().to_string())
+    };
+    let layout = value("X11 Layout:").filter(|l| !l.is_empty() && l != "n/a")?;
+    Some((layout, value("X11 Variant:").unwrap_or_default()))
+}
+
+/// The system keyboard layout. Empty names make libxkbcommon use the
+/// XKB_DEFAULT_* environment variables or its built-in default.
+fn system_layout() -> (String, String) {
+    if std::env::var_os("XKB_DEFAULT_LAYOUT").is_some() {
+        return (String::new(), String::new());
+    }
+    fs::read_to_string("/etc/default/keyboard")
+        .ok()
+        .and_then(|content| parse_default_keyboard(&content))
+        .or_else(|| {
+            let output = Command::new("localectl").arg("status").output().ok()?;
+            parse_localectl(&String::from_utf8_lossy(&output.stdout))
+        })
+        .unwrap_or_default()
+}
+
+/// Splits an XKB layout name like "de(nodeadkeys)" into layout and variant
+fn parse_layout_name(name: &str) -> (String, String) {
+    let name = name.trim();
+    match name.split_once('(') {
+        Some((layout, variant)) => (layout.trim().to_string(), variant.trim_end_matches(')').trim().to_string()),
+        None => (name.to_string(), String::new()),
+    }
+}
+
+/// Keymap of the configured layout, or of the system one when none is configured
+fn compile_keymap(layout_override: Option<&str>) -> HashMap<char, KeyStroke> {
+    let (layout, variant) = layout_override.map(parse_layout_name).unwrap_or_else(system_layout);
+    let keymap = xkb_keymap(&layout, &variant).unwrap_or_else(|| {
+        log::warn!("Could not compile XKB keymap for layout '{}', assuming US layout", layout);
+        us_keymap()
+    });
+    log::info!("Virtual keyboard uses layout '{}' ({} characters)", layout, keymap.len());
+    keymap
+}
+
+/// Maps every character of the layout to the key and modifiers (Shift, AltGr) producing it
+fn xkb_keymap(layout: &str, variant: &str) -> Option<HashMap<char, KeyStroke>> {
+    let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+    let keymap = xkb::Keymap::new_from_names(&context, "", "", layout, variant, None, xkb::KEYMAP_COMPILE_NO_FLAGS)?;
+
+    let mod_mask = |name: &str| match keymap.mod_get_index(name) {
+        index if index < 32 => 1u32 << index,
+        _ => 0,
+    };
+    let shift = mod_mask(xkb::MOD_NAME_SHIFT);
+    let level3 = mod_mask(xkb::MOD_NAME_ISO_LEVEL3_SHIFT);
+
+    let mut map = HashMap::new();
+    keymap.key_for_each(|keymap, key| {
+        // XKB key codes are evdev codes offset by 8
+        let Some(code) = key.raw().checked_sub(8).and_then(|code| u16::try_from(code).ok()) else {
+            return;
+        };
+
+        for level in 0..keymap.num_levels_for_key(key, 0) {
+            let mut masks = [0u32; 8];
+            let count = keymap.key_get_mods_for_level(key, 0, level, &mut masks);
+            // Only levels reachable with Shift and/or AltGr
+            let Some(mask) = masks[..count.min(masks.len())]
+                .iter()
+                .copied()
+                .filter(|mask| mask & !(shift | level3) == 0)
+                .min_by_key(|mask| mask.count_ones())
+            else {
+                continue;
+            };
+
+            for sym in keymap.key_get_syms_by_level(key, 0, level) {
+                let Some(c) = char::from_u32(xkb::keysym_to_utf32(*sym)) else {
+                    continue;
+                };
+                if c == '\0' || c.is_control() {
+                    continue;
+                }
+                // Keep the first (main block) key when several produce the character
+                map.entry(c).or_insert(KeyStroke {
+                    code,
+                    shift: mask & shift != 0,
+                    altgr: mask & level3 != 0,
+                });
+            }
+        }
+    });
+
+    if map.is_empty() {
+        None
+    } else {
+        Some(map)
+    }
+}
+
+fn special_key_code(key: SpecialKey) -> u16 {
+    match key {
+        SpecialKey::Enter => 28,
+        SpecialKey::Tab => 15,
+        SpecialKey::Escape => 1,
+        SpecialKey::Backspace => 14,
+        SpecialKey::Delete => 111,
+        SpecialKey::Space => 57,
+        SpecialKey::Up => 103,
+        SpecialKey::Down => 108,
+        SpecialKey::Left => 105,
+        SpecialKey::Right => 106,
+        SpecialKey::Home => 102,
+        SpecialKey::End => 107,
+        SpecialKey::PageUp => 104,
+        SpecialKey::PageDown => 109,
+        SpecialKey::F(n @ 1..=10) => 58 + n as u16,
+        SpecialKey::F(11) => 87,
+        SpecialKey::F(12) => 88,
+        SpecialKey::F(n) => 170 + n as u16, // F13 = 183 ... F20 = 190
+    }
+}
+
+fn ioctl(file: &File, request: libc::c_ulong, arg: libc::c_ulong) -> Result<(), String> {
+    // SAFETY: the fd is open for the lifetime of `file`; the requests used take an int argument
+    if unsafe { libc::ioctl(file.as_raw_fd(), request as _, arg) } < 0 {
+        return Err(format!("uinput ioctl {:#x} failed: {}", request, std::io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+/// Virtual keyboard device, kept for the lifetime of the app so the display
+/// server only has to discover it once.
+///
+/// The kernel device has no layout: the display server applies the session's
+/// layout to its key codes. The keymap used to pick key codes therefore comes
+/// from the system settings, which can differ from the live session (e.g. a
+/// layout switched in the desktop settings); `layout_override` fixes that.
+pub struct VirtualKeyboard {
+    file: File,
+    keymap: HashMap<char, KeyStroke>,
+    layout_override: Option<String>,
+}
+
+impl VirtualKeyboard {
+    fn create() -> Result<Self, String> {
+        let file = OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(UINPUT_PATH)
+            .map_err(|e| format!("Failed to open {}: {}", UINPUT_PATH, e))?;
+
+        ioctl(&file, UI_SET_EVBIT, EV_KEY as libc::c_ulong)?;
+        ioctl(&file, UI_SET_EVBIT, EV_SYN as libc::c_ulong)?;
+        for code in 1..=MAX_KEY_CODE {
+            ioctl(&file, UI_SET_KEYBIT, code as libc::c_ulong)?;
+        }
+
+        // SAFETY: uinput_setup is plain old data, all-zero is a valid value
+        let mut setup: libc::uinput_setup = unsafe { std::mem::zeroed() };
+        setup.id = libc::input_id { bustype: BUS_VIRTUAL, vendor: 0x5343, product: 0x4c4b, version: 1 };
+        for (dst, src) in setup.name.iter_mut().zip(DEVICE_NAME.bytes()) {
+            *dst = src as libc::c_char;
+        }
+        // SAFETY: UI_DEV_SETUP reads a uinput_setup, which outlives the call
+        if unsafe { libc::ioctl(file.as_raw_fd(), UI_DEV_SETUP as _, &setup as *const libc::uinput_setup) } < 0 {
+            return Err(format!("uinput setup failed: {}", std::io::Error::last_os_error()));
+        }
+        ioctl(&file, UI_DEV_CREATE, 0)?;
+        log::info!("Created uinput virtual keyboard");
+
+        let keymap = compile_keymap(None);
+        thread::sleep(DEVICE_SETTLE_TIME);
+        Ok(Self { file, keymap, layout_override: None })
+    }
+
+    /// Switches the keymap to the configured layout (None = system layout)
+    fn use_layout(&mut self, layout_override: Option<&str>) {
+        if self.layout_override.as_deref() != layout_override {
+            self.keymap = compile_keymap(layout_override);
+            self.layout_override = layout_override.map(str::to_string);
+        }
+    }
+
+    fn emit(&self, events: &[(u16, u16, i32)]) -> Result<(), String> {
+        let mut buffer = Vec::with_capacity(events.len() * std::mem::size_of::<libc::input_event>());
+        for &(type_, code, value) in events {
+            // SAFETY: input_event is plain old data; the kernel fills in the timestamp
+            let mut event: libc::input_event = unsafe { std::mem::zeroed() };
+            event.type_ = type_;
+            event.code = code;
+            event.value = value;
+            // SAFETY: reading the bytes of a fully initialised repr(C) struct
+            let bytes = unsafe {
+                std::slice::from_raw_parts(
+                    &event as *const libc::input_event as *const u8,
+                    std::mem::size_of::<libc::input_event>(),
+                )
+            };
+            buffer.extend_from_slice(bytes);
+        }
+
+        (&self.file)
+            .write_all(&buffer)
+            .map_err(|e| format!("Failed to write to {}: {}", UINPUT_PATH, e))
+    }
+
+    /// Presses and releases a key while holding the given modifiers
+    fn stroke(&self, code: u16, modifiers: &[u16]) -> Result<(), String> {
+        let mut events = Vec::new();
+        for &modifier in modifiers {
+            events.push((EV_KEY, modifier, 1));
+        }
+        events.extend([(EV_KEY, code, 1), (EV_SYN, SYN_REPORT, 0), (EV_KEY, code, 0)]);
+        for &modifier in modifiers.iter().rev() {
+            events.push((EV_KEY, modifier, 0));
+        }
+        events.push((EV_SYN, SYN_REPORT, 0));
+        self.emit(&events)
+    }
+
+    pub fn press_key(&self, key: SpecialKey) -> Result<(), String> {
+        self.stroke(special_key_code(key), &[])
+    }
+
+    pub fn can_type(&self, c: char) -> bool {
+        matches!(c, '\n' | '\r' | '\t') || self.keymap.contains_key(&c)
+    }
+
+    /// Types a character of the current layout. Fails for characters the layout cannot produce.
+    pub fn type_char(&self, c: char) -> Result<(), String> {
+        match c {
+            '\n' | '\r' => return self.press_key(SpecialKey::Enter),
+            '\t' => return self.press_key(SpecialKey::Tab),
+            _ => {}
+        }
+
+        let stroke = self.keymap.get(&c).ok_or_else(|| format!("No key produces {:?}", c))?;
+        let mut modifiers = Vec::new();
+        if stroke.shift {
+            modifiers.push(KEY_LEFTSHIFT);
+        }
+        if stroke.altgr {
+            modifiers.push(KEY_RIGHTALT);
+        }
+        self.stroke(stroke.code, &modifiers)
+    }
+
+    /// Ctrl+V, using whichever key produces 'v' in the current layout
+    pub fn paste_shortcut(&self) -> Result<(), String> {
+        let code = self.keymap.get(&'v').map(|stroke| stroke.code).unwrap_or(KEY_V);
+        self.stroke(code, &[KEY_LEFTCTRL])
+    }
+}
+
+impl Drop for VirtualKeyboard {
+    fn drop(&mut self) {
+        let _ = ioctl(&self.file, UI_DEV_DESTROY, 0);
+    }
+}
+
+static KEYBOARD: Mutex<Option<VirtualKeyboard>> = Mutex::new(None);
+
+/// Runs `f` with the shared virtual keyboard, creating it on first use
+fn with_shared_keyboard<R>(f: impl FnOnce(&mut VirtualKeyboard) -> Result<R, String>) -> Result<R, String> {
+    let mut guard = KEYBOARD.lock().unwrap();
+    if guard.is_none() {
+        *guard = Some(VirtualKeyboard::create()?);
+    }
+    match guard.as_mut() {
+        Some(keyboard) => f(keyboard),
+        None => Err("Virtual keyboard not available".to_string()),
+    }
+}
+
+/// Runs `f` with the shared virtual keyboard, keeping its current layout
+pub fn with_keyboard<R>(f: impl FnOnce(&VirtualKeyboard) -> Result<R, String>) -> Result<R, String> {
+    with_shared_keyboard(|keyboard| f(keyboard))
+}
+
+/// Like `with_keyboard`, typing with the given XKB layout (None = system layout)
+pub fn with_keyboard_layout<R>(
+    layout_override: Option<&str>,
+    f: impl FnOnce(&VirtualKeyboard) -> Result<R, String>,
+) -> Result<R, String> {
+    with_shared_keyboard(|keyboard| {
+        keyboard.use_layout(layout_override);
+        f(keyboard)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_us_keymap() {
+        assert_eq!(US_PLAIN.chars().count(), US_CODES.len());
+        assert_eq!(US_SHIFTED.chars().count(), US_CODES.len());
+
+        let map = us_keymap();
+        assert_eq!(map[&'a'], KeyStroke { code: 30, shift: false, altgr: false });
+        assert_eq!(map[&'A'], KeyStroke { code: 30, shift: true, altgr: false });
+        assert_eq!(map[&'@'], KeyStroke { code: 3, shift: true, altgr: false });
+        assert!(!map.contains_key(&'é'));
+    }
+
+    #[test]
+    fn test_layout_detection() {
+        let debian = "XKBMODEL=\"pc105\"\nXKBLAYOUT=\"de,us\"\nXKBVARIANT=\"nodeadkeys,\"\n";
+        assert_eq!(parse_default_keyboard(debian), Some(("de".to_string(), "nodeadkeys".to_string())));
+
+        let localectl = "   System Locale: LANG=pt_BR.UTF-8\n       X11 Layout: br\n        X11 Model: pc105\n";
+        assert_eq!(parse_localectl(localectl), Some(("br".to_string(), String::new())));
+        assert_eq!(parse_localectl("X11 Layout: n/a"), None);
+    }
+
+    #[test]
+    fn test_parse_layout_name() {
+        assert_eq!(parse_layout_name("de(nodeadkeys)"), ("de".to_string(), "nodeadkeys".to_string()));
+        assert_eq!(parse_layout_name(" br "), ("br".to_string(), String::new()));
+    }
+
+    #[test]
+    fn test_special_key_codes() {
+        assert_eq!(special_key_code(SpecialKey::F(1)), 59);
+        assert_eq!(special_key_code(SpecialKey::F(10)), 68);
+        assert_eq!(special_key_code(SpecialKey::F(13)), 183);
+        assert_eq!(special_key_code(SpecialKey::F(20)), 190);
+    }
+}
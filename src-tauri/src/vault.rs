@@ -0,0 +1,507 @@
+//! Encryption of the secret material in config.json (master token, token keys,
+//! TLS private key). The key lives in the OS keyring (Secret Service on Linux,
+//! the macOS keychain, DPAPI on Windows), or is derived from a passphrase with
+//! Argon2 on headless machines without one.
+
+use std::fmt;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use argon2::Argon2;
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use crate::security;
+
+/// Config field holding the encrypted secrets
+pub const SEALED_FIELD: &str = "sealed_secrets";
+
+/// Environment variable with the passphrase for machines without a keyring
+pub const PASSPHRASE_ENV: &str = "SCANLINK_CONFIG_PASSPHRASE";
+
+/// Authenticated alongside the secrets so they cannot be swapped into another file type
+const AAD: &[u8] = b"scanlink-config-secrets";
+
+const KEYRING_SERVICE: &str = "ScanLink";
+const KEYRING_ACCOUNT: &str = "config-key";
+const SALT_SIZE: usize = 16;
+
+/// Where the sealing key comes from
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeySource {
+    Keyring,
+    Passphrase,
+}
+
+/// Stored in place of the plaintext secrets
+#[derive(Debug, Serialize, Deserialize)]
+struct SealedSecrets {
+    source: KeySource,
+    /// Argon2 salt (base64), passphrase keys only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    salt: Option<String>,
+    /// AES-256-GCM over a JSON object of secrets by JSON pointer
+    data: String,
+}
+
+/// Why sealed secrets could not be decrypted
+#[derive(Debug, Clone, PartialEq)]
+pub enum UnsealError {
+    /// The key is missing, locked or wrong. The file itself may be fine and
+    /// must not be treated as corrupt.
+    KeyUnavailable(String),
+    /// The sealed data is malformed
+    Invalid(String),
+}
+
+impl fmt::Display for UnsealError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::KeyUnavailable(e) | Self::Invalid(e) => f.write_str(e),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct ConfigKey {
+    source: KeySource,
+    salt: Option<String>,
+    /// base64 AES-256 key
+    key: String,
+}
+
+/// Source of config keys. The process uses `system()`; tests build their own
+/// so they never touch the environment, the keyring or each other.
+pub struct Keys {
+    /// Also look at `PASSPHRASE_ENV` and the OS keyring
+    system: bool,
+    /// Explicit passphrase, taking precedence over the environment and the keyring
+    passphrase: Mutex<Option<String>>,
+    /// Key resolved on first use (inner None: no key available), so the keyring
+    /// and Argon2 are not hit on every save
+    cached: Mutex<Option<Option<ConfigKey>>>,
+}
+
+static SYSTEM_KEYS: Keys = Keys {
+    system: true,
+    passphrase: Mutex::new(None),
+    cached: Mutex::new(None),
+};
+
+/// Keys of this process: passphrase, environment, then OS keyring
+pub fn system() -> &'static Keys {
+    &SYSTEM_KEYS
+}
+
+/// Sets the passphrase (e.g. from the daemon's `--passphrase-file`), taking
+/// precedence over the environment and the keyring for new keys
+pub fn set_passphrase(passphrase: String) {
+    SYSTEM_KEYS.set_passphrase(passphrase);
+}
+
+fn derive_key(passphrase: &str, salt: &str) -> Result<String, String> {
+    let salt = BASE64.decode(salt).map_err(|e| format!("Invalid salt: {}", e))?;
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+        .map_err(|e| format!("Key derivation failed: {}", e))?;
+    Ok(BASE64.encode(key))
+}
+
+fn passphrase_key(passphrase: &str, salt: Option<String>) -> Result<ConfigKey, String> {
+    let salt = salt.unwrap_or_else(|| {
+        let mut bytes = [0u8; SALT_SIZE];
+        rand::rngs::OsRng.fill_bytes(&mut bytes);
+        BASE64.encode(bytes)
+    });
+    Ok(ConfigKey {
+        source: KeySource::Passphrase,
+        key: derive_key(passphrase, &salt)?,
+        salt: Some(salt),
+    })
+}
+
+/// Runs a keyring tool and returns its trimmed stdout on success
+fn keyring_command(program: &str, args: &[&str], stdin: Option<&str>) -> Option<String> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .ok()?;
+    if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        pipe.write_all(input.as_bytes()).ok()?;
+    }
+    let output = child.wait_with_output().ok()?;
+    if !output.status.success() {
+        log::debug!("{} failed: {}", program, String::from_utf8_lossy(&output.stderr).trim());
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Windows has no keyring CLI; the key is encrypted with DPAPI for the current
+/// user (what Credential Manager uses) and kept next to the config
+const DPAPI_KEY_FILE: &str = "config.key";
+
+const DPAPI_UNPROTECT: &str = "Add-Type -AssemblyName System.Security; \
+    $blob = [Convert]::FromBase64String([Console]::In.ReadToEnd().Trim()); \
+    $key = [Security.Cryptography.ProtectedData]::Unprotect($blob, $null, 'CurrentUser'); \
+    [Console]::Out.Write([Text.Encoding]::UTF8.GetString($key))";
+
+const DPAPI_PROTECT: &str = "Add-Type -AssemblyName System.Security; \
+    $key = [Text.Encoding]::UTF8.GetBytes([Console]::In.ReadToEnd().Trim()); \
+    $blob = [Security.Cryptography.ProtectedData]::Protect($key, $null, 'CurrentUser'); \
+    [Console]::Out.Write([Convert]::ToBase64String($blob))";
+
+fn powershell(script: &str, stdin: &str) -> Option<String> {
+    keyring_command("powershell", &["-NoProfile", "-NonInteractive", "-Command", script], Some(stdin))
+}
+
+fn dpapi_lookup() -> Option<String> {
+    let path = crate::storage::get_data_file_path(DPAPI_KEY_FILE).ok()?;
+    let blob = std::fs::read_to_string(path).ok()?;
+    powershell(DPAPI_UNPROTECT, blob.trim())
+}
+
+fn dpapi_store(key: &str) -> Option<String> {
+    let blob = powershell(DPAPI_PROTECT, key)?;
+    let path = crate::storage::get_data_file_path(DPAPI_KEY_FILE).ok()?;
+    std::fs::write(path, blob).ok().map(|_| String::new())
+}
+
+/// Reads the key from the freedesktop Secret Service (secret-tool), the macOS
+/// keychain or the DPAPI-protected key file on Windows
+fn keyring_lookup() -> Option<String> {
+    let key = if cfg!(target_os = "macos") {
+        keyring_command("security", &["find-generic-password", "-s", KEYRING_SERVICE, "-a", KEYRING_ACCOUNT, "-w"], None)
+    } else if cfg!(target_os = "linux") {
+        keyring_command("secret-tool", &["lookup", "service", KEYRING_SERVICE, "account", KEYRING_ACCOUNT], None)
+    } else if cfg!(target_os = "windows") {
+        dpapi_lookup()
+    } else {
+        None
+    };
+    key.filter(|k| !k.is_empty())
+}
+
+fn keyring_store(key: &str) -> bool {
+    let stored = if cfg!(target_os = "macos") {
+        // Commands read by `security -i` from stdin stay out of the process list
+        let command = format!(
+            "add-generic-password -U -s {} -a {} -w {}\n",
+            KEYRING_SERVICE, KEYRING_ACCOUNT, key
+        );
+        keyring_command("security", &["-i"], Some(&command))
+    } else if cfg!(target_os = "linux") {
+        keyring_command(
+            "secret-tool",
+            &["store", "--label=ScanLink config key", "service", KEYRING_SERVICE, "account", KEYRING_ACCOUNT],
+            Some(key),
+        )
+    } else if cfg!(target_os = "windows") {
+        dpapi_store(key)
+    } else {
+        None
+    };
+    // Only trust the keyring if the key reads back
+    stored.is_some() && keyring_lookup().as_deref() == Some(key)
+}
+
+/// Key from the keyring, creating one if none exists yet
+fn keyring_key() -> Option<ConfigKey> {
+    let key = keyring_lookup().or_else(|| {
+        let key = security::generate_secret_key();
+        keyring_store(&key).then_some(key)
+    })?;
+    Some(ConfigKey { source: KeySource::Keyring, salt: None, key })
+}
+
+impl Keys {
+    /// Keys from a fixed passphrase only
+    #[cfg(test)]
+    pub fn with_passphrase(passphrase: &str) -> Self {
+        Self {
+            system: false,
+            passphrase: Mutex::new(Some(passphrase.to_string())),
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// No key at all, as on a machine without keyring or passphrase
+    #[cfg(test)]
+    pub fn none() -> Self {
+        Self {
+            system: false,
+            passphrase: Mutex::new(None),
+            cached: Mutex::new(None),
+        }
+    }
+
+    pub fn set_passphrase(&self, passphrase: String) {
+        *self.passphrase.lock().unwrap() = Some(passphrase);
+        *self.cached.lock().unwrap() = None;
+    }
+
+    fn passphrase(&self) -> Option<String> {
+        let explicit = self.passphrase.lock().unwrap().clone();
+        explicit
+            .or_else(|| self.system.then(|| std::env::var(PASSPHRASE_ENV).ok()).flatten())
+            .filter(|p| !p.is_empty())
+    }
+
+    /// Key for writing secrets: the cached one, a passphrase key, or the keyring
+    fn sealing_key(&self) -> Option<ConfigKey> {
+        let mut cached = self.cached.lock().unwrap();
+        if cached.is_none() {
+            let key = match self.passphrase() {
+                Some(passphrase) => match passphrase_key(&passphrase, None) {
+                    Ok(key) => Some(key),
+                    Err(e) => {
+                        log::error!("{}", e);
+                        None
+                    }
+                },
+                None if self.system => keyring_key(),
+                None => None,
+            };
+            match key.as_ref().map(|key| key.source) {
+                Some(source) => log::info!("Config secrets are encrypted with a {:?} key", source),
+                None if self.system && cfg!(not(any(target_os = "linux", target_os = "macos", target_os = "windows"))) => log::warn!(
+                    "There is no supported keyring on this platform and {} is not set, config secrets are stored unencrypted",
+                    PASSPHRASE_ENV
+                ),
+                None => log::warn!(
+                    "No keyring available and {} is not set, config secrets are stored unencrypted",
+                    PASSPHRASE_ENV
+                ),
+            }
+            *cached = Some(key);
+        }
+        cached.clone().flatten()
+    }
+
+    /// Key that sealed an existing config
+    fn unsealing_key(&self, source: KeySource, salt: Option<&str>) -> Result<ConfigKey, UnsealError> {
+        let mut cached = self.cached.lock().unwrap();
+        if let Some(Some(key)) = cached.as_ref() {
+            if key.source == source && key.salt.as_deref() == salt {
+                return Ok(key.clone());
+            }
+        }
+
+        let key = match source {
+            KeySource::Keyring => ConfigKey {
+                source,
+                salt: None,
+                key: self.system.then(keyring_lookup).flatten().ok_or_else(|| {
+                    UnsealError::KeyUnavailable(
+                        "Config secrets are encrypted with the system keyring, which is locked or unavailable".to_string(),
+                    )
+                })?,
+            },
+            KeySource::Passphrase => {
+                let passphrase = self.passphrase().ok_or_else(|| {
+                    UnsealError::KeyUnavailable(format!(
+                        "Config secrets are encrypted with a passphrase, set {} to unlock them",
+                        PASSPHRASE_ENV
+                    ))
+                })?;
+                let salt = salt.ok_or_else(|| UnsealError::Invalid("Sealed config secrets have no salt".to_string()))?;
+                passphrase_key(&passphrase, Some(salt.to_string())).map_err(UnsealError::Invalid)?
+            }
+        };
+        *cached = Some(Some(key.clone()));
+        Ok(key)
+    }
+
+    /// Whether secrets can be encrypted on this machine
+    pub fn is_available(&self) -> bool {
+        self.sealing_key().is_some()
+    }
+
+    /// Replaces the secrets in a serialized config with an encrypted copy. Leaves
+    /// them in plaintext (with a warning) when no key is available.
+    pub fn seal(&self, config: &mut Value) -> Result<(), String> {
+        match self.sealing_key() {
+            Some(key) => seal_with(config, &key),
+            None => Ok(()),
+        }
+    }
+
+    /// Decrypts sealed secrets back into their fields. Plaintext configs pass through.
+    pub fn unseal(&self, config: &mut Value) -> Result<(), UnsealError> {
+        let Some(sealed) = config.as_object_mut().and_then(|object| object.remove(SEALED_FIELD)) else {
+            return Ok(());
+        };
+        let sealed: SealedSecrets = serde_json::from_value(sealed)
+            .map_err(|e| UnsealError::Invalid(format!("Invalid sealed secrets: {}", e)))?;
+        let key = self.unsealing_key(sealed.source, sealed.salt.as_deref())?;
+        unseal_with(config, &sealed, &key)
+    }
+}
+
+/// JSON pointers of the secrets in a config
+fn secret_pointers(config: &Value) -> Vec<String> {
+    let mut pointers = vec![
+        "/master_token".to_string(),
+        "/secret_key".to_string(),
+        "/tls_identity/key_pem".to_string(),
+    ];
+    if let Some(devices) = config.get("authorized_devices").and_then(Value::as_object) {
+        for device_id in devices.keys() {
+            let escaped = device_id.replace('~', "~0").replace('/', "~1");
+            pointers.push(format!("/authorized_devices/{}/secret_key", escaped));
+        }
+    }
+    pointers
+}
+
+/// Splits a pointer into its parent and the unescaped last key
+fn split_pointer(pointer: &str) -> Option<(&str, String)> {
+    let (parent, key) = pointer.rsplit_once('/')?;
+    Some((parent, key.replace("~1", "/").replace("~0", "~")))
+}
+
+/// Whether the config holds secrets in plaintext
+pub fn has_plaintext_secrets(config: &Value) -> bool {
+    secret_pointers(config)
+        .iter()
+        .any(|pointer| config.pointer(pointer).is_some_and(Value::is_string))
+}
+
+fn seal_with(config: &mut Value, key: &ConfigKey) -> Result<(), String> {
+    let mut secrets = Map::new();
+    for pointer in secret_pointers(config) {
+        let Some((parent, field)) = split_pointer(&pointer) else { continue };
+        let removed = config
+            .pointer_mut(parent)
+            .and_then(Value::as_object_mut)
+            .and_then(|object| object.remove(&field));
+        if let Some(secret @ Value::String(_)) = removed {
+            secrets.insert(pointer, secret);
+        }
+    }
+    if secrets.is_empty() {
+        return Ok(());
+    }
+
+    let plaintext = serde_json::to_string(&secrets).map_err(|e| e.to_string())?;
+    let sealed = SealedSecrets {
+        source: key.source,
+        salt: key.salt.clone(),
+        data: security::encrypt_with_aad(&key.key, &plaintext, AAD)?,
+    };
+    config[SEALED_FIELD] = serde_json::to_value(sealed).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+fn unseal_with(config: &mut Value, sealed: &SealedSecrets, key: &ConfigKey) -> Result<(), UnsealError> {
+    // AES-GCM cannot tell a wrong key from damaged data; assume the key, since
+    // treating the file as corrupt would lose it
+    let plaintext = security::decrypt_with_aad(&key.key, &sealed.data, AAD).map_err(|_| {
+        UnsealError::KeyUnavailable("Failed to decrypt config secrets, wrong key or passphrase".to_string())
+    })?;
+    let secrets: Map<String, Value> = serde_json::from_str(&plaintext)
+        .map_err(|e| UnsealError::Invalid(format!("Invalid sealed secrets: {}", e)))?;
+
+    for (pointer, secret) in secrets {
+        let Some((parent, field)) = split_pointer(&pointer) else { continue };
+        // Secrets of devices removed by hand are dropped
+        if let Some(object) = config.pointer_mut(parent).and_then(Value::as_object_mut) {
+            object.insert(field, secret);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_seal_round_trip() {
+        let original = json!({
+            "master_token": "token",
+            "secret_key": null,
+            "authorized_devices": {
+                "a/b": { "device_id": "a/b", "secret_key": "device-key" }
+            },
+            "tls_identity": { "cert_pem": "cert", "key_pem": "private" },
+            "auto_start": true
+        });
+        let key = passphrase_key("correct horse", None).unwrap();
+
+        let mut config = original.clone();
+        seal_with(&mut config, &key).unwrap();
+        assert!(!has_plaintext_secrets(&config));
+        assert!(!config.to_string().contains("device-key"));
+        assert_eq!(config["tls_identity"]["cert_pem"], "cert");
+
+        let sealed: SealedSecrets = serde_json::from_value(config[SEALED_FIELD].clone()).unwrap();
+        let wrong = passphrase_key("wrong", sealed.salt.clone()).unwrap();
+        assert!(unseal_with(&mut config.clone(), &sealed, &wrong).is_err());
+
+        let same = passphrase_key("correct horse", sealed.salt.clone()).unwrap();
+        config.as_object_mut().unwrap().remove(SEALED_FIELD);
+        unseal_with(&mut config, &sealed, &same).unwrap();
+
+        let mut expected = original;
+        expected.as_object_mut().unwrap().remove("secret_key");
+        assert_eq!(config, expected);
+    }
+
+    fn sample() -> Value {
+        json!({
+            "master_token": "token",
+            "authorized_devices": { "phone": { "device_id": "phone", "secret_key": "device-key" } },
+            "auto_start": true
+        })
+    }
+
+    #[test]
+    fn test_passphrase_seal_unseal() {
+        let mut config = sample();
+        Keys::with_passphrase("correct horse").seal(&mut config).unwrap();
+        assert!(!has_plaintext_secrets(&config));
+        assert_eq!(config[SEALED_FIELD]["source"], "passphrase");
+
+        // A fresh key source derives the same key from the stored salt
+        Keys::with_passphrase("correct horse").unseal(&mut config).unwrap();
+        assert_eq!(config, sample());
+    }
+
+    #[test]
+    fn test_wrong_passphrase_is_key_unavailable() {
+        let mut config = sample();
+        Keys::with_passphrase("correct horse").seal(&mut config).unwrap();
+        let sealed = config.clone();
+
+        let error = Keys::with_passphrase("battery staple").unseal(&mut config).unwrap_err();
+        assert!(matches!(error, UnsealError::KeyUnavailable(_)), "{:?}", error);
+        assert_eq!(config.get("master_token"), sealed.get("master_token"));
+    }
+
+    #[test]
+    fn test_missing_key() {
+        let keys = Keys::none();
+        let mut config = sample();
+        keys.seal(&mut config).unwrap();
+        assert_eq!(config, sample(), "secrets stay in plaintext without a key");
+
+        Keys::with_passphrase("correct horse").seal(&mut config).unwrap();
+        let error = keys.unseal(&mut config).unwrap_err();
+        assert!(matches!(error, UnsealError::KeyUnavailable(_)), "{:?}", error);
+
+        let mut keyring_sealed = json!({ "sealed_secrets": { "source": "keyring", "data": "AAAA" } });
+        let error = keys.unseal(&mut keyring_sealed).unwrap_err();
+        assert!(matches!(error, UnsealError::KeyUnavailable(_)), "{:?}", error);
+
+        let mut malformed = json!({ "sealed_secrets": { "source": "passphrase" } });
+        assert!(matches!(keys.unseal(&mut malformed), Err(UnsealError::Invalid(_))));
+    }
+}
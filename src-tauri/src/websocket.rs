@@ -1,13 +1,166 @@
 use std::sync::{Arc, Mutex};
 use std::collections::HashMap;
+use std::future::Future;
+use std::io::ErrorKind;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
 use warp::Filter;
 use warp::ws::{Message, WebSocket};
 use futures_util::{StreamExt, SinkExt};
 use tokio::sync::mpsc;
-use serde_json;
-use crate::models::{BarcodeMessage, ScanMessage, PairRequest, ReconnectRequest, DeviceInfo};
+use crate::dedup::RecentScanIds;
+use crate::models::{BarcodeMessage, CommandAck, CommandAckEvent, CommandAckStatus, DeviceCommand, LockoutEvent, ScanMessage, ScanBatchMessage, PairRequest, ReconnectRequest, RefreshTokenRequest, HandshakeRequest, DeviceInfo};
+use crate::protocol::{self, ClientMessage, ErrorCode, ParseError, ReconnectStatus, ScanItemResult, ScanItemStatus, ServerMessage};
+use crate::rate_limit::{RateLimiter, Throttled};
 use crate::storage::{AppConfig, self};
-use crate::security::{self, AuthorizedDevice};
+use crate::security::{self, AuthorizedDevice, TokenError};
+use crate::session::{SecureSession, SessionError};
+
+/// Uncommon port to avoid conflicts with other local services
+pub const DEFAULT_PORT: u16 = 47592;
+
+/// Where the server listens, as read and written by the settings UI
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkSettings {
+    pub port: u16,
+    /// Interface to listen on; 0.0.0.0 listens on all of them
+    pub bind_address: IpAddr,
+    /// How many following ports to try when `port` is taken (0 = fail instead)
+    pub port_fallback_attempts: u16,
+    /// Serve wss://; phones paired over ws:// must scan a new QR code when it changes
+    pub tls_enabled: bool,
+}
+
+impl NetworkSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.port == 0 {
+            return Err("Port must be between 1 and 65535".to_string());
+        }
+        if self.port.checked_add(self.port_fallback_attempts).is_none() {
+            return Err("Port fallback range goes past port 65535".to_string());
+        }
+        Ok(())
+    }
+}
+
+type ServeFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Server bound to its address; serves connections when awaited with `run`
+pub struct BoundServer {
+    pub addr: SocketAddr,
+    serve: ServeFuture,
+}
+
+impl BoundServer {
+    pub async fn run(self) {
+        self.serve.await;
+        log::info!("WebSocket server stopped");
+    }
+}
+
+/// Saves the shared config to the server's config file (none for servers built in tests)
+fn save_config(path: Option<&Path>, config: &AppConfig) -> Result<(), String> {
+    match path {
+        Some(path) => storage::save_at(path, config),
+        None => Err("No config file to save to".to_string()),
+    }
+}
+
+/// Rotates the master token if its policy says so, saving the config and
+/// telling the app about the new token
+fn refresh_master_token(
+    config: &Arc<Mutex<AppConfig>>,
+    config_path: Option<&Path>,
+    event_sender: &mpsc::UnboundedSender<ServerEvent>,
+) {
+    let mut cfg = config.lock().unwrap();
+    let previous = cfg.master_token.clone();
+    if !cfg.ensure_master_token(chrono::Utc::now().timestamp()) {
+        return;
+    }
+    if let Err(e) = save_config(config_path, &cfg) {
+        log::error!("Failed to save config: {}", e);
+    }
+    if cfg.master_token != previous {
+        let _ = event_sender.send(ServerEvent::MasterTokenRotated);
+    }
+}
+
+/// Keeps time-based rotation going while nobody pairs
+async fn rotate_master_token_when_due(
+    config: Arc<Mutex<AppConfig>>,
+    config_path: Option<PathBuf>,
+    event_sender: mpsc::UnboundedSender<ServerEvent>,
+) {
+    let mut interval = tokio::time::interval(TOKEN_ROTATION_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        refresh_master_token(&config, config_path.as_deref(), &event_sender);
+    }
+}
+
+/// Forgets commands that were not finally acknowledged in time and returns
+/// the timeout acks to report for them
+fn expire_pending_commands(pending: &PendingCommands, now: i64) -> Vec<CommandAckEvent> {
+    let mut pending = pending.lock().unwrap();
+    let expired: Vec<String> = pending
+        .iter()
+        .filter(|(_, command)| now - command.sent_at >= COMMAND_TIMEOUT_SECS)
+        .map(|(command_id, _)| command_id.clone())
+        .collect();
+
+    expired
+        .into_iter()
+        .filter_map(|command_id| {
+            let command = pending.remove(&command_id)?;
+            log::warn!("Command {} to device {} timed out", command_id, command.device_id);
+            Some(CommandAckEvent {
+                command_id,
+                device_id: command.device_id,
+                status: CommandAckStatus::TimedOut,
+                message: None,
+                timestamp: now,
+            })
+        })
+        .collect()
+}
+
+async fn expire_commands_when_due(pending: PendingCommands, event_sender: mpsc::UnboundedSender<ServerEvent>) {
+    let mut interval = tokio::time::interval(COMMAND_TIMEOUT_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        for event in expire_pending_commands(&pending, chrono::Utc::now().timestamp()) {
+            if event_sender.send(ServerEvent::CommandAck(event)).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// True when a bind failed because the port is taken, looking through the
+/// warp and hyper errors wrapping the I/O error
+fn is_addr_in_use(error: &(dyn std::error::Error + 'static)) -> bool {
+    let mut source = Some(error);
+    while let Some(e) = source {
+        if e.downcast_ref::<std::io::Error>().is_some_and(|io| io.kind() == ErrorKind::AddrInUse) {
+            return true;
+        }
+        source = e.source();
+    }
+    false
+}
+
+fn ports_in_use_error(port: u16, fallback_attempts: u16) -> String {
+    if fallback_attempts == 0 {
+        format!("Port {} is already in use by another application", port)
+    } else {
+        format!("Ports {}-{} are all in use by other applications", port, port.saturating_add(fallback_attempts))
+    }
+}
 
 type Clients = Arc<Mutex<HashMap<usize, ClientInfo>>>;
 
@@ -17,27 +170,91 @@ pub struct ClientInfo {
     pub device_id: Option<String>,
     pub device_name: Option<String>,
     pub authenticated: bool,
+    /// Protocol version agreed in the handshake (legacy clients default to the minimum)
+    pub protocol_version: u32,
+    /// Encrypted envelope state, set after pair/reconnect when the client asks for it
+    pub session: Option<SecureSession>,
+    /// Peer address, used to throttle authentication attempts
+    pub remote_ip: Option<IpAddr>,
+    /// When the auth token this connection authenticated with was issued; the
+    /// connection stops accepting scans once that token expires
+    pub token_issued_at: Option<i64>,
 }
 
 #[derive(Clone)]
 pub struct WebSocketServer {
-    pub token: String,
-    pub port: u16,
     clients: Clients,
     next_client_id: Arc<Mutex<usize>>,
     shutdown_tx: Arc<Mutex<Option<mpsc::UnboundedSender<()>>>>,
     config: Arc<Mutex<AppConfig>>,
+    /// Where handlers save config changes (pairings, key migrations, token rotation)
+    config_path: Option<PathBuf>,
+    /// Shared by all connections, as retries usually arrive on a new connection
+    recent_scans: Arc<Mutex<RecentScanIds>>,
+    rate_limiter: Arc<Mutex<RateLimiter>>,
+    pending_commands: PendingCommands,
+}
+
+/// Notifications for the desktop app besides scanned barcodes
+#[derive(Debug, Clone)]
+pub enum ServerEvent {
+    /// A phone acknowledged a command sent with `send_command`
+    CommandAck(CommandAckEvent),
+    /// An IP or device id was temporarily banned after repeated failures
+    Lockout(LockoutEvent),
+    /// The master token in the config was replaced, so the pairing QR code changed
+    MasterTokenRotated,
+}
+
+/// How often a running server checks whether the master token is due for rotation
+const TOKEN_ROTATION_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// How long a phone has to finally acknowledge a command before it is reported as timed out
+const COMMAND_TIMEOUT_SECS: i64 = 30;
+const COMMAND_TIMEOUT_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A command sent with `send_command` that has no final ack yet
+struct PendingCommand {
+    device_id: String,
+    sent_at: i64,
+}
+
+/// Pending commands by command id
+type PendingCommands = Arc<Mutex<HashMap<String, PendingCommand>>>;
+
+/// State shared by every connection's message handlers
+#[derive(Clone)]
+struct ServerContext {
+    clients: Clients,
+    config: Arc<Mutex<AppConfig>>,
+    config_path: Option<PathBuf>,
+    barcode_sender: mpsc::UnboundedSender<BarcodeMessage>,
+    event_sender: mpsc::UnboundedSender<ServerEvent>,
+    recent_scans: Arc<Mutex<RecentScanIds>>,
+    rate_limiter: Arc<Mutex<RateLimiter>>,
+    pending_commands: PendingCommands,
+    /// Served over wss://; device secrets are only handed out on encrypted connections
+    tls: bool,
 }
 
 impl WebSocketServer {
-    pub fn new(token: String, port: u16, config: AppConfig) -> Self {
+    /// The config is shared with the caller so revocations apply to live connections
+    pub fn new(config: Arc<Mutex<AppConfig>>) -> Self {
+        let config_path = storage::get_config_path()
+            .map_err(|e| log::warn!("Config changes made by the server cannot be saved: {}", e))
+            .ok();
+        Self::with_storage(config, config_path, RecentScanIds::persistent())
+    }
+
+    fn with_storage(config: Arc<Mutex<AppConfig>>, config_path: Option<PathBuf>, recent_scans: RecentScanIds) -> Self {
         Self {
-            token,
-            port,
             clients: Arc::new(Mutex::new(HashMap::new())),
             next_client_id: Arc::new(Mutex::new(0)),
             shutdown_tx: Arc::new(Mutex::new(None)),
-            config: Arc::new(Mutex::new(config)),
+            config,
+            config_path,
+            recent_scans: Arc::new(Mutex::new(recent_scans)),
+            rate_limiter: Arc::new(Mutex::new(RateLimiter::new())),
+            pending_commands: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
@@ -49,6 +266,24 @@ impl WebSocketServer {
         self.clients.lock().unwrap().clear();
     }
 
+    /// Closes every connection of a device, e.g. after it was revoked
+    pub fn disconnect_device(&self, device_id: &str) {
+        let mut clients = self.clients.lock().unwrap();
+        let client_ids: Vec<usize> = clients
+            .iter()
+            .filter(|(_, c)| c.device_id.as_deref() == Some(device_id))
+            .map(|(id, _)| *id)
+            .collect();
+        for client_id in &client_ids {
+            if let Some(client) = clients.remove(client_id) {
+                close_client(&client, "Device disconnected");
+            }
+        }
+        if !client_ids.is_empty() {
+            log::info!("Disconnected device {}", device_id);
+        }
+    }
+
     pub fn get_connected_count(&self) -> usize {
         // Count only authenticated clients with unique device_ids
         let clients = self.clients.lock().unwrap();
@@ -66,7 +301,7 @@ impl WebSocketServer {
     }
 
     pub fn get_connected_devices(&self) -> Vec<DeviceInfo> {
-        self.clients
+        let mut devices: Vec<DeviceInfo> = self.clients
             .lock()
             .unwrap()
             .values()
@@ -78,33 +313,93 @@ impl WebSocketServer {
                 paired_at: None,
                 last_seen: None,
                 is_connected: true,
+                legacy_key: false,
             })
-            .collect()
+            .collect();
+
+        // Handlers lock the config before the clients, so only take it afterwards
+        let config = self.config.lock().unwrap();
+        for device in &mut devices {
+            device.legacy_key = config.uses_legacy_key(&device.device_id);
+        }
+        devices
     }
 
-    pub async fn start(
-        self,
-        barcode_sender: mpsc::UnboundedSender<BarcodeMessage>,
-    ) -> Result<(), String> {
-        let (shutdown_tx, mut shutdown_rx) = mpsc::unbounded_channel();
-        *self.shutdown_tx.lock().unwrap() = Some(shutdown_tx);
+    /// Pushes a command to a connected device and returns its command id.
+    /// The phone's acknowledgements arrive later as `ServerEvent::CommandAck`,
+    /// or a `TimedOut` one if it sends no final ack within `COMMAND_TIMEOUT_SECS`.
+    pub fn send_command(&self, device_id: &str, command: DeviceCommand) -> Result<String, String> {
+        // Use the newest connection if the device briefly has more than one
+        let target = self.clients
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, c)| c.authenticated && c.device_id.as_deref() == Some(device_id))
+            .max_by_key(|(id, _)| **id)
+            .map(|(id, c)| (*id, c.protocol_version));
+
+        let (client_id, protocol_version) = target
+            .ok_or_else(|| format!("Device {} is not connected", device_id))?;
+
+        if protocol_version < protocol::COMMANDS_MIN_PROTOCOL_VERSION {
+            return Err(format!("Device {} uses an app version that does not support commands", device_id));
+        }
 
-        let clients = self.clients.clone();
-        let token = self.token.clone();
+        let command_id = uuid::Uuid::new_v4().to_string();
+        log::info!("Sending command {} to device {}: {:?}", command_id, device_id, command);
+
+        let now = chrono::Utc::now().timestamp();
+        self.pending_commands.lock().unwrap().insert(
+            command_id.clone(),
+            PendingCommand { device_id: device_id.to_string(), sent_at: now },
+        );
+        let message = ServerMessage::Command {
+            command_id: command_id.clone(),
+            command,
+            timestamp: now,
+        };
+        send_to_client(&self.clients, client_id, &message);
+
+        Ok(command_id)
+    }
+
+    /// Binds to the configured address and port (or the next free one in the
+    /// fallback range). Must be called inside the Tokio runtime.
+    pub fn bind(
+        &self,
+        barcode_sender: mpsc::UnboundedSender<BarcodeMessage>,
+        event_sender: mpsc::UnboundedSender<ServerEvent>,
+    ) -> Result<BoundServer, String> {
+        let (network, tls_identity) = {
+            let config = self.config.lock().unwrap();
+            (config.network_settings(), config.active_tls_identity().cloned())
+        };
+        network.validate()?;
+
+        let rotation_check = rotate_master_token_when_due(self.config.clone(), self.config_path.clone(), event_sender.clone());
+        let command_timeouts = expire_commands_when_due(self.pending_commands.clone(), event_sender.clone());
+        let context = ServerContext {
+            clients: self.clients.clone(),
+            config: self.config.clone(),
+            config_path: self.config_path.clone(),
+            barcode_sender,
+            event_sender,
+            recent_scans: self.recent_scans.clone(),
+            rate_limiter: self.rate_limiter.clone(),
+            pending_commands: self.pending_commands.clone(),
+            tls: tls_identity.is_some(),
+        };
         let next_client_id = self.next_client_id.clone();
-        let config = self.config.clone();
 
         // Accept WebSocket connections on root path
         let ws_route = warp::ws()
-            .map(move |ws: warp::ws::Ws| {
-                let clients = clients.clone();
-                let token = token.clone();
-                let barcode_sender = barcode_sender.clone();
+            .and(warp::addr::remote())
+            .map(move |ws: warp::ws::Ws, remote: Option<SocketAddr>| {
+                let context = context.clone();
                 let next_client_id = next_client_id.clone();
-                let config = config.clone();
 
                 ws.on_upgrade(move |socket| {
-                    handle_connection(socket, clients, token, barcode_sender, next_client_id, config)
+                    handle_connection(socket, context, next_client_id, remote.map(|addr| addr.ip()))
                 })
             });
 
@@ -115,34 +410,87 @@ impl WebSocketServer {
 
         let routes = ws_route.with(cors);
 
-        log::info!("WebSocket server starting on port {}", self.port);
-
-        log::info!("WebSocket server starting on port {}", self.port);
-
-        let (_, server) = warp::serve(routes)
-            .bind_with_graceful_shutdown(([0, 0, 0, 0], self.port), async move {
+        // Bind each port of the fallback range in turn and keep the listener that
+        // succeeded, so no other process can take the port in between
+        let last = network.port.saturating_add(network.port_fallback_attempts);
+        let mut bound = None;
+        for candidate in network.port..=last {
+            let addr = SocketAddr::new(network.bind_address, candidate);
+            let (shutdown_tx, mut shutdown_rx) = mpsc::unbounded_channel();
+            let shutdown_signal = async move {
                 shutdown_rx.recv().await;
                 log::info!("WebSocket server received shutdown signal");
-            });
+            };
+
+            let result: Result<(SocketAddr, ServeFuture), warp::Error> = match &tls_identity {
+                Some(identity) => warp::serve(routes.clone())
+                    .tls()
+                    .cert(identity.cert_pem.clone())
+                    .key(identity.key_pem.clone())
+                    .try_bind_with_graceful_shutdown(addr, shutdown_signal)
+                    .map(|(addr, server)| (addr, Box::pin(server) as ServeFuture)),
+                None => warp::serve(routes.clone())
+                    .try_bind_with_graceful_shutdown(addr, shutdown_signal)
+                    .map(|(addr, server)| (addr, Box::pin(server) as ServeFuture)),
+            };
+
+            match result {
+                Ok((addr, serve)) => {
+                    bound = Some((addr, serve, shutdown_tx));
+                    break;
+                }
+                Err(e) if is_addr_in_use(&e) => log::debug!("Port {} is in use", candidate),
+                Err(e) => return Err(format!("Cannot listen on {}: {}", addr, e)),
+            }
+        }
+        let Some((addr, serve, shutdown_tx)) = bound else {
+            return Err(ports_in_use_error(network.port, network.port_fallback_attempts));
+        };
 
-        server.await;
+        if addr.port() != network.port {
+            log::warn!("Port {} is in use, falling back to port {}", network.port, addr.port());
+        }
+        match &tls_identity {
+            Some(identity) => log::info!("WebSocket server starting on {} (wss, fingerprint {})", addr, identity.fingerprint),
+            None => log::warn!("WebSocket server starting on {} without TLS (plain ws)", addr),
+        }
 
-        log::info!("WebSocket server stopped");
-        Ok(())
+        // The periodic checks end together with the server
+        let serve = Box::pin(async move {
+            tokio::select! {
+                _ = serve => {}
+                _ = rotation_check => {}
+                _ = command_timeouts => {}
+            }
+        });
+
+        *self.shutdown_tx.lock().unwrap() = Some(shutdown_tx);
+        Ok(BoundServer { addr, serve })
     }
 }
 
 async fn handle_connection(
     ws: WebSocket,
-    clients: Clients,
-    master_token: String,
-    barcode_sender: mpsc::UnboundedSender<BarcodeMessage>,
+    context: ServerContext,
     next_client_id: Arc<Mutex<usize>>,
-    config: Arc<Mutex<AppConfig>>,
+    remote_ip: Option<IpAddr>,
 ) {
+    let clients = context.clients.clone();
     let (mut ws_tx, mut ws_rx) = ws.split();
     let (tx, mut rx) = mpsc::unbounded_channel();
 
+    // Turn banned addresses away before they can send anything
+    let ban_remaining = remote_ip.and_then(|ip| context.rate_limiter.lock().unwrap().ban_remaining(ip));
+    if let Some(retry_after_secs) = ban_remaining {
+        log::warn!("Rejected connection from banned address {:?}", remote_ip);
+        let error = ServerMessage::rate_limited("Too many failed attempts. Try again later.", retry_after_secs);
+        if let Ok(text) = serde_json::to_string(&error) {
+            let _ = ws_tx.send(Message::text(text)).await;
+        }
+        let _ = ws_tx.close().await;
+        return;
+    }
+
     // Generate client ID
     let client_id = {
         let mut id = next_client_id.lock().unwrap();
@@ -158,18 +506,25 @@ async fn handle_connection(
             device_id: None,
             device_name: None,
             authenticated: false,
+            protocol_version: protocol::PROTOCOL_VERSION_MIN,
+            session: None,
+            remote_ip,
+            token_issued_at: None,
         };
         clients.lock().unwrap().insert(client_id, client_info);
     }
-    log::info!("Client {} connected", client_id);
+    log::info!("Client {} connected from {:?}", client_id, remote_ip);
 
-    // Spawn task to send messages to this client
+    // Spawn task to send messages to this client. It ends after a Close frame or
+    // once the client was dropped from the map, closing the socket.
     tokio::spawn(async move {
         while let Some(message) = rx.recv().await {
-            if ws_tx.send(message).await.is_err() {
+            let closing = message.is_close();
+            if ws_tx.send(message).await.is_err() || closing {
                 break;
             }
         }
+        let _ = ws_tx.close().await;
     });
 
     // Handle incoming messages
@@ -178,79 +533,36 @@ async fn handle_connection(
     while let Some(result) = ws_rx.next().await {
         match result {
             Ok(msg) => {
+                // Disconnected from the desktop side; stop reading before the peer answers the Close
+                if !clients_for_send.lock().unwrap().contains_key(&client_id) {
+                    break;
+                }
+
                 if let Ok(text) = msg.to_str() {
                     log::info!("Received message from client {}: {}", client_id, text);
 
-                    // Try to parse as JSON to check message type
-                    if let Ok(json) = serde_json::from_str::<serde_json::Value>(text) {
-                        if let Some(action) = json.get("action").and_then(|v| v.as_str()) {
-                            match action {
-                                // Handle handshake (simple connection check)
-                                "handshake" => {
-                                    log::info!("Client {} sent handshake", client_id);
-                                    let response = serde_json::json!({
-                                        "action": "handshake_ack",
-                                        "status": "connected",
-                                        "clientId": client_id,
-                                        "timestamp": chrono::Utc::now().timestamp()
-                                    });
-                                    send_to_client(&clients_for_send, client_id, &response);
-                                    continue;
-                                }
-
-                                // Handle pairing request (first-time connection via QR code)
-                                "pair" => {
-                                    if let Ok(pair_request) = serde_json::from_str::<PairRequest>(text) {
-                                        handle_pair_request(
-                                            &clients_for_send,
-                                            client_id,
-                                            &pair_request,
-                                            &master_token,
-                                            &config,
-                                        );
-                                    } else {
-                                        send_error(&clients_for_send, client_id, "Invalid pair request format");
-                                    }
-                                    continue;
-                                }
-
-                                // Handle reconnection (returning device with auth token)
-                                "reconnect" => {
-                                    if let Ok(reconnect_request) = serde_json::from_str::<ReconnectRequest>(text) {
-                                        handle_reconnect_request(
-                                            &clients_for_send,
-                                            client_id,
-                                            &reconnect_request,
-                                            &config,
-                                        );
-                                    } else {
-                                        send_error(&clients_for_send, client_id, "Invalid reconnect request format");
-                                    }
-                                    continue;
-                                }
-
-                                // Handle scan (barcode received)
-                                "scan" => {
-                                    if let Ok(scan_msg) = serde_json::from_str::<ScanMessage>(text) {
-                                        handle_scan_message(
-                                            &clients_for_send,
-                                            client_id,
-                                            &scan_msg,
-                                            &master_token,
-                                            &config,
-                                            &barcode_sender,
-                                        );
-                                    } else {
-                                        send_error(&clients_for_send, client_id, "Invalid scan message format");
-                                    }
-                                    continue;
-                                }
-
-                                _ => {
-                                    log::warn!("Unknown action '{}' from client {}", action, client_id);
-                                }
+                    // Connections that have not authenticated share a per-IP message budget
+                    if !check_unauthenticated_message(&context, client_id) {
+                        break;
+                    }
+
+                    let Some(message) = parse_client_message(&clients_for_send, client_id, text) else {
+                        continue;
+                    };
+
+                    let keep_open = match message {
+                        // Unwrap encrypted envelopes and handle the inner message
+                        ClientMessage::Encrypted(frame) => {
+                            match open_encrypted_frame(&clients_for_send, client_id, frame.seq, &frame.payload) {
+                                Some(inner) => handle_client_message(inner, true, &context, client_id),
+                                None => true,
                             }
                         }
+                        message => handle_client_message(message, false, &context, client_id),
+                    };
+
+                    if !keep_open {
+                        break;
                     }
                 }
             }
@@ -267,18 +579,356 @@ async fn handle_connection(
     log::info!("Client {} disconnected (authenticated: {})", client_id, was_authenticated);
 }
 
-fn send_to_client(clients: &Clients, client_id: usize, message: &serde_json::Value) {
-    if let Some(client) = clients.lock().unwrap().get(&client_id) {
-        let _ = client.sender.send(Message::text(message.to_string()));
+/// Parses a frame, answering the client with a structured error when it is invalid
+fn parse_client_message(clients: &Clients, client_id: usize, text: &str) -> Option<ClientMessage> {
+    match ClientMessage::parse(text) {
+        Ok(message) => Some(message),
+        Err(ParseError::InvalidMessage(reason)) => {
+            log::warn!("Invalid message from client {}: {}", client_id, reason);
+            send_error(clients, client_id, ErrorCode::InvalidMessage, &reason);
+            None
+        }
+        Err(ParseError::InvalidAction { action, reason }) => {
+            log::warn!("Invalid '{}' message from client {}: {}", action, client_id, reason);
+            send_error(clients, client_id, ErrorCode::InvalidMessage, &format!("Invalid {} message format", action));
+            None
+        }
+        Err(ParseError::UnknownAction(action)) => {
+            log::warn!("Unknown action '{}' from client {}", action, client_id);
+            send_error(clients, client_id, ErrorCode::UnknownAction, &format!("Unknown action '{}'", action));
+            None
+        }
     }
 }
 
-fn send_error(clients: &Clients, client_id: usize, message: &str) {
-    let error = serde_json::json!({
-        "action": "error",
-        "message": message
-    });
-    send_to_client(clients, client_id, &error);
+/// Decrypts an envelope with the connection's session and parses the inner message
+fn open_encrypted_frame(clients: &Clients, client_id: usize, seq: u64, payload: &str) -> Option<ClientMessage> {
+    let opened = {
+        let mut clients_guard = clients.lock().unwrap();
+        clients_guard
+            .get_mut(&client_id)
+            .and_then(|c| c.session.as_mut())
+            .map(|session| session.open(seq, payload))
+    };
+
+    let text = match opened {
+        Some(Ok(text)) => text,
+        Some(Err(SessionError::Replay { seq, last_seq })) => {
+            log::warn!("Client {} replayed frame {} (last accepted {})", client_id, seq, last_seq);
+            send_error(clients, client_id, ErrorCode::ReplayDetected, "Sequence number already used");
+            return None;
+        }
+        Some(Err(SessionError::Decrypt(e))) => {
+            log::warn!("Client {} sent undecryptable frame {}: {}", client_id, seq, e);
+            send_error(clients, client_id, ErrorCode::InvalidEnvelope, "Failed to decrypt message");
+            return None;
+        }
+        None => {
+            log::warn!("Client {} sent encrypted frame without a session", client_id);
+            send_error(clients, client_id, ErrorCode::InvalidEnvelope, "No encrypted session established");
+            return None;
+        }
+    };
+
+    match parse_client_message(clients, client_id, &text)? {
+        ClientMessage::Encrypted(_) => {
+            send_error(clients, client_id, ErrorCode::InvalidEnvelope, "Nested encrypted frames are not allowed");
+            None
+        }
+        message => Some(message),
+    }
+}
+
+/// Dispatches one message. Returns false when the connection must be closed.
+fn handle_client_message(
+    message: ClientMessage,
+    encrypted: bool,
+    context: &ServerContext,
+    client_id: usize,
+) -> bool {
+    let clients = &context.clients;
+
+    match message {
+        // Handle handshake (connection check and version negotiation)
+        ClientMessage::Handshake(request) => {
+            return handle_handshake(clients, client_id, &request);
+        }
+
+        // Handle pairing request (first-time connection via QR code)
+        ClientMessage::Pair(pair_request) => {
+            handle_pair_request(context, client_id, &pair_request);
+        }
+
+        // Handle reconnection (returning device with auth token)
+        ClientMessage::Reconnect(reconnect_request) => {
+            handle_reconnect_request(context, client_id, &reconnect_request);
+        }
+
+        // Handle scan (barcode received)
+        ClientMessage::Scan(scan_msg) => {
+            if check_encryption_required(context, client_id, encrypted) {
+                handle_scan_message(context, client_id, &scan_msg);
+            }
+        }
+
+        // Handle scan batch (scans queued while the phone was offline)
+        ClientMessage::ScanBatch(batch) => {
+            if check_encryption_required(context, client_id, encrypted) {
+                handle_scan_batch_message(context, client_id, &batch);
+            }
+        }
+
+        // Handle token refresh (authenticated device renewing before expiry)
+        ClientMessage::RefreshToken(refresh_request) => {
+            handle_refresh_token_request(context, client_id, &refresh_request);
+        }
+
+        // Handle command acknowledgement (reply to a desktop-initiated command)
+        ClientMessage::CommandAck(ack) => {
+            handle_command_ack(context, client_id, ack);
+        }
+
+        // Only valid at the top level, unwrapped by the connection loop
+        ClientMessage::Encrypted(_) => {
+            send_error(clients, client_id, ErrorCode::InvalidEnvelope, "Nested encrypted frames are not allowed");
+        }
+
+        // Filtered out by ClientMessage::parse
+        ClientMessage::Unknown => {}
+    }
+
+    true
+}
+
+fn client_ip(clients: &Clients, client_id: usize) -> Option<IpAddr> {
+    clients.lock().unwrap().get(&client_id).and_then(|c| c.remote_ip)
+}
+
+fn send_throttled(clients: &Clients, client_id: usize, throttled: &Throttled) {
+    let message = if throttled.banned {
+        "Too many failed attempts. Try again later."
+    } else {
+        "Too many attempts. Slow down."
+    };
+    send_to_client(clients, client_id, &ServerMessage::rate_limited(message, throttled.retry_after_secs));
+}
+
+/// Returns false (after answering the client) when an unauthenticated connection sends too much
+fn check_unauthenticated_message(context: &ServerContext, client_id: usize) -> bool {
+    let (authenticated, remote_ip) = match context.clients.lock().unwrap().get(&client_id) {
+        Some(client) => (client.authenticated, client.remote_ip),
+        None => return false,
+    };
+    let Some(ip) = remote_ip.filter(|_| !authenticated) else {
+        return true;
+    };
+
+    let result = context.rate_limiter.lock().unwrap().check_message(ip);
+    match result {
+        Ok(()) => true,
+        Err(throttled) => {
+            log::warn!("Closing client {}: too many unauthenticated messages from {}", client_id, ip);
+            send_throttled(&context.clients, client_id, &throttled);
+            false
+        }
+    }
+}
+
+/// Counts a pair/reconnect/scan authentication attempt against the client's address.
+/// Returns false (after answering the client) while the address is throttled.
+fn check_auth_attempt(context: &ServerContext, client_id: usize, device_id: &str) -> bool {
+    let Some(ip) = client_ip(&context.clients, client_id) else {
+        return true;
+    };
+    let result = context.rate_limiter.lock().unwrap().check_attempt(ip);
+    match result {
+        Ok(()) => true,
+        Err(throttled) => {
+            log::warn!("Throttled authentication attempt from client {} ({}) for device {}", client_id, ip, device_id);
+            send_throttled(&context.clients, client_id, &throttled);
+            false
+        }
+    }
+}
+
+/// Counts a failed authentication attempt against the client's address and
+/// reports any resulting ban to the app. The claimed device id is only logged.
+fn record_auth_failure(context: &ServerContext, client_id: usize, device_id: &str) {
+    let Some(ip) = client_ip(&context.clients, client_id) else {
+        return;
+    };
+    let lockout = context.rate_limiter.lock().unwrap().record_failure(ip);
+
+    if let Some(lockout) = lockout {
+        log::warn!(
+            "Locked out {} until {} after {} failed attempts (last for device {})",
+            lockout.ip,
+            lockout.locked_until,
+            lockout.failures,
+            device_id
+        );
+        if let Err(e) = context.event_sender.send(ServerEvent::Lockout(lockout)) {
+            log::error!("Failed to forward lockout: {}", e);
+        }
+    }
+}
+
+fn handle_command_ack(context: &ServerContext, client_id: usize, ack: CommandAck) {
+    let device_id = context.clients
+        .lock()
+        .unwrap()
+        .get(&client_id)
+        .filter(|c| c.authenticated)
+        .and_then(|c| c.device_id.clone());
+
+    let Some(device_id) = device_id else {
+        log::warn!("Client {} acknowledged a command without being authenticated", client_id);
+        send_error(&context.clients, client_id, ErrorCode::NotAuthenticated, "Authenticate before acknowledging commands");
+        return;
+    };
+
+    // Only the device a command went to may answer it, and only until it timed out
+    {
+        let mut pending = context.pending_commands.lock().unwrap();
+        match pending.get(&ack.command_id) {
+            Some(command) if command.device_id == device_id => {
+                if ack.status != CommandAckStatus::Delivered {
+                    pending.remove(&ack.command_id);
+                }
+            }
+            _ => {
+                drop(pending);
+                log::warn!("Device {} acknowledged unknown command {}", device_id, ack.command_id);
+                send_error(&context.clients, client_id, ErrorCode::InvalidMessage, "Unknown or expired command id");
+                return;
+            }
+        }
+    }
+
+    log::info!("Device {} acknowledged command {}: {:?}", device_id, ack.command_id, ack.status);
+
+    let event = CommandAckEvent {
+        command_id: ack.command_id,
+        device_id,
+        status: ack.status,
+        message: ack.message,
+        timestamp: chrono::Utc::now().timestamp(),
+    };
+    if let Err(e) = context.event_sender.send(ServerEvent::CommandAck(event)) {
+        log::error!("Failed to forward command ack: {}", e);
+    }
+}
+
+/// Returns false (after answering the client) when scans must arrive encrypted but did not
+fn check_encryption_required(context: &ServerContext, client_id: usize, encrypted: bool) -> bool {
+    if encrypted {
+        return true;
+    }
+
+    // Once a session is active, plaintext scans could be injected by anyone on the path
+    let has_session = context.clients.lock().unwrap().get(&client_id).is_some_and(|c| c.session.is_some());
+    let require_encryption = context.config.lock().unwrap().require_encryption || has_session;
+    if require_encryption {
+        log::warn!("Client {} sent a plaintext scan while encryption is required", client_id);
+        send_error(&context.clients, client_id, ErrorCode::EncryptionRequired, "Scans must be sent in an encrypted session");
+        return false;
+    }
+
+    true
+}
+
+/// Sends a message, wrapping it in an encrypted envelope once the connection has a session
+fn send_to_client(clients: &Clients, client_id: usize, message: &ServerMessage) {
+    let text = match serde_json::to_string(message) {
+        Ok(text) => text,
+        Err(e) => {
+            log::error!("Failed to serialize message for client {}: {}", client_id, e);
+            return;
+        }
+    };
+
+    let mut clients_guard = clients.lock().unwrap();
+    let Some(client) = clients_guard.get_mut(&client_id) else {
+        return;
+    };
+
+    let text = match client.session.as_mut() {
+        Some(session) => {
+            let envelope = session.seal(&text)
+                .map(|(seq, payload)| ServerMessage::Encrypted { seq, payload })
+                .and_then(|envelope| serde_json::to_string(&envelope).map_err(|e| e.to_string()));
+            match envelope {
+                Ok(envelope) => envelope,
+                Err(e) => {
+                    log::error!("Failed to encrypt message for client {}: {}", client_id, e);
+                    return;
+                }
+            }
+        }
+        None => text,
+    };
+
+    let _ = client.sender.send(Message::text(text));
+}
+
+/// Starts an encrypted session if the client sent a nonce. The ack carrying the
+/// returned server nonce must be sent before calling `activate_session`.
+fn prepare_session(
+    client_id: usize,
+    device_key: &str,
+    client_nonce: Option<&str>,
+) -> Option<(SecureSession, String)> {
+    let client_nonce = client_nonce?;
+    match SecureSession::establish(device_key, client_nonce) {
+        Ok(prepared) => Some(prepared),
+        Err(e) => {
+            // The ack goes out without a session nonce, so the client stays in plaintext
+            log::warn!("Client {} requested an encrypted session but it failed: {}", client_id, e);
+            None
+        }
+    }
+}
+
+fn activate_session(clients: &Clients, client_id: usize, session: Option<SecureSession>) {
+    if let Some(session) = session {
+        if let Some(client) = clients.lock().unwrap().get_mut(&client_id) {
+            client.session = Some(session);
+            log::info!("Encrypted session established for client {}", client_id);
+        }
+    }
+}
+
+fn send_error(clients: &Clients, client_id: usize, code: ErrorCode, message: &str) {
+    send_to_client(clients, client_id, &ServerMessage::error(code, message));
+}
+
+/// Negotiates the protocol version. Returns false when the client must be disconnected.
+fn handle_handshake(clients: &Clients, client_id: usize, request: &HandshakeRequest) -> bool {
+    log::info!("Client {} sent handshake (protocol {:?})", client_id, request.protocol_version);
+
+    let version = match protocol::negotiate_version(request) {
+        Ok(version) => version,
+        Err(reason) => {
+            log::warn!("Rejecting client {}: {}", client_id, reason);
+            send_to_client(clients, client_id, &ServerMessage::unsupported_version(reason));
+            return false;
+        }
+    };
+
+    if let Some(client) = clients.lock().unwrap().get_mut(&client_id) {
+        client.protocol_version = version;
+    }
+
+    let response = ServerMessage::HandshakeAck {
+        status: "connected",
+        client_id,
+        protocol_version: version,
+        min_protocol_version: protocol::PROTOCOL_VERSION_MIN,
+        max_protocol_version: protocol::PROTOCOL_VERSION_MAX,
+        timestamp: chrono::Utc::now().timestamp(),
+    };
+    send_to_client(clients, client_id, &response);
+    true
 }
 
 /// Remove any existing connection from the same device to avoid duplicates
@@ -295,59 +945,77 @@ fn remove_previous_device_connection(clients: &Clients, device_id: &str, current
 
     for old_id in old_client_ids {
         log::info!("Removing old connection {} for device {}", old_id, device_id);
-        clients_guard.remove(&old_id);
+        if let Some(client) = clients_guard.remove(&old_id) {
+            close_client(&client, "Replaced by a newer connection");
+        }
     }
 }
 
+/// Asks the connection's writer to send a Close frame; it shuts the socket down after it
+fn close_client(client: &ClientInfo, reason: &'static str) {
+    let _ = client.sender.send(Message::close_with(1000u16, reason));
+}
+
 fn handle_pair_request(
-    clients: &Clients,
+    context: &ServerContext,
     client_id: usize,
     request: &PairRequest,
-    master_token: &str,
-    config: &Arc<Mutex<AppConfig>>,
 ) {
+    let clients = &context.clients;
+    let config = &context.config;
+
     log::info!("Pair request from device {} ({})", request.device_id, request.device_name);
+
+    if !check_auth_attempt(context, client_id, &request.device_id) {
+        return;
+    }
+
+    // An expired master token is replaced first, so its QR code no longer pairs
+    refresh_master_token(config, context.config_path.as_deref(), &context.event_sender);
+    let master_token = config.lock().unwrap().master_token.clone().unwrap_or_default();
+
     log::debug!("Pair request details: token_len={}, master_token_len={}, match={}", request.master_token.len(), master_token.len(), request.master_token == master_token);
 
     // Validate master token from QR code
-    if request.master_token != master_token {
+    if master_token.is_empty() || request.master_token != master_token {
         log::warn!("Invalid master token from device {}: token_mismatch", request.device_id);
-        send_error(clients, client_id, "Invalid pairing token");
+        record_auth_failure(context, client_id, &request.device_id);
+        send_error(clients, client_id, ErrorCode::InvalidPairingToken, "Invalid pairing token");
         return;
     }
 
-    // Get or create secret key
+    // Every pairing gets a fresh per-device key, invalidating older tokens of this device
     let mut cfg = config.lock().unwrap();
-    if cfg.secret_key.is_none() {
-        log::debug!("Generating new secret key for device {}", request.device_id);
-        cfg.secret_key = Some(security::generate_secret_key());
-    }
-    let secret_key = cfg.secret_key.clone().unwrap();
+    let device = AuthorizedDevice::new(
+        request.device_id.clone(),
+        request.device_name.clone(),
+        request.device_model.clone(),
+    );
+    let secret_key = device.secret_key.clone().unwrap_or_default();
 
     // Create auth token for this device
     log::debug!("Creating auth token for device {}", request.device_id);
     let auth_token = security::create_auth_token(&request.device_id, &secret_key);
+    let issued_at = chrono::Utc::now().timestamp();
+    let expires_at = security::auth_token_expires_at(issued_at, cfg.auth_token_lifetime_secs);
 
     // Add device to authorized list
-    let device = AuthorizedDevice {
-        device_id: request.device_id.clone(),
-        device_name: request.device_name.clone(),
-        device_model: request.device_model.clone(),
-        paired_at: chrono::Utc::now().to_rfc3339(),
-        last_seen: chrono::Utc::now().to_rfc3339(),
-    };
     log::debug!("Adding device to authorized devices list");
     cfg.add_device(device);
+    let token_rotated = cfg.record_pairing(chrono::Utc::now().timestamp());
 
     // Save config
     drop(cfg);
     if let Ok(cfg) = config.lock() {
-        if let Err(e) = storage::save(&cfg) {
+        if let Err(e) = save_config(context.config_path.as_deref(), &cfg) {
             log::error!("Failed to save config: {}", e);
         } else {
             log::debug!("Config saved successfully");
         }
     }
+    if token_rotated {
+        let _ = context.event_sender.send(ServerEvent::MasterTokenRotated);
+    }
 
     // Remove any old connection from this device
     remove_previous_device_connection(clients, &request.device_id, client_id);
@@ -357,67 +1025,141 @@ fn handle_pair_request(
         client.authenticated = true;
         client.device_id = Some(request.device_id.clone());
         client.device_name = Some(request.device_name.clone());
+        client.token_issued_at = Some(issued_at);
         log::debug!("Client {} updated as authenticated", client_id);
     }
 
     log::info!("Device {} paired successfully", request.device_id);
 
+    // Over plain ws:// the secret would travel in the clear, so the phone gets
+    // neither it nor a session derived from it and keeps sending plaintext scans
+    let device_secret = if context.tls { security::derive_device_secret(&secret_key).ok() } else { None };
+    let prepared = device_secret
+        .as_ref()
+        .and_then(|_| prepare_session(client_id, &secret_key, request.session_nonce.as_deref()));
+    let (session, session_nonce) = match prepared {
+        Some((session, server_nonce)) => (Some(session), Some(server_nonce)),
+        None => (None, None),
+    };
+
     // Send success response with auth token
-    let response = serde_json::json!({
-        "action": "pair_ack",
-        "status": "paired",
-        "auth_token": auth_token,
-        "device_id": request.device_id,
-        "timestamp": chrono::Utc::now().timestamp()
-    });
+    let response = ServerMessage::PairAck {
+        status: "paired",
+        auth_token,
+        device_id: request.device_id.clone(),
+        expires_at,
+        device_secret,
+        session_nonce,
+        timestamp: chrono::Utc::now().timestamp(),
+    };
     log::debug!("Sending pair_ack to client {} for device {}", client_id, request.device_id);
     send_to_client(clients, client_id, &response);
     log::debug!("Pair_ack sent successfully");
+
+    // Everything after the ack is encrypted
+    activate_session(clients, client_id, session);
 }
 
 fn handle_reconnect_request(
-    clients: &Clients,
+    context: &ServerContext,
     client_id: usize,
     request: &ReconnectRequest,
-    config: &Arc<Mutex<AppConfig>>,
 ) {
+    let clients = &context.clients;
+    let config = &context.config;
+
     log::info!("Reconnect request from device {}", request.device_id);
 
+    if !check_auth_attempt(context, client_id, &request.device_id) {
+        return;
+    }
+
     let mut cfg = config.lock().unwrap();
 
     // Check if device is authorized
     if !cfg.is_device_authorized(&request.device_id) {
         log::warn!("Device {} is not authorized", request.device_id);
-        let error = serde_json::json!({
-            "action": "reconnect_ack",
-            "status": "unauthorized",
-            "message": "Device not authorized. Please pair again."
-        });
+        drop(cfg);
+        record_auth_failure(context, client_id, &request.device_id);
+        let error = ServerMessage::ReconnectAck {
+            status: ReconnectStatus::Unauthorized,
+            message: Some("Device not authorized. Please pair again.".to_string()),
+            device_id: None,
+            auth_token: None,
+            expires_at: None,
+            device_secret: None,
+            session_nonce: None,
+            timestamp: None,
+        };
         send_to_client(clients, client_id, &error);
         return;
     }
 
     // Validate auth token
-    let secret_key = match &cfg.secret_key {
-        Some(key) => key.clone(),
+    let secret_key = match cfg.device_secret_key(&request.device_id) {
+        Some(key) => key,
         None => {
-            log::error!("No secret key configured");
-            send_error(clients, client_id, "Server configuration error");
+            log::error!("No secret key configured for device {}", request.device_id);
+            send_error(clients, client_id, ErrorCode::ServerError, "Server configuration error");
             return;
         }
     };
 
-    if !security::validate_auth_token(&request.auth_token, &request.device_id, &secret_key) {
-        log::warn!("Invalid auth token from device {}", request.device_id);
-        let error = serde_json::json!({
-            "action": "reconnect_ack",
-            "status": "invalid_token",
-            "message": "Invalid auth token. Please pair again."
-        });
-        send_to_client(clients, client_id, &error);
-        return;
+    let mut token_issued_at = match security::validate_auth_token(&request.auth_token, &request.device_id, &secret_key, cfg.auth_token_lifetime_secs) {
+        Ok(issued_at) => issued_at,
+        Err(e) => {
+            let (status, message) = match e {
+                TokenError::Expired => {
+                    log::warn!("Expired auth token from device {}", request.device_id);
+                    (ReconnectStatus::TokenExpired, "Auth token expired. Please pair again.")
+                }
+                TokenError::Invalid => {
+                    log::warn!("Invalid auth token from device {}", request.device_id);
+                    record_auth_failure(context, client_id, &request.device_id);
+                    (ReconnectStatus::InvalidToken, "Invalid auth token. Please pair again.")
+                }
+            };
+            let error = ServerMessage::ReconnectAck {
+                status,
+                message: Some(message.to_string()),
+                device_id: None,
+                auth_token: None,
+                expires_at: None,
+                device_secret: None,
+                session_nonce: None,
+                timestamp: None,
+            };
+            send_to_client(clients, client_id, &error);
+            return;
+        }
+    };
+
+    // Move devices paired with the legacy shared key onto their own key, if the
+    // client understands rotated tokens in reconnect_ack
+    let client_version = clients
+        .lock()
+        .unwrap()
+        .get(&client_id)
+        .map(|c| c.protocol_version)
+        .unwrap_or(protocol::PROTOCOL_VERSION_MIN);
+
+    let mut rotated_token = None;
+    if cfg.uses_legacy_key(&request.device_id) && client_version >= protocol::KEY_ROTATION_MIN_PROTOCOL_VERSION {
+        if let Some(device_key) = migrate_device_key_and_save(context, &mut cfg, &request.device_id) {
+            let auth_token = security::create_auth_token(&request.device_id, &device_key);
+            token_issued_at = chrono::Utc::now().timestamp();
+            let expires_at = security::auth_token_expires_at(token_issued_at, cfg.auth_token_lifetime_secs);
+            rotated_token = Some((auth_token, expires_at));
+        }
     }
 
+    // Sessions need the device's own key; legacy devices stay in plaintext until migrated
+    let device_key = if cfg.uses_legacy_key(&request.device_id) {
+        None
+    } else {
+        cfg.device_secret_key(&request.device_id)
+    };
+
     // Update last seen
     if let Some(device) = cfg.authorized_devices.get_mut(&request.device_id) {
         device.last_seen = chrono::Utc::now().to_rfc3339();
@@ -426,7 +1168,9 @@ fn handle_reconnect_request(
     // Save config
     drop(cfg);
     if let Ok(cfg) = config.lock() {
-        let _ = storage::save(&cfg);
+        if let Err(e) = save_config(context.config_path.as_deref(), &cfg) {
+            log::error!("Failed to save config: {}", e);
+        }
     }
 
     // Remove any old connection from this device
@@ -444,38 +1188,155 @@ fn handle_reconnect_request(
         client.authenticated = true;
         client.device_id = Some(request.device_id.clone());
         client.device_name = device_name;
+        client.token_issued_at = Some(token_issued_at);
     }
 
     log::info!("Device {} reconnected successfully", request.device_id);
 
     // Send success response
-    let response = serde_json::json!({
-        "action": "reconnect_ack",
-        "status": "connected",
-        "device_id": request.device_id,
-        "timestamp": chrono::Utc::now().timestamp()
-    });
+    let (auth_token, expires_at) = match rotated_token {
+        Some((token, expires_at)) => (Some(token), expires_at),
+        None => (None, None),
+    };
+    // The device secret only changes when the key was just migrated, and is only
+    // sent over wss://; without it the phone cannot join a session on the new key
+    let key_migrated = auth_token.is_some();
+    let device_secret = match (&auth_token, &device_key) {
+        (Some(_), Some(key)) if context.tls => security::derive_device_secret(key).ok(),
+        _ => None,
+    };
+    let prepared = device_key
+        .as_deref()
+        .filter(|_| !key_migrated || device_secret.is_some())
+        .and_then(|key| prepare_session(client_id, key, request.session_nonce.as_deref()));
+    let (session, session_nonce) = match prepared {
+        Some((session, server_nonce)) => (Some(session), Some(server_nonce)),
+        None => (None, None),
+    };
+
+    let response = ServerMessage::ReconnectAck {
+        status: ReconnectStatus::Connected,
+        message: None,
+        device_id: Some(request.device_id.clone()),
+        auth_token,
+        expires_at,
+        device_secret,
+        session_nonce,
+        timestamp: Some(chrono::Utc::now().timestamp()),
+    };
     send_to_client(clients, client_id, &response);
+
+    // Everything after the ack is encrypted
+    activate_session(clients, client_id, session);
 }
 
-fn handle_scan_message(
-    clients: &Clients,
+/// Gives a legacy device its own key, but only once the config holding it is
+/// saved: a token signed with an unsaved key stops validating after a restart
+fn migrate_device_key_and_save(context: &ServerContext, cfg: &mut AppConfig, device_id: &str) -> Option<String> {
+    let mut migrated = cfg.clone();
+    let device_key = migrated.migrate_device_key(device_id)?;
+    match save_config(context.config_path.as_deref(), &migrated) {
+        Ok(()) => {
+            log::info!("Migrated device {} to its own key", device_id);
+            *cfg = migrated;
+            Some(device_key)
+        }
+        Err(e) => {
+            log::error!("Device {} keeps the shared key, its own key could not be saved: {}", device_id, e);
+            None
+        }
+    }
+}
+
+fn handle_refresh_token_request(
+    context: &ServerContext,
     client_id: usize,
-    scan_msg: &ScanMessage,
-    master_token: &str,
-    config: &Arc<Mutex<AppConfig>>,
-    barcode_sender: &mpsc::UnboundedSender<BarcodeMessage>,
+    request: &RefreshTokenRequest,
 ) {
-    // Get the payload - if missing, we can't process
-    let payload = match &scan_msg.payload {
-        Some(p) => p,
+    let clients = &context.clients;
+    let config = &context.config;
+    log::info!("Token refresh request from device {}", request.device_id);
+
+    // Only a connection already authenticated as this device may refresh
+    let is_same_device = clients
+        .lock()
+        .unwrap()
+        .get(&client_id)
+        .map(|c| c.authenticated && c.device_id.as_deref() == Some(request.device_id.as_str()))
+        .unwrap_or(false);
+
+    if !is_same_device {
+        log::warn!("Client {} tried to refresh a token without being authenticated as {}", client_id, request.device_id);
+        send_error(clients, client_id, ErrorCode::NotAuthenticated, "Authenticate before refreshing the token");
+        return;
+    }
+
+    let mut cfg = config.lock().unwrap();
+
+    let secret_key = match cfg.device_secret_key(&request.device_id) {
+        Some(key) => key,
         None => {
-            log::warn!("Client {} sent scan without payload", client_id);
-            send_error(clients, client_id, "Missing payload");
+            log::warn!("Device {} is not authorized", request.device_id);
+            send_error(clients, client_id, ErrorCode::InvalidToken, "Device not authorized. Please pair again.");
             return;
         }
     };
 
+    // The current token must still be valid; expired tokens require pairing again
+    match security::validate_auth_token(&request.auth_token, &request.device_id, &secret_key, cfg.auth_token_lifetime_secs) {
+        Ok(_) => {}
+        Err(TokenError::Expired) => {
+            send_error(clients, client_id, ErrorCode::TokenExpired, "Auth token expired. Please pair again.");
+            return;
+        }
+        Err(TokenError::Invalid) => {
+            send_error(clients, client_id, ErrorCode::InvalidToken, "Invalid auth token");
+            return;
+        }
+    }
+
+    // Refreshed tokens are signed with the device's own key, once it could be saved
+    let migrated_key = if cfg.uses_legacy_key(&request.device_id) {
+        migrate_device_key_and_save(context, &mut cfg, &request.device_id)
+    } else {
+        None
+    };
+    let migrated = migrated_key.is_some();
+    let device_key = migrated_key.unwrap_or(secret_key);
+
+    let now = chrono::Utc::now().timestamp();
+    let auth_token = security::create_auth_token(&request.device_id, &device_key);
+    let expires_at = security::auth_token_expires_at(now, cfg.auth_token_lifetime_secs);
+    drop(cfg);
+
+    if let Some(client) = clients.lock().unwrap().get_mut(&client_id) {
+        client.token_issued_at = Some(now);
+    }
+    log::info!("Issued refreshed auth token for device {}", request.device_id);
+
+    let response = ServerMessage::RefreshTokenAck {
+        status: "refreshed",
+        auth_token,
+        device_id: request.device_id.clone(),
+        expires_at,
+        device_secret: if migrated && context.tls { security::derive_device_secret(&device_key).ok() } else { None },
+        timestamp: now,
+    };
+    send_to_client(clients, client_id, &response);
+}
+
+/// Checks the credentials sent with a scan and marks the connection as this device.
+/// Returns false (after answering the client) when they are not accepted.
+fn authenticate_scan(
+    context: &ServerContext,
+    client_id: usize,
+    device_id: &str,
+    device_name: Option<&String>,
+    auth_token: Option<&String>,
+    token: Option<&String>,
+) -> bool {
+    let clients = &context.clients;
+
     // Check if client is already authenticated
     let is_authenticated = clients
         .lock()
@@ -484,53 +1345,137 @@ fn handle_scan_message(
         .map(|c| c.authenticated)
         .unwrap_or(false);
 
-    // Validate token - either master token or auth token
-    let valid = if is_authenticated {
-        // Client already authenticated, just verify device is still authorized
-        let cfg = config.lock().unwrap();
-        cfg.is_device_authorized(&scan_msg.device_id)
-    } else if let Some(ref auth_token) = scan_msg.auth_token {
-        // Validate via encrypted auth token
-        let cfg = config.lock().unwrap();
-        if let Some(ref secret_key) = cfg.secret_key {
-            cfg.is_device_authorized(&scan_msg.device_id)
-                && security::validate_auth_token(auth_token, &scan_msg.device_id, secret_key)
+    // Scans from unauthenticated connections carry credentials and are throttled like pairing
+    if !is_authenticated && !check_auth_attempt(context, client_id, device_id) {
+        return false;
+    }
+
+    // Validate token - either master token or auth token. Yields when the auth
+    // token was issued, so the connection expires with it.
+    let validation = if is_authenticated {
+        // Client already authenticated: the device must still be authorized and its token unexpired
+        let cfg = context.config.lock().unwrap();
+        let issued_at = clients.lock().unwrap().get(&client_id).and_then(|c| c.token_issued_at);
+        let expires_at = issued_at.and_then(|issued_at| security::auth_token_expires_at(issued_at, cfg.auth_token_lifetime_secs));
+        if !cfg.is_device_authorized(device_id) {
+            Err(TokenError::Invalid)
+        } else if expires_at.is_some_and(|expires_at| chrono::Utc::now().timestamp() >= expires_at) {
+            Err(TokenError::Expired)
         } else {
-            false
+            Ok(issued_at)
+        }
+    } else if let Some(auth_token) = auth_token {
+        // Validate via encrypted auth token
+        let cfg = context.config.lock().unwrap();
+        match cfg.device_secret_key(device_id) {
+            Some(secret_key) => {
+                security::validate_auth_token(auth_token, device_id, &secret_key, cfg.auth_token_lifetime_secs)
+                    .map(Some)
+            }
+            None => Err(TokenError::Invalid),
         }
-    } else if let Some(ref token) = scan_msg.token {
+    } else if let Some(token) = token {
         // Fallback: validate via master token (backward compatibility / initial connection)
-        token == master_token
+        let cfg = context.config.lock().unwrap();
+        if cfg.master_token.as_deref() == Some(token.as_str()) { Ok(None) } else { Err(TokenError::Invalid) }
     } else {
-        false
+        Err(TokenError::Invalid)
     };
 
-    if !valid {
-        log::warn!("Client {} sent invalid token for scan", client_id);
-        send_error(clients, client_id, "Invalid token");
-        return;
-    }
+    let token_issued_at = match validation {
+        Ok(issued_at) => issued_at,
+        Err(TokenError::Expired) => {
+            log::warn!("Client {} sent expired token for scan", client_id);
+            send_error(clients, client_id, ErrorCode::TokenExpired, "Auth token expired");
+            if is_authenticated {
+                // The session was derived for this token; the phone has to reconnect and pair again
+                if let Some(client) = clients.lock().unwrap().remove(&client_id) {
+                    close_client(&client, "Auth token expired");
+                }
+            }
+            return false;
+        }
+        Err(TokenError::Invalid) => {
+            log::warn!("Client {} sent invalid token for scan", client_id);
+            if !is_authenticated {
+                record_auth_failure(context, client_id, device_id);
+            }
+            send_error(clients, client_id, ErrorCode::InvalidToken, "Invalid token");
+            return false;
+        }
+    };
 
     // Update client as authenticated
     if let Some(client) = clients.lock().unwrap().get_mut(&client_id) {
         client.authenticated = true;
-        client.device_id = Some(scan_msg.device_id.clone());
-        client.device_name = scan_msg.device_name.clone();
+        client.device_id = Some(device_id.to_string());
+        client.device_name = device_name.cloned();
+        client.token_issued_at = token_issued_at;
     }
 
-    log::info!(
-        "Barcode received from device {}: {}",
-        scan_msg.device_id,
-        payload.barcode
-    );
+    true
+}
 
-    // Send acknowledgment
-    let ack = serde_json::json!({
-        "action": "scan_ack",
-        "status": "received",
-        "barcode": payload.barcode
-    });
-    send_to_client(clients, client_id, &ack);
+/// Outcome of handing a scan to the desktop pipeline
+#[derive(Debug, PartialEq)]
+enum Delivery {
+    Accepted,
+    /// Its scan id was already delivered
+    Duplicate,
+    /// The pipeline is gone (server shutting down); the phone should retry
+    Failed,
+}
+
+/// Forwards a scan unless its id was already delivered. The id is only
+/// remembered once the pipeline took the scan, so a failed delivery can be retried.
+fn deliver_scan(context: &ServerContext, scan_id: Option<&str>, barcode_msg: BarcodeMessage) -> Delivery {
+    // Held across the send so a retry racing on another connection waits for the outcome
+    let mut recent_scans = context.recent_scans.lock().unwrap();
+    // Scans without an id (older apps) cannot be de-duplicated
+    if let Some(scan_id) = scan_id {
+        if recent_scans.contains(&barcode_msg.device_id, scan_id) {
+            return Delivery::Duplicate;
+        }
+    }
+
+    let device_id = barcode_msg.device_id.clone();
+    if let Err(e) = context.barcode_sender.send(barcode_msg) {
+        log::error!("Failed to send barcode to frontend: {}", e);
+        return Delivery::Failed;
+    }
+    if let Some(scan_id) = scan_id {
+        recent_scans.insert(&device_id, scan_id);
+    }
+    Delivery::Accepted
+}
+
+fn handle_scan_message(
+    context: &ServerContext,
+    client_id: usize,
+    scan_msg: &ScanMessage,
+) {
+    let clients = &context.clients;
+
+    // Get the payload - if missing, we can't process
+    let payload = match &scan_msg.payload {
+        Some(p) => p,
+        None => {
+            log::warn!("Client {} sent scan without payload", client_id);
+            send_error(clients, client_id, ErrorCode::MissingPayload, "Missing payload");
+            return;
+        }
+    };
+
+    if !authenticate_scan(
+        context,
+        client_id,
+        &scan_msg.device_id,
+        scan_msg.device_name.as_ref(),
+        scan_msg.auth_token.as_ref(),
+        scan_msg.token.as_ref(),
+    ) {
+        return;
+    }
 
     // Convert to BarcodeMessage for frontend
     let barcode_msg = BarcodeMessage {
@@ -538,10 +1483,433 @@ fn handle_scan_message(
         timestamp: scan_msg.timestamp,
         device_id: scan_msg.device_id.clone(),
         device_name: scan_msg.device_name.clone(),
+        barcode_type: payload.barcode_type.clone(),
+        gs1: None,
     };
 
-    // Forward barcode to Tauri frontend
-    if let Err(e) = barcode_sender.send(barcode_msg) {
-        log::error!("Failed to send barcode to frontend: {}", e);
+    // Forward barcode to Tauri frontend, then acknowledge
+    let status = match deliver_scan(context, scan_msg.scan_id.as_deref(), barcode_msg) {
+        Delivery::Accepted => {
+            log::info!("Barcode received from device {}: {}", scan_msg.device_id, payload.barcode);
+            "received"
+        }
+        Delivery::Duplicate => {
+            log::info!("Ignoring duplicate scan {:?} from device {}", scan_msg.scan_id, scan_msg.device_id);
+            "duplicate"
+        }
+        Delivery::Failed => {
+            send_error(clients, client_id, ErrorCode::ServerError, "Server is shutting down");
+            return;
+        }
+    };
+
+    let ack = ServerMessage::ScanAck {
+        status,
+        barcode: payload.barcode.clone(),
+        scan_id: scan_msg.scan_id.clone(),
+    };
+    send_to_client(clients, client_id, &ack);
+}
+
+fn handle_scan_batch_message(
+    context: &ServerContext,
+    client_id: usize,
+    batch: &ScanBatchMessage,
+) {
+    let clients = &context.clients;
+
+    if batch.scans.len() > protocol::MAX_SCAN_BATCH_SIZE {
+        log::warn!("Client {} sent a batch of {} scans", client_id, batch.scans.len());
+        send_error(
+            clients,
+            client_id,
+            ErrorCode::InvalidMessage,
+            &format!("A batch may contain at most {} scans", protocol::MAX_SCAN_BATCH_SIZE),
+        );
+        return;
+    }
+
+    if !authenticate_scan(
+        context,
+        client_id,
+        &batch.device_id,
+        batch.device_name.as_ref(),
+        batch.auth_token.as_ref(),
+        batch.token.as_ref(),
+    ) {
+        return;
+    }
+
+    log::info!("Scan batch of {} received from device {}", batch.scans.len(), batch.device_id);
+
+    // Items are forwarded in the order the phone queued them
+    let mut results = Vec::with_capacity(batch.scans.len());
+    for item in &batch.scans {
+        let result = |status, message: Option<&str>| ScanItemResult {
+            scan_id: item.scan_id.clone(),
+            status,
+            message: message.map(str::to_string),
+        };
+
+        if item.scan_id.is_empty() || item.payload.barcode.is_empty() {
+            results.push(result(ScanItemStatus::Rejected, Some("Missing scan id or barcode")));
+            continue;
+        }
+
+        let barcode_msg = BarcodeMessage {
+            barcode: item.payload.barcode.clone(),
+            timestamp: item.timestamp,
+            device_id: batch.device_id.clone(),
+            device_name: batch.device_name.clone(),
+            barcode_type: item.payload.barcode_type.clone(),
+            gs1: None,
+        };
+
+        match deliver_scan(context, Some(&item.scan_id), barcode_msg) {
+            Delivery::Accepted => results.push(result(ScanItemStatus::Accepted, None)),
+            Delivery::Duplicate => {
+                log::debug!("Skipping duplicate scan {} from device {}", item.scan_id, batch.device_id);
+                results.push(result(ScanItemStatus::Duplicate, None));
+            }
+            Delivery::Failed => results.push(result(ScanItemStatus::Rejected, Some("Server is shutting down"))),
+        }
+    }
+
+    let ack = ServerMessage::ScanBatchAck {
+        results,
+        timestamp: chrono::Utc::now().timestamp(),
+    };
+    send_to_client(clients, client_id, &ack);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, TcpListener};
+
+    fn test_server() -> WebSocketServer {
+        // Nothing is saved: handlers log that the config could not be written
+        WebSocketServer::with_storage(Arc::new(Mutex::new(AppConfig::default())), None, RecentScanIds::new())
+    }
+
+    /// Context handlers see, with the receiving end of the scan pipeline
+    fn test_context(
+        server: &WebSocketServer,
+    ) -> (ServerContext, mpsc::UnboundedReceiver<BarcodeMessage>, mpsc::UnboundedReceiver<ServerEvent>) {
+        let (barcode_sender, barcode_receiver) = mpsc::unbounded_channel();
+        let (event_sender, event_receiver) = mpsc::unbounded_channel();
+        let context = ServerContext {
+            clients: server.clients.clone(),
+            config: server.config.clone(),
+            config_path: server.config_path.clone(),
+            barcode_sender,
+            event_sender,
+            recent_scans: server.recent_scans.clone(),
+            rate_limiter: server.rate_limiter.clone(),
+            pending_commands: server.pending_commands.clone(),
+            tls: true,
+        };
+        (context, barcode_receiver, event_receiver)
+    }
+
+    fn test_barcode(device_id: &str) -> BarcodeMessage {
+        BarcodeMessage {
+            barcode: "4006381333931".to_string(),
+            timestamp: 0,
+            device_id: device_id.to_string(),
+            device_name: None,
+            barcode_type: None,
+            gs1: None,
+        }
+    }
+
+    #[test]
+    fn test_failed_delivery_can_be_retried() {
+        let server = test_server();
+        let (context, receiver, _events) = test_context(&server);
+        drop(receiver);
+        assert_eq!(deliver_scan(&context, Some("s1"), test_barcode("phone")), Delivery::Failed);
+
+        let (context, mut receiver, _events) = test_context(&server);
+        assert_eq!(deliver_scan(&context, Some("s1"), test_barcode("phone")), Delivery::Accepted);
+        assert_eq!(deliver_scan(&context, Some("s1"), test_barcode("phone")), Delivery::Duplicate);
+        assert_eq!(deliver_scan(&context, None, test_barcode("phone")), Delivery::Accepted);
+        assert_eq!(receiver.try_recv().unwrap().barcode, "4006381333931");
+    }
+
+    /// Registers an authenticated connection for `device_id` and returns what it is sent
+    fn connect_test_client(server: &WebSocketServer, client_id: usize, device_id: &str) -> mpsc::UnboundedReceiver<Message> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let client = ClientInfo {
+            sender,
+            device_id: Some(device_id.to_string()),
+            device_name: Some("Phone".to_string()),
+            authenticated: true,
+            protocol_version: protocol::PROTOCOL_VERSION_MAX,
+            session: None,
+            remote_ip: Some(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            token_issued_at: None,
+        };
+        server.clients.lock().unwrap().insert(client_id, client);
+        receiver
+    }
+
+    /// Registers a connection from `ip` that has not authenticated yet
+    fn connect_anonymous_client(server: &WebSocketServer, client_id: usize, ip: &str) -> mpsc::UnboundedReceiver<Message> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let client = ClientInfo {
+            sender,
+            device_id: None,
+            device_name: None,
+            authenticated: false,
+            protocol_version: protocol::PROTOCOL_VERSION_MAX,
+            session: None,
+            remote_ip: Some(ip.parse().unwrap()),
+            token_issued_at: None,
+        };
+        server.clients.lock().unwrap().insert(client_id, client);
+        receiver
+    }
+
+    /// Text of every message sent to a test connection so far
+    fn sent_messages(receiver: &mut mpsc::UnboundedReceiver<Message>) -> Vec<String> {
+        std::iter::from_fn(|| receiver.try_recv().ok())
+            .filter_map(|message| message.to_str().ok().map(str::to_string))
+            .collect()
+    }
+
+    #[test]
+    fn test_failures_for_a_claimed_device_do_not_lock_it_out() {
+        let server = test_server();
+        let device = AuthorizedDevice::new("victim".to_string(), "Phone".to_string(), None);
+        let auth_token = security::create_auth_token("victim", device.secret_key.as_deref().unwrap());
+        server.config.lock().unwrap().add_device(device);
+        let (context, _barcodes, _events) = test_context(&server);
+
+        let mut attacker = connect_anonymous_client(&server, 0, "10.0.0.66");
+        let forged = ReconnectRequest { device_id: "victim".to_string(), auth_token: "forged".to_string(), session_nonce: None };
+        for _ in 0..10 {
+            handle_reconnect_request(&context, 0, &forged);
+        }
+        assert!(sent_messages(&mut attacker).last().unwrap().contains("rate_limited"));
+
+        let mut phone = connect_anonymous_client(&server, 1, "10.0.0.7");
+        let request = ReconnectRequest { device_id: "victim".to_string(), auth_token, session_nonce: None };
+        handle_reconnect_request(&context, 1, &request);
+        let replies = sent_messages(&mut phone);
+        assert!(replies[0].contains("\"connected\""), "{:?}", replies);
+    }
+
+    #[test]
+    fn test_legacy_key_is_migrated_only_once_saved() {
+        let shared_key = security::generate_secret_key();
+        let legacy_server = |config_path: Option<PathBuf>| {
+            let mut config = AppConfig { secret_key: Some(shared_key.clone()), ..Default::default() };
+            let mut device = AuthorizedDevice::new("phone".to_string(), "Phone".to_string(), None);
+            device.secret_key = None;
+            config.add_device(device);
+            WebSocketServer::with_storage(Arc::new(Mutex::new(config)), config_path, RecentScanIds::new())
+        };
+        let request = ReconnectRequest {
+            device_id: "phone".to_string(),
+            auth_token: security::create_auth_token("phone", &shared_key),
+            session_nonce: None,
+        };
+
+        // The save fails: the phone keeps its token on the shared key
+        let server = legacy_server(None);
+        let (context, _barcodes, _events) = test_context(&server);
+        let mut phone = connect_anonymous_client(&server, 0, "10.0.0.7");
+        handle_reconnect_request(&context, 0, &request);
+        let reply: serde_json::Value = serde_json::from_str(&sent_messages(&mut phone)[0]).unwrap();
+        assert_eq!(reply["status"], "connected");
+        assert!(reply.get("auth_token").is_none());
+        assert!(server.config.lock().unwrap().uses_legacy_key("phone"));
+
+        // Saved: the phone gets a token on its own key, which the saved config validates
+        let dir = std::env::temp_dir().join(format!("scanlink-websocket-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config_path = dir.join("config.json");
+        let server = legacy_server(Some(config_path.clone()));
+        let (context, _barcodes, _events) = test_context(&server);
+        let mut phone = connect_anonymous_client(&server, 0, "10.0.0.7");
+        handle_reconnect_request(&context, 0, &request);
+        let reply: serde_json::Value = serde_json::from_str(&sent_messages(&mut phone)[0]).unwrap();
+        let rotated = reply["auth_token"].as_str().unwrap();
+        let device_key = server.config.lock().unwrap().device_secret_key("phone").unwrap();
+        assert!(!server.config.lock().unwrap().uses_legacy_key("phone"));
+        assert!(security::validate_auth_token(rotated, "phone", &device_key, 0).is_ok());
+        assert!(config_path.exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_send_command() {
+        let server = test_server();
+        let command = DeviceCommand::Vibrate { duration_ms: None };
+        assert!(server.send_command("phone", command.clone()).is_err(), "not connected");
+
+        let mut phone = connect_test_client(&server, 0, "phone");
+        server.clients.lock().unwrap().get_mut(&0).unwrap().protocol_version = protocol::COMMANDS_MIN_PROTOCOL_VERSION - 1;
+        assert!(server.send_command("phone", command.clone()).is_err(), "app too old for commands");
+
+        server.clients.lock().unwrap().get_mut(&0).unwrap().protocol_version = protocol::COMMANDS_MIN_PROTOCOL_VERSION;
+        let command_id = server.send_command("phone", command).unwrap();
+        let sent = sent_messages(&mut phone);
+        assert!(sent[0].contains(&command_id), "{:?}", sent);
+        assert!(server.pending_commands.lock().unwrap().contains_key(&command_id));
+    }
+
+    #[test]
+    fn test_command_acks_must_match_a_pending_command() {
+        let server = test_server();
+        let (context, _barcodes, mut events) = test_context(&server);
+        let mut phone = connect_test_client(&server, 0, "phone");
+        let mut other = connect_test_client(&server, 1, "other");
+        let command_id = server.send_command("phone", DeviceCommand::TriggerScan).unwrap();
+        sent_messages(&mut phone);
+
+        let ack = |status| CommandAck { command_id: command_id.clone(), status, message: None };
+
+        // Another device cannot answer for the phone
+        handle_command_ack(&context, 1, ack(CommandAckStatus::Executed));
+        assert!(sent_messages(&mut other)[0].contains("invalid_message"));
+        assert!(events.try_recv().is_err());
+
+        // Delivered keeps waiting for the final ack, which ends the command
+        handle_command_ack(&context, 0, ack(CommandAckStatus::Delivered));
+        handle_command_ack(&context, 0, ack(CommandAckStatus::Executed));
+        for status in [CommandAckStatus::Delivered, CommandAckStatus::Executed] {
+            let ServerEvent::CommandAck(event) = events.try_recv().unwrap() else { panic!("expected a command ack") };
+            assert_eq!((event.device_id.as_str(), event.status), ("phone", status));
+        }
+        handle_command_ack(&context, 0, ack(CommandAckStatus::Executed));
+        assert!(sent_messages(&mut phone)[0].contains("invalid_message"));
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_unanswered_commands_time_out() {
+        let server = test_server();
+        let _phone = connect_test_client(&server, 0, "phone");
+        let command_id = server.send_command("phone", DeviceCommand::TriggerScan).unwrap();
+        let sent_at = server.pending_commands.lock().unwrap()[&command_id].sent_at;
+
+        assert!(expire_pending_commands(&server.pending_commands, sent_at + COMMAND_TIMEOUT_SECS - 1).is_empty());
+        let expired = expire_pending_commands(&server.pending_commands, sent_at + COMMAND_TIMEOUT_SECS);
+        assert_eq!(expired.len(), 1);
+        assert_eq!((expired[0].command_id.as_str(), expired[0].status), (command_id.as_str(), CommandAckStatus::TimedOut));
+        assert!(server.pending_commands.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_disconnect_device_closes_its_connections() {
+        let server = test_server();
+        let mut first = connect_test_client(&server, 0, "phone");
+        let mut second = connect_test_client(&server, 1, "phone");
+        let mut other = connect_test_client(&server, 2, "other");
+
+        server.disconnect_device("phone");
+
+        assert!(first.try_recv().unwrap().is_close());
+        assert!(second.try_recv().unwrap().is_close());
+        assert!(other.try_recv().is_err());
+        let clients = server.clients.lock().unwrap();
+        assert_eq!(clients.keys().collect::<Vec<_>>(), vec![&2]);
+    }
+
+    #[test]
+    fn test_connections_stop_accepting_scans_when_their_token_expires() {
+        let server = test_server();
+        {
+            let mut config = server.config.lock().unwrap();
+            config.auth_token_lifetime_secs = 60;
+            config.add_device(AuthorizedDevice::new("phone".to_string(), "Phone".to_string(), None));
+        }
+        let (context, mut barcodes, _events) = test_context(&server);
+        let now = chrono::Utc::now().timestamp();
+        let scan: ScanMessage = serde_json::from_value(serde_json::json!({
+            "deviceId": "phone",
+            "timestamp": now,
+            "payload": { "barcode": "4006381333931" },
+        }))
+        .unwrap();
+        let batch: ScanBatchMessage = serde_json::from_value(serde_json::json!({
+            "deviceId": "phone",
+            "scans": [{ "scanId": "s1", "timestamp": now, "payload": { "barcode": "4006381333931" } }],
+        }))
+        .unwrap();
+
+        let mut fresh = connect_test_client(&server, 0, "phone");
+        server.clients.lock().unwrap().get_mut(&0).unwrap().token_issued_at = Some(now - 30);
+        handle_scan_message(&context, 0, &scan);
+        assert!(barcodes.try_recv().is_ok());
+        assert!(sent_messages(&mut fresh).iter().any(|m| m.contains("scan_ack")));
+
+        let mut expired = connect_test_client(&server, 1, "phone");
+        server.clients.lock().unwrap().get_mut(&1).unwrap().token_issued_at = Some(now - 61);
+        handle_scan_message(&context, 1, &scan);
+        assert!(barcodes.try_recv().is_err());
+        assert!(sent_messages(&mut expired).iter().any(|m| m.contains("token_expired")));
+        assert!(!server.clients.lock().unwrap().contains_key(&1), "the expired connection is closed");
+
+        let mut expired_batch = connect_test_client(&server, 2, "phone");
+        server.clients.lock().unwrap().get_mut(&2).unwrap().token_issued_at = Some(now - 61);
+        handle_scan_batch_message(&context, 2, &batch);
+        assert!(barcodes.try_recv().is_err());
+        assert!(sent_messages(&mut expired_batch).iter().any(|m| m.contains("token_expired")));
+        assert!(!server.clients.lock().unwrap().contains_key(&2));
+    }
+
+    #[tokio::test]
+    async fn test_bind_falls_back_to_a_free_port() {
+        let taken = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let server = test_server();
+        let bind = |fallback_attempts: u16| {
+            {
+                let mut config = server.config.lock().unwrap();
+                config.set_network_settings(NetworkSettings {
+                    port,
+                    bind_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                    port_fallback_attempts: fallback_attempts,
+                    tls_enabled: false,
+                });
+            }
+            let (barcode_sender, _) = mpsc::unbounded_channel();
+            let (event_sender, _) = mpsc::unbounded_channel();
+            server.bind(barcode_sender, event_sender)
+        };
+
+        let error = bind(0).err().unwrap();
+        assert!(error.contains("already in use"), "{}", error);
+
+        // Other ports of the range may be taken too on a busy machine
+        if let Ok(bound) = bind(10) {
+            assert!(bound.addr.port() > port && bound.addr.port() - port <= 10);
+            // The server keeps the port it found free
+            assert!(TcpListener::bind(bound.addr).is_err());
+        }
+    }
+
+    #[test]
+    fn test_is_addr_in_use() {
+        let in_use = std::io::Error::from(ErrorKind::AddrInUse);
+        assert!(is_addr_in_use(&in_use));
+        assert!(!is_addr_in_use(&std::io::Error::from(ErrorKind::AddrNotAvailable)));
+    }
+
+    #[test]
+    fn test_network_settings_validation() {
+        let settings = NetworkSettings {
+            port: DEFAULT_PORT,
+            bind_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port_fallback_attempts: 5,
+            tls_enabled: true,
+        };
+        assert!(settings.validate().is_ok());
+        assert!(NetworkSettings { port: 0, ..settings.clone() }.validate().is_err());
+        assert!(NetworkSettings { port: u16::MAX, ..settings }.validate().is_err());
     }
 }
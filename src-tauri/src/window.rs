@@ -0,0 +1,77 @@
+use serde::{Deserialize, Serialize};
+use std::process::Command;
+use crate::keyboard::is_wayland;
+
+/// Window that has keyboard focus, where the keyboard sink would type
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FocusedWindow {
+    /// WM_CLASS class name, e.g. "firefox" or "Slack"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub class: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+}
+
+/// Runs a command and returns its trimmed stdout when it succeeds
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let text = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if text.is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+/// Extracts the class from `WM_CLASS(STRING) = "instance", "Class"`
+fn parse_xprop_class(output: &str) -> Option<String> {
+    let values = output.split_once('=')?.1;
+    let class = values.rsplit(',').next()?.trim().trim_matches('"');
+    if class.is_empty() {
+        None
+    } else {
+        Some(class.to_string())
+    }
+}
+
+/// Class of a window; `getwindowclassname` needs xdotool 3.2021 or newer, so xprop is the fallback
+fn window_class(window_id: &str) -> Option<String> {
+    command_output("xdotool", &["getwindowclassname", window_id]).or_else(|| {
+        command_output("xprop", &["-id", window_id, "WM_CLASS"])
+            .and_then(|output| parse_xprop_class(&output))
+    })
+}
+
+/// Detects the focused window on X11. Returns None on Wayland, where other
+/// applications' windows cannot be inspected, or when xdotool is missing.
+pub fn focused_window() -> Option<FocusedWindow> {
+    if is_wayland() {
+        log::debug!("Focused window detection is not available on Wayland");
+        return None;
+    }
+
+    let window_id = command_output("xdotool", &["getactivewindow"])?;
+    let window = FocusedWindow {
+        class: window_class(&window_id),
+        title: command_output("xdotool", &["getwindowname", &window_id]),
+    };
+    log::debug!("Focused window: {:?}", window);
+    Some(window)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_xprop_class() {
+        assert_eq!(
+            parse_xprop_class(r#"WM_CLASS(STRING) = "slack", "Slack""#),
+            Some("Slack".to_string())
+        );
+        assert_eq!(parse_xprop_class("WM_CLASS:  not found."), None);
+    }
+}
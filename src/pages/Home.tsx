@@ -1,10 +1,11 @@
 import { Button } from '@/components/ui/button';
 import { Card, CardContent, CardDescription, CardHeader, CardTitle } from '@/components/ui/card';
+import { Input } from '@/components/ui/input';
 import { Sheet, SheetContent, SheetDescription, SheetHeader, SheetTitle } from '@/components/ui/sheet';
-import { useAppStore } from '@/store';
+import { useAppStore, type ConfigRecovery, type Gs1Data, type LockoutEvent, type QRCodeData } from '@/store';
 import { invoke } from '@tauri-apps/api/core';
 import { listen } from '@tauri-apps/api/event';
-import { Play, QrCode, Settings, Smartphone, Square, Trash2, Wifi } from 'lucide-react';
+import { Play, QrCode, RefreshCw, Settings, Smartphone, Square, Trash2, Wifi } from 'lucide-react';
 import { useEffect, useState } from 'react';
 import { useTranslation } from 'react-i18next';
 
@@ -13,6 +14,8 @@ interface BarcodeMessage {
   timestamp: string;
   device_id: string;
   device_name?: string;
+  barcode_type?: string;
+  gs1?: Gs1Data;
 }
 
 interface ConnectedDevice {
@@ -20,6 +23,7 @@ interface ConnectedDevice {
   deviceName: string;
   deviceModel?: string;
   isConnected: boolean;
+  legacyKey?: boolean;
 }
 
 interface HomeProps {
@@ -46,6 +50,8 @@ export default function Home({ onOpenSettings }: HomeProps) {
   const [showDevicesSheet, setShowDevicesSheet] = useState(false);
   const [connectedDevices, setConnectedDevices] = useState<ConnectedDevice[]>([]);
   const [loadingDevices, setLoadingDevices] = useState(false);
+  const [configRecovery, setConfigRecovery] = useState<ConfigRecovery | null>(null);
+  const [passphrase, setPassphrase] = useState('');
 
   const fetchConnectedDevices = async () => {
     setLoadingDevices(true);
@@ -97,6 +103,31 @@ export default function Home({ onOpenSettings }: HomeProps) {
       setQRData(event.payload);
     });
 
+    // The QR code changes when the master token rotates or its policy changes
+    const unlistenQRPromise = listen<QRCodeData>('qr-updated', (event) => {
+      setQRData(event.payload);
+    });
+
+    // Surface lockouts after repeated failed pairing attempts
+    const unlistenLockoutPromise = listen<LockoutEvent>('client-locked-out', (event) => {
+      const { ip, lockedUntil } = event.payload;
+      setError(
+        t('security.lockout', {
+          source: ip,
+          until: new Date(lockedUntil * 1000).toLocaleTimeString(),
+        })
+      );
+    });
+
+    // Offer the config backup when the config file was corrupt at startup. The
+    // event may fire before this listener exists, so also ask the backend.
+    const unlistenRecoveryPromise = listen<ConfigRecovery>('config-recovery', (event) => {
+      setConfigRecovery(event.payload);
+    });
+    invoke<ConfigRecovery | null>('get_config_recovery')
+      .then(setConfigRecovery)
+      .catch((err) => console.error('[ERROR] Failed to get config recovery:', err));
+
     // Check server status periodically
     const interval = window.setInterval(() => {
       checkServerStatus();
@@ -140,6 +171,9 @@ export default function Home({ onOpenSettings }: HomeProps) {
     return () => {
       unlistenBarcodePromise.then((unlisten) => unlisten());
       unlistenServerPromise.then((unlisten) => unlisten());
+      unlistenQRPromise.then((unlisten) => unlisten());
+      unlistenLockoutPromise.then((unlisten) => unlisten());
+      unlistenRecoveryPromise.then((unlisten) => unlisten());
       if (interval) clearInterval(interval);
     };
     // eslint-disable-next-line react-hooks/exhaustive-deps
@@ -182,6 +216,59 @@ export default function Home({ onOpenSettings }: HomeProps) {
     }
   };
 
+  const handleRegenerateToken = async () => {
+    setError(null);
+    try {
+      setQRData(await invoke<QRCodeData>('regenerate_token'));
+    } catch (err) {
+      console.error('[ERROR] Failed to regenerate token:', err);
+      setError(err as string);
+    }
+  };
+
+  const qrValidity = (() => {
+    const validity = qrData?.validity;
+    if (!validity) return null;
+    if (validity.expiresAt !== undefined) {
+      return t('qrCode.validUntil', { date: new Date(validity.expiresAt * 1000).toLocaleString() });
+    }
+    if (validity.remainingPairings !== undefined) {
+      const count = validity.remainingPairings;
+      return t(`qrCode.validForPairings${count === 1 ? '' : '_plural'}`, { count });
+    }
+    return t('qrCode.validUntilRegenerated');
+  })();
+
+  const handleRestoreConfig = async () => {
+    try {
+      await invoke('restore_config_backup');
+      setConfigRecovery(null);
+    } catch (err) {
+      console.error('[ERROR] Failed to restore config backup:', err);
+      setError(err as string);
+    }
+  };
+
+  const handleUnlockConfig = async () => {
+    setError(null);
+    try {
+      const recovery = await invoke<ConfigRecovery | null>('unlock_config', { passphrase: passphrase || null });
+      setPassphrase('');
+      setConfigRecovery(recovery);
+    } catch (err) {
+      console.error('[ERROR] Failed to unlock config:', err);
+      setError(err as string);
+    }
+  };
+
+  const handleDismissConfigRecovery = async () => {
+    try {
+      await invoke('dismiss_config_recovery');
+    } finally {
+      setConfigRecovery(null);
+    }
+  };
+
   const handleStopServer = async () => {
     setLoading(true);
     setError(null);
@@ -291,6 +378,47 @@ export default function Home({ onOpenSettings }: HomeProps) {
                   </CardDescription>
                 </CardHeader>
                 <CardContent className="space-y-3">
+                  {configRecovery?.keyUnavailable && (
+                    <div className="bg-[var(--error-muted)] border border-[var(--error)]/30 rounded-lg p-3 text-[var(--error)] text-xs space-y-2">
+                      <p>{t('configRecovery.locked')}</p>
+                      <Input
+                        type="password"
+                        placeholder={t('configRecovery.passphrase')}
+                        value={passphrase}
+                        onChange={(e) => setPassphrase(e.target.value)}
+                        onKeyDown={(e) => e.key === 'Enter' && handleUnlockConfig()}
+                      />
+                      <Button size="sm" onClick={handleUnlockConfig}>
+                        {t('configRecovery.unlock')}
+                      </Button>
+                    </div>
+                  )}
+
+                  {configRecovery && !configRecovery.keyUnavailable && (
+                    <div className="bg-[var(--error-muted)] border border-[var(--error)]/30 rounded-lg p-3 text-[var(--error)] text-xs space-y-2">
+                      <p>
+                        {configRecovery.backupAvailable
+                          ? t('configRecovery.corruptWithBackup', {
+                              savedAt: new Date(configRecovery.backupSavedAt ?? '').toLocaleString(),
+                            })
+                          : t('configRecovery.corrupt')}
+                      </p>
+                      {configRecovery.quarantinedPath && (
+                        <p className="break-all">{t('configRecovery.quarantined', { path: configRecovery.quarantinedPath })}</p>
+                      )}
+                      <div className="flex gap-2">
+                        {configRecovery.backupAvailable && (
+                          <Button size="sm" onClick={handleRestoreConfig}>
+                            {t('configRecovery.restore')}
+                          </Button>
+                        )}
+                        <Button size="sm" variant="outline" onClick={handleDismissConfigRecovery}>
+                          {t('configRecovery.dismiss')}
+                        </Button>
+                      </div>
+                    </div>
+                  )}
+
                   {error && (
                     <div className="bg-[var(--error-muted)] border border-[var(--error)]/30 rounded-lg p-3 text-[var(--error)] text-xs">
                       {error}
@@ -357,6 +485,18 @@ export default function Home({ onOpenSettings }: HomeProps) {
                         <span className="font-mono text-[var(--warning)] text-[10px] break-all opacity-80">{qrData.connection_info.token}</span>
                       </div>
                     </div>
+                    {qrValidity && (
+                      <p className="text-xs text-[var(--foreground-muted)] text-center">{qrValidity}</p>
+                    )}
+                    <Button
+                      onClick={handleRegenerateToken}
+                      variant="outline"
+                      size="sm"
+                      className="w-full h-8 text-xs"
+                    >
+                      <RefreshCw className="w-3.5 h-3.5 mr-1.5" />
+                      {t('qrCode.regenerate')}
+                    </Button>
                   </CardContent>
                 </Card>
               )}
@@ -516,6 +656,11 @@ export default function Home({ onOpenSettings }: HomeProps) {
                             {device.deviceModel}
                           </p>
                         )}
+                        {device.legacyKey && (
+                          <p className="text-xs text-[var(--warning)] mt-1">
+                            {t('devices.legacyKey')}
+                          </p>
+                        )}
                         <p className="text-xs text-[var(--foreground-muted)] mt-1 font-mono truncate opacity-60">
                           ID: {device.deviceId.substring(0, 8)}...
                         </p>
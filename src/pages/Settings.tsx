@@ -1,10 +1,13 @@
 import { Button } from '@/components/ui/button';
 import { Card, CardContent, CardDescription, CardHeader, CardTitle } from '@/components/ui/card';
+import { Input } from '@/components/ui/input';
 import { Label } from '@/components/ui/label';
 import { Switch } from '@/components/ui/switch';
 import { useTheme } from '@/hooks/useTheme';
-import { useAppStore, type Theme } from '@/store';
-import { ArrowLeft, Check, Monitor, Moon, Sun } from 'lucide-react';
+import { useAppStore, type InputBackend, type InputOverrides, type NetworkSettings, type PolicyInfo, type Theme, type TokenRotation } from '@/store';
+import { invoke } from '@tauri-apps/api/core';
+import { ArrowLeft, Check, Lock, Monitor, Moon, Sun } from 'lucide-react';
+import { useEffect, useState } from 'react';
 import { useTranslation } from 'react-i18next';
 
 const languages = [
@@ -12,6 +15,9 @@ const languages = [
   { code: 'pt-BR', name: 'Português (Brasil)' },
 ];
 
+const rotationPolicies: TokenRotation['policy'][] = ['never', 'daily', 'after_pairings', 'every_pairing'];
+const inputBackends: (InputBackend | null)[] = [null, 'xdotool', 'uinput', 'ydotool', 'enigo'];
+
 const themes: { value: Theme; icon: React.ComponentType<{ className?: string }>; labelKey: string }[] = [
   { value: 'light', icon: Sun, labelKey: 'settings.sections.appearance.themes.light' },
   { value: 'dark', icon: Moon, labelKey: 'settings.sections.appearance.themes.dark' },
@@ -26,11 +32,107 @@ export default function Settings({ onBack }: SettingsProps) {
   const { t, i18n } = useTranslation('common');
   const { settings, updateSettings } = useAppStore();
   const { theme, setTheme } = useTheme();
+  const [policy, setPolicy] = useState<PolicyInfo | null>(null);
+  const [network, setNetwork] = useState<NetworkSettings | null>(null);
+  const [networkStatus, setNetworkStatus] = useState<{ saved: boolean; error?: string } | null>(null);
+  const [rotation, setRotation] = useState<TokenRotation | null>(null);
+  const [rotationError, setRotationError] = useState<string | null>(null);
+  const [requireEncryption, setRequireEncryption] = useState<boolean | null>(null);
+  const [encryptionError, setEncryptionError] = useState<string | null>(null);
+  const [legacyDevices, setLegacyDevices] = useState<{ device_name: string }[]>([]);
+  const [input, setInput] = useState<InputOverrides | null>(null);
+  const [inputStatus, setInputStatus] = useState<{ saved: boolean; error?: string } | null>(null);
+
+  useEffect(() => {
+    invoke<PolicyInfo>('get_policy')
+      .then(setPolicy)
+      .catch((err) => console.error('[ERROR] Failed to get policy:', err));
+    invoke<NetworkSettings>('get_network_settings')
+      .then(setNetwork)
+      .catch((err) => console.error('[ERROR] Failed to get network settings:', err));
+    invoke<TokenRotation>('get_token_rotation')
+      .then(setRotation)
+      .catch((err) => console.error('[ERROR] Failed to get token rotation:', err));
+    invoke<boolean>('get_require_encryption')
+      .then(setRequireEncryption)
+      .catch((err) => console.error('[ERROR] Failed to get encryption setting:', err));
+    invoke<InputOverrides>('get_input_overrides')
+      .then(setInput)
+      .catch((err) => console.error('[ERROR] Failed to get input overrides:', err));
+    invoke<{ device_name: string }[]>('get_legacy_key_devices')
+      .then(setLegacyDevices)
+      .catch((err) => console.error('[ERROR] Failed to get legacy key devices:', err));
+  }, []);
+
+  const isLocked = (setting: string) => policy?.lockedSettings.includes(setting) ?? false;
 
   const handleMinimizeToTrayChange = (checked: boolean) => {
     updateSettings({ minimizeToTray: checked });
   };
 
+  const updateNetwork = (changes: Partial<NetworkSettings>) => {
+    setNetwork((current) => (current ? { ...current, ...changes } : current));
+    setNetworkStatus(null);
+  };
+
+  const handleSaveNetwork = async () => {
+    if (!network) return;
+    try {
+      await invoke('set_network_settings', { settings: network });
+      setNetworkStatus({ saved: true });
+    } catch (err) {
+      setNetworkStatus({ saved: false, error: err as string });
+    }
+  };
+
+  const networkLocked = ['port', 'bind_address', 'port_fallback_attempts', 'tls_enabled'].some(isLocked);
+
+  const handleRotationChange = async (next: TokenRotation) => {
+    if (next.policy === 'after_pairings' && !(next.count >= 1)) {
+      setRotation(next);
+      return;
+    }
+    try {
+      await invoke('set_token_rotation', { rotation: next });
+      setRotation(next);
+      setRotationError(null);
+    } catch (err) {
+      setRotationError(err as string);
+    }
+  };
+
+  const updateInput = (changes: Partial<InputOverrides>) => {
+    setInput((current) => (current ? { ...current, ...changes } : current));
+    setInputStatus(null);
+  };
+
+  const handleSaveInput = async () => {
+    if (!input) return;
+    try {
+      await invoke('set_input_overrides', { overrides: input });
+      setInputStatus({ saved: true });
+    } catch (err) {
+      setInputStatus({ saved: false, error: err as string });
+    }
+  };
+
+  const inputLocked = ['input_backend', 'keyboard_layout'].some(isLocked);
+
+  const handleRequireEncryptionChange = async (required: boolean) => {
+    try {
+      await invoke('set_require_encryption', { required });
+      setRequireEncryption(required);
+      setEncryptionError(null);
+    } catch (err) {
+      setEncryptionError(err as string);
+    }
+  };
+
+  const selectRotationPolicy = (policy: TokenRotation['policy']) => {
+    if (policy === rotation?.policy) return;
+    handleRotationChange(policy === 'after_pairings' ? { policy, count: 5 } : { policy } as TokenRotation);
+  };
+
   return (
     <div className="h-screen bg-[var(--background)] text-[var(--foreground)] flex flex-col overflow-hidden transition-colors duration-200">
       {/* Header */}
@@ -136,6 +238,325 @@ export default function Settings({ onBack }: SettingsProps) {
             </CardContent>
           </Card>
 
+          {/* Settings managed by a system policy */}
+          {policy && (policy.lockedSettings.length > 0 || policy.error) && (
+            <Card>
+              <CardHeader className="pb-3">
+                <CardTitle className="text-base font-semibold flex items-center gap-2">
+                  <Lock className="w-4 h-4" />
+                  {t('settings.sections.policy.title')}
+                </CardTitle>
+                <CardDescription className="text-xs">
+                  {t('settings.sections.policy.description', { path: policy.path })}
+                </CardDescription>
+              </CardHeader>
+              <CardContent className="space-y-2">
+                {policy.error && (
+                  <div className="bg-[var(--error-muted)] border border-[var(--error)]/30 rounded-lg p-3 text-[var(--error)] text-xs">
+                    {t('settings.sections.policy.error', { error: policy.error })}
+                  </div>
+                )}
+                <div className="flex flex-wrap gap-2">
+                  {policy.lockedSettings.map((setting) => (
+                    <span
+                      key={setting}
+                      className="px-2 py-1 rounded-md text-xs font-mono bg-[var(--surface)]/30 border border-[var(--border-subtle)] text-[var(--foreground-secondary)]"
+                    >
+                      {setting}
+                    </span>
+                  ))}
+                </div>
+              </CardContent>
+            </Card>
+          )}
+
+          {/* Pairing QR code */}
+          {rotation && (
+            <Card>
+              <CardHeader className="pb-3">
+                <CardTitle className="text-base font-semibold">
+                  {t('settings.sections.pairing.title')}
+                </CardTitle>
+                <CardDescription className="text-xs">
+                  {t('settings.sections.pairing.description')}
+                </CardDescription>
+              </CardHeader>
+              <CardContent className="space-y-2">
+                {rotationPolicies.map((policy) => (
+                  <button
+                    key={policy}
+                    onClick={() => selectRotationPolicy(policy)}
+                    disabled={isLocked('token_rotation')}
+                    className={`
+                      w-full flex items-center justify-between p-3 rounded-lg border transition-all text-left disabled:cursor-not-allowed disabled:opacity-50
+                      ${rotation.policy === policy
+                        ? 'bg-[var(--primary-muted)] border-[var(--primary)]/50'
+                        : 'bg-[var(--surface)]/30 border-[var(--border-subtle)] hover:bg-[var(--surface)]/50 hover:border-[var(--border)]'
+                      }
+                    `}
+                  >
+                    <span className={`text-sm font-medium ${rotation.policy === policy ? 'text-[var(--primary)]' : 'text-[var(--foreground)]'}`}>
+                      {t(`settings.sections.pairing.policies.${policy}`)}
+                    </span>
+                    {rotation.policy === policy && (
+                      <Check className="w-4 h-4 text-[var(--primary)]" />
+                    )}
+                  </button>
+                ))}
+                {rotation.policy === 'after_pairings' && (
+                  <div className="flex items-center justify-between gap-4 p-3 rounded-lg bg-[var(--surface)]/30 border border-[var(--border-subtle)]">
+                    <Label htmlFor="rotation-count" className="text-sm text-[var(--foreground-secondary)]">
+                      {t('settings.sections.pairing.count')}
+                    </Label>
+                    <Input
+                      id="rotation-count"
+                      type="number"
+                      min={1}
+                      className="w-24"
+                      value={rotation.count}
+                      onChange={(e) => handleRotationChange({ policy: 'after_pairings', count: Number(e.target.value) })}
+                      disabled={isLocked('token_rotation')}
+                    />
+                  </div>
+                )}
+                {isLocked('token_rotation') && (
+                  <p className="text-xs text-[var(--foreground-muted)] flex items-center gap-1">
+                    <Lock className="w-3 h-3" />
+                    {t('settings.sections.policy.locked')}
+                  </p>
+                )}
+                {rotationError && (
+                  <div className="bg-[var(--error-muted)] border border-[var(--error)]/30 rounded-lg p-3 text-[var(--error)] text-xs">
+                    {rotationError}
+                  </div>
+                )}
+              </CardContent>
+            </Card>
+          )}
+
+          {/* Security */}
+          {requireEncryption !== null && (
+            <Card>
+              <CardHeader className="pb-3">
+                <CardTitle className="text-base font-semibold">
+                  {t('settings.sections.security.title')}
+                </CardTitle>
+                <CardDescription className="text-xs">
+                  {t('settings.sections.security.description')}
+                </CardDescription>
+              </CardHeader>
+              <CardContent className="space-y-3">
+                <div className="flex items-center justify-between gap-4 p-3 rounded-lg bg-[var(--surface)]/30 border border-[var(--border-subtle)]">
+                  <div className="space-y-1">
+                    <Label htmlFor="require-encryption" className="text-sm font-medium text-[var(--foreground)] cursor-pointer">
+                      {t('settings.sections.security.requireEncryption.label')}
+                    </Label>
+                    <p className="text-xs text-[var(--foreground-muted)] leading-relaxed">
+                      {t('settings.sections.security.requireEncryption.description')}
+                    </p>
+                    {isLocked('require_encryption') && (
+                      <p className="text-xs text-[var(--foreground-muted)] flex items-center gap-1">
+                        <Lock className="w-3 h-3" />
+                        {t('settings.sections.policy.locked')}
+                      </p>
+                    )}
+                  </div>
+                  <Switch
+                    id="require-encryption"
+                    checked={requireEncryption}
+                    onCheckedChange={handleRequireEncryptionChange}
+                    disabled={isLocked('require_encryption')}
+                  />
+                </div>
+                {legacyDevices.length > 0 && (
+                  <p className="text-xs text-[var(--warning)] leading-relaxed">
+                    {t('settings.sections.security.legacyDevices', {
+                      devices: legacyDevices.map((device) => device.device_name).join(', '),
+                    })}
+                  </p>
+                )}
+                {encryptionError && (
+                  <div className="bg-[var(--error-muted)] border border-[var(--error)]/30 rounded-lg p-3 text-[var(--error)] text-xs">
+                    {encryptionError}
+                  </div>
+                )}
+              </CardContent>
+            </Card>
+          )}
+
+          {/* Typing */}
+          {input && (
+            <Card>
+              <CardHeader className="pb-3">
+                <CardTitle className="text-base font-semibold">
+                  {t('settings.sections.input.title')}
+                </CardTitle>
+                <CardDescription className="text-xs">
+                  {t('settings.sections.input.description')}
+                </CardDescription>
+              </CardHeader>
+              <CardContent className="space-y-2">
+                {inputBackends.map((backend) => {
+                  const selected = (input.backend ?? null) === backend;
+                  return (
+                    <button
+                      key={backend ?? 'auto'}
+                      onClick={() => updateInput({ backend })}
+                      disabled={inputLocked}
+                      className={`
+                        w-full flex items-center justify-between p-3 rounded-lg border transition-all text-left disabled:cursor-not-allowed disabled:opacity-50
+                        ${selected
+                          ? 'bg-[var(--primary-muted)] border-[var(--primary)]/50'
+                          : 'bg-[var(--surface)]/30 border-[var(--border-subtle)] hover:bg-[var(--surface)]/50 hover:border-[var(--border)]'
+                        }
+                      `}
+                    >
+                      <span className={`text-sm font-medium ${selected ? 'text-[var(--primary)]' : 'text-[var(--foreground)]'}`}>
+                        {t(`settings.sections.input.backends.${backend ?? 'auto'}`)}
+                      </span>
+                      {selected && (
+                        <Check className="w-4 h-4 text-[var(--primary)]" />
+                      )}
+                    </button>
+                  );
+                })}
+                <div className="space-y-1 pt-1">
+                  <Label htmlFor="keyboard-layout" className="text-sm text-[var(--foreground-secondary)]">
+                    {t('settings.sections.input.layout')}
+                  </Label>
+                  <Input
+                    id="keyboard-layout"
+                    placeholder={t('settings.sections.input.layoutPlaceholder')}
+                    value={input.keyboardLayout ?? ''}
+                    onChange={(e) => updateInput({ keyboardLayout: e.target.value })}
+                    disabled={inputLocked}
+                  />
+                </div>
+                <p className="text-xs text-[var(--foreground-muted)] leading-relaxed">
+                  {t('settings.sections.input.hint')}
+                </p>
+                {inputLocked && (
+                  <p className="text-xs text-[var(--foreground-muted)] flex items-center gap-1">
+                    <Lock className="w-3 h-3" />
+                    {t('settings.sections.policy.locked')}
+                  </p>
+                )}
+                {inputStatus?.error && (
+                  <div className="bg-[var(--error-muted)] border border-[var(--error)]/30 rounded-lg p-3 text-[var(--error)] text-xs">
+                    {inputStatus.error}
+                  </div>
+                )}
+                <div className="flex items-center justify-end gap-3">
+                  {inputStatus?.saved && (
+                    <span className="text-xs text-[var(--foreground-muted)]">
+                      {t('settings.sections.input.saved')}
+                    </span>
+                  )}
+                  <Button size="sm" onClick={handleSaveInput} disabled={inputLocked}>
+                    {t('settings.sections.input.save')}
+                  </Button>
+                </div>
+              </CardContent>
+            </Card>
+          )}
+
+          {/* Network */}
+          {network && (
+            <Card>
+              <CardHeader className="pb-3">
+                <CardTitle className="text-base font-semibold">
+                  {t('settings.sections.network.title')}
+                </CardTitle>
+                <CardDescription className="text-xs">
+                  {t('settings.sections.network.description')}
+                </CardDescription>
+              </CardHeader>
+              <CardContent className="space-y-3">
+                <div className="grid grid-cols-3 gap-3">
+                  <div className="space-y-1">
+                    <Label htmlFor="network-bind-address" className="text-sm text-[var(--foreground-secondary)]">
+                      {t('settings.sections.network.bindAddress')}
+                    </Label>
+                    <Input
+                      id="network-bind-address"
+                      value={network.bindAddress}
+                      onChange={(e) => updateNetwork({ bindAddress: e.target.value.trim() })}
+                      disabled={networkLocked}
+                    />
+                  </div>
+                  <div className="space-y-1">
+                    <Label htmlFor="network-port" className="text-sm text-[var(--foreground-secondary)]">
+                      {t('settings.sections.network.port')}
+                    </Label>
+                    <Input
+                      id="network-port"
+                      type="number"
+                      min={1}
+                      max={65535}
+                      value={network.port}
+                      onChange={(e) => updateNetwork({ port: Number(e.target.value) })}
+                      disabled={networkLocked}
+                    />
+                  </div>
+                  <div className="space-y-1">
+                    <Label htmlFor="network-fallback" className="text-sm text-[var(--foreground-secondary)]">
+                      {t('settings.sections.network.fallbackAttempts')}
+                    </Label>
+                    <Input
+                      id="network-fallback"
+                      type="number"
+                      min={0}
+                      max={100}
+                      value={network.portFallbackAttempts}
+                      onChange={(e) => updateNetwork({ portFallbackAttempts: Number(e.target.value) })}
+                      disabled={networkLocked}
+                    />
+                  </div>
+                </div>
+                <div className="flex items-center justify-between gap-4 p-3 rounded-lg bg-[var(--surface)]/30 border border-[var(--border-subtle)]">
+                  <div className="space-y-1">
+                    <Label htmlFor="network-tls" className="text-sm font-medium text-[var(--foreground)] cursor-pointer">
+                      {t('settings.sections.network.tls.label')}
+                    </Label>
+                    <p className="text-xs text-[var(--foreground-muted)] leading-relaxed">
+                      {t('settings.sections.network.tls.description')}
+                    </p>
+                  </div>
+                  <Switch
+                    id="network-tls"
+                    checked={network.tlsEnabled}
+                    onCheckedChange={(checked) => updateNetwork({ tlsEnabled: checked })}
+                    disabled={networkLocked}
+                  />
+                </div>
+                <p className="text-xs text-[var(--foreground-muted)] leading-relaxed">
+                  {t('settings.sections.network.hint')}
+                </p>
+                {networkLocked && (
+                  <p className="text-xs text-[var(--foreground-muted)] flex items-center gap-1">
+                    <Lock className="w-3 h-3" />
+                    {t('settings.sections.policy.locked')}
+                  </p>
+                )}
+                {networkStatus?.error && (
+                  <div className="bg-[var(--error-muted)] border border-[var(--error)]/30 rounded-lg p-3 text-[var(--error)] text-xs">
+                    {networkStatus.error}
+                  </div>
+                )}
+                <div className="flex items-center justify-end gap-3">
+                  {networkStatus?.saved && (
+                    <span className="text-xs text-[var(--foreground-muted)]">
+                      {t('settings.sections.network.saved')}
+                    </span>
+                  )}
+                  <Button size="sm" onClick={handleSaveNetwork} disabled={networkLocked}>
+                    {t('settings.sections.network.save')}
+                  </Button>
+                </div>
+              </CardContent>
+            </Card>
+          )}
+
           {/* Application Behavior */}
           <Card>
             <CardHeader className="pb-3">
@@ -156,11 +577,18 @@ export default function Settings({ onBack }: SettingsProps) {
                   <p className="text-xs text-[var(--foreground-muted)] leading-relaxed">
                     {t('settings.sections.application.minimizeToTray.description')}
                   </p>
+                  {isLocked('minimize_to_tray') && (
+                    <p className="text-xs text-[var(--foreground-muted)] flex items-center gap-1">
+                      <Lock className="w-3 h-3" />
+                      {t('settings.sections.policy.locked')}
+                    </p>
+                  )}
                 </div>
                 <Switch
                   id="minimize-tray"
                   checked={settings.minimizeToTray}
                   onCheckedChange={handleMinimizeToTrayChange}
+                  disabled={isLocked('minimize_to_tray')}
                 />
               </div>
             </CardContent>
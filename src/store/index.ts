@@ -9,15 +9,38 @@ export interface BarcodeItem {
 	id: string
 }
 
+export interface Gs1Data {
+	elements: { ai: string; value: string }[]
+	gtin?: string
+	lot?: string
+	expiry?: string
+	serial?: string
+}
+
 export interface ConnectionInfo {
 	ip: string
 	port: number
 	token: string
+	scheme?: "ws" | "wss"
+	certFingerprint?: string
+}
+
+export type TokenRotation =
+	| { policy: "never" }
+	| { policy: "daily" }
+	| { policy: "after_pairings"; count: number }
+	| { policy: "every_pairing" }
+
+export interface TokenValidity {
+	rotation: TokenRotation
+	expiresAt?: number
+	remainingPairings?: number
 }
 
 export interface QRCodeData {
 	qr_base64: string
 	connection_info: ConnectionInfo
+	validity?: TokenValidity
 }
 
 export interface ServerState {
@@ -25,6 +48,54 @@ export interface ServerState {
 	connected_clients: number
 }
 
+export type DeviceCommand =
+	| { command: "trigger_scan" }
+	| { command: "show_message"; message: string; durationMs?: number }
+	| { command: "vibrate"; durationMs?: number }
+	| { command: "set_continuous_scan"; enabled: boolean }
+
+export interface CommandAckEvent {
+	commandId: string
+	deviceId: string
+	status: "delivered" | "executed" | "failed" | "unsupported" | "timed_out"
+	message?: string
+	timestamp: number
+}
+
+export interface LockoutEvent {
+	ip: string
+	failures: number
+	lockedUntil: number
+}
+
+export interface ConfigRecovery {
+	error: string
+	quarantinedPath?: string
+	backupAvailable: boolean
+	backupSavedAt?: string
+	keyUnavailable?: boolean
+}
+
+export interface PolicyInfo {
+	path?: string
+	error?: string
+	lockedSettings: string[]
+}
+
+export type InputBackend = "uinput" | "xdotool" | "ydotool" | "enigo"
+
+export interface InputOverrides {
+	backend?: InputBackend | null
+	keyboardLayout?: string | null
+}
+
+export interface NetworkSettings {
+	port: number
+	bindAddress: string
+	portFallbackAttempts: number
+	tlsEnabled: boolean
+}
+
 export interface AppSettings {
 	minimizeToTray: boolean
 	theme: Theme